@@ -0,0 +1,117 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use atm0s_sdn_visualization::{ConnectionMetric, ConnectionStatus, NodeConnectionData, SdnMonitorController};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const NODES: u32 = 1000;
+const CONNS_PER_NODE: u32 = 20;
+const READER_THREADS: usize = 4;
+/// Connections per agent message, as sent by `build_conns_stats_msg`
+const REPORT_BATCH: u32 = 10;
+
+fn conns(node_id: u32, latency: u16) -> Vec<NodeConnectionData> {
+    conns_range(node_id, 0..CONNS_PER_NODE, latency)
+}
+
+fn conns_range(node_id: u32, range: std::ops::Range<u32>, latency: u16) -> Vec<NodeConnectionData> {
+    range
+        .map(|index| {
+            let dest = (node_id + index + 1) % NODES;
+            NodeConnectionData {
+                id: (index as u64) << 16 | (dest as u64),
+                node_id: dest,
+                protocol: 1,
+                addr: format!("/ip4/127.0.0.1/udp/{}", 50000 + dest),
+                metric: ConnectionMetric {
+                    latency,
+                    bandwidth: 1000,
+                    loss_percent: 0,
+                },
+                status: ConnectionStatus::CONNECTED,
+                last_updated_at: latency as u64,
+                direction: 0,
+            }
+        })
+        .collect()
+}
+
+fn build_controller() -> SdnMonitorController {
+    let mut controller = SdnMonitorController::new();
+    for node_id in 0..NODES {
+        controller.upsert_node(node_id, format!("/ip4/127.0.0.1/udp/{}", 50000 + node_id), 1);
+        controller.update_node_conns(node_id, conns(node_id, 1));
+    }
+    controller
+}
+
+/// Agent updates applied by the network plane thread, with and without dashboard readers hammering `get_nodes`
+fn bench_update_with_readers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update_node_conns");
+    for readers in [0, READER_THREADS] {
+        let mut controller = build_controller();
+        let stop = Arc::new(AtomicBool::new(false));
+        let handles: Vec<_> = (0..readers)
+            .map(|_| {
+                let controller = controller.clone();
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        criterion::black_box(controller.get_nodes());
+                    }
+                })
+            })
+            .collect();
+
+        let mut tick = 0;
+        group.bench_function(BenchmarkId::new("readers", readers), |b| {
+            b.iter(|| {
+                tick += 1;
+                let node_id = tick % NODES;
+                controller.update_node_conns(node_id, conns(node_id, (tick % 100) as u16));
+            })
+        });
+
+        stop.store(true, Ordering::Relaxed);
+        for handle in handles {
+            handle.join().expect("reader should stop");
+        }
+    }
+    group.finish();
+}
+
+/// One full report of a node with thousands of connections, split in `REPORT_BATCH` sized messages
+fn bench_large_node(c: &mut Criterion) {
+    let mut group = c.benchmark_group("large_node_report");
+    group.sample_size(10);
+    for conns_count in [1000, 5000] {
+        let mut controller = SdnMonitorController::new();
+        controller.upsert_node(0, String::from("/ip4/127.0.0.1/udp/50000"), 1);
+        controller.update_node_conns(0, conns_range(0, 0..conns_count, 1));
+
+        let mut tick = 0;
+        group.bench_function(BenchmarkId::new("conns", conns_count), |b| {
+            b.iter(|| {
+                tick += 1;
+                for start in (0..conns_count).step_by(REPORT_BATCH as usize) {
+                    controller.update_node_conns(0, conns_range(0, start..(start + REPORT_BATCH).min(conns_count), (tick % 100) as u16));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_reads(c: &mut Criterion) {
+    let controller = build_controller();
+    c.bench_function("get_nodes", |b| b.iter(|| criterion::black_box(controller.get_nodes())));
+    c.bench_function("get_node", |b| b.iter(|| criterion::black_box(controller.get_node(NODES / 2))));
+}
+
+criterion_group!(benches, bench_update_with_readers, bench_large_node, bench_reads);
+criterion_main!(benches);
@@ -0,0 +1,148 @@
+use atm0s_sdn_visualization::{
+    testing::{SyntheticTopology, SyntheticTopologyConf, TopologyModel},
+    ConnectionMetric, ConnectionStatus, ExportFormat, LinkMetric, NodeConnectionData, NodeConnectionStorage, NodeListQuery, SdnMonitorController, VisualizationMasterLogic,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use parking_lot::RwLock;
+
+const NODES: u32 = 5000;
+
+fn models() -> Vec<(&'static str, TopologyModel)> {
+    vec![
+        ("random", TopologyModel::Random { avg_degree: 8 }),
+        ("scale_free", TopologyModel::ScaleFree { links_per_node: 4 }),
+        (
+            "regional",
+            TopologyModel::RegionalClusters {
+                regions: 10,
+                intra_degree: 8,
+                inter_links: 50,
+            },
+        ),
+    ]
+}
+
+/// Master store after a few rounds of the topology, the generator keeps going from there
+fn warmed_up(model: TopologyModel) -> (SdnMonitorController, VisualizationMasterLogic, SyntheticTopology) {
+    let controller = SdnMonitorController::new();
+    let mut logic = VisualizationMasterLogic::new(controller.clone());
+    let mut topology = SyntheticTopology::new(SyntheticTopologyConf::new(NODES, model));
+    for _ in 0..3 {
+        let msgs = topology.next_round();
+        logic.process_agent_msgs(msgs, topology.now_ms());
+    }
+    (controller, logic, topology)
+}
+
+/// One reporting round of every node, applied message by message and as a single batch like the ingest worker
+fn bench_ingest_round(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ingest_round");
+    group.sample_size(10);
+    for (name, model) in models() {
+        let (_controller, mut logic, mut topology) = warmed_up(model);
+        let msgs_per_round = topology.next_round().len();
+        group.throughput(Throughput::Elements(msgs_per_round as u64));
+
+        group.bench_function(BenchmarkId::new("per_msg", name), |b| {
+            b.iter_batched(
+                || (topology.next_round(), topology.now_ms()),
+                |(msgs, now_ms)| {
+                    for msg in msgs {
+                        logic.process_agent_msg(msg, now_ms);
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_function(BenchmarkId::new("batch", name), |b| {
+            b.iter_batched(
+                || (topology.next_round(), topology.now_ms()),
+                |(msgs, now_ms)| logic.process_agent_msgs(msgs, now_ms),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Ten connections of `node_id` to the next nodes of the ring, `ts` must grow for the update to be applied
+fn ring_conns(node_id: u32, nodes: u32, ts: u64) -> Vec<NodeConnectionData> {
+    (1..=10)
+        .map(|step| {
+            let dest = (node_id + step) % nodes;
+            NodeConnectionData {
+                id: dest as u64,
+                node_id: dest,
+                protocol: 1,
+                addr: format!("/ip4/127.0.0.1/udp/{}", 50000 + dest),
+                metric: ConnectionMetric {
+                    latency: (ts % 100) as u16,
+                    bandwidth: 1000,
+                    loss_percent: 0,
+                },
+                status: ConnectionStatus::CONNECTED,
+                last_updated_at: ts,
+                direction: 0,
+            }
+        })
+        .collect()
+}
+
+/// One connection report written in place under a lock, as the controller did before snapshots, against the same
+/// report published as a new snapshot. Both should stay flat as the node count grows.
+fn bench_write_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write_throughput");
+    group.throughput(Throughput::Elements(1));
+    for nodes in [1000, NODES] {
+        let storage = RwLock::new(NodeConnectionStorage::new());
+        let mut controller = SdnMonitorController::new();
+        for node_id in 0..nodes {
+            let addr = format!("/ip4/127.0.0.1/udp/{}", 50000 + node_id);
+            storage.write().upsert_node(node_id, addr.clone(), 1);
+            storage.write().update_node_connection(node_id, ring_conns(node_id, nodes, 1));
+            controller.upsert_node(node_id, addr, 1);
+            controller.update_node_conns(node_id, ring_conns(node_id, nodes, 1));
+        }
+
+        let mut tick = 1;
+        group.bench_function(BenchmarkId::new("in_place", nodes), |b| {
+            b.iter(|| {
+                tick += 1;
+                let node_id = (tick % nodes as u64) as u32;
+                storage.write().update_node_connection(node_id, ring_conns(node_id, nodes, tick))
+            })
+        });
+        let mut tick = 1;
+        group.bench_function(BenchmarkId::new("snapshot", nodes), |b| {
+            b.iter(|| {
+                tick += 1;
+                let node_id = (tick % nodes as u64) as u32;
+                controller.update_node_conns(node_id, ring_conns(node_id, nodes, tick))
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Reads behind the HTTP API on a store of `NODES` scale-free nodes
+fn bench_api_reads(c: &mut Criterion) {
+    let (controller, _logic, _topology) = warmed_up(TopologyModel::ScaleFree { links_per_node: 4 });
+    let mut group = c.benchmark_group("api_reads");
+    group.sample_size(20);
+    group.bench_function("get_nodes", |b| b.iter(|| criterion::black_box(controller.get_nodes())));
+    group.bench_function("query_nodes_page", |b| {
+        let query = NodeListQuery {
+            limit: Some(100),
+            ..Default::default()
+        };
+        b.iter(|| criterion::black_box(controller.query_nodes(&query, 0)))
+    });
+    group.bench_function("get_node_detail", |b| b.iter(|| criterion::black_box(controller.get_node_detail(NODES / 2))));
+    group.bench_function("worst_links", |b| b.iter(|| criterion::black_box(controller.worst_links(20, LinkMetric::Loss))));
+    group.bench_function("shortest_path", |b| b.iter(|| criterion::black_box(controller.shortest_path(1, NODES))));
+    group.bench_function("export_jgf", |b| b.iter(|| criterion::black_box(controller.export(ExportFormat::Jgf, None))));
+    group.finish();
+}
+
+criterion_group!(benches, bench_ingest_round, bench_write_throughput, bench_api_reads);
+criterion_main!(benches);
@@ -0,0 +1,191 @@
+//! Load report of the master fed by synthetic topologies: ingest throughput, store memory per node and HTTP API
+//! latency, printed as markdown. Run with `cargo bench --features testing --bench load_report`, the node count
+//! defaults to 5000 and is read from `LOAD_REPORT_NODES`. A generated report is kept in `benches/load_report.md`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use atm0s_sdn_visualization::{
+    build_visualization_route,
+    testing::{SyntheticTopology, SyntheticTopologyConf, TopologyModel},
+    ConnectionSample, SampleFilter, SdnMonitorController, VisualizationMasterLogic, MAX_CONNECTION_SAMPLES,
+};
+use poem::{http::Uri, Endpoint, Request};
+
+/// Counts live heap bytes, the store memory is the difference around its construction
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const WARMUP_ROUNDS: usize = 3;
+const MEASURED_ROUNDS: usize = 10;
+const API_CALLS: usize = 20;
+
+fn models() -> Vec<(&'static str, TopologyModel)> {
+    vec![
+        ("random", TopologyModel::Random { avg_degree: 8 }),
+        ("scale_free", TopologyModel::ScaleFree { links_per_node: 4 }),
+        (
+            "regional",
+            TopologyModel::RegionalClusters {
+                regions: 10,
+                intra_degree: 8,
+                inter_links: 50,
+            },
+        ),
+    ]
+}
+
+/// Too few samples are taken for a meaningful p99, the slowest one is reported as max instead
+fn percentile(samples: &mut [Duration], percent: usize) -> Duration {
+    samples.sort();
+    samples[(samples.len() - 1) * percent / 100]
+}
+
+struct IngestResult {
+    msgs_per_round: usize,
+    msgs_per_sec: f64,
+    round_p50: Duration,
+    round_max: Duration,
+}
+
+/// Warm the store up, then time `MEASURED_ROUNDS` rounds applied per message or in one batch
+fn run_ingest(logic: &mut VisualizationMasterLogic, topology: &mut SyntheticTopology, batch: bool) -> IngestResult {
+    for _ in 0..WARMUP_ROUNDS {
+        let msgs = topology.next_round();
+        logic.process_agent_msgs(msgs, topology.now_ms());
+    }
+    let mut msgs_count = 0;
+    let mut rounds = vec![];
+    for _ in 0..MEASURED_ROUNDS {
+        let msgs = topology.next_round();
+        let now_ms = topology.now_ms();
+        msgs_count += msgs.len();
+        let started = Instant::now();
+        if batch {
+            logic.process_agent_msgs(msgs, now_ms);
+        } else {
+            for msg in msgs {
+                logic.process_agent_msg(msg, now_ms);
+            }
+        }
+        rounds.push(started.elapsed());
+    }
+    let total: Duration = rounds.iter().sum();
+    IngestResult {
+        msgs_per_round: msgs_count / MEASURED_ROUNDS,
+        msgs_per_sec: msgs_count as f64 / total.as_secs_f64(),
+        round_p50: percentile(&mut rounds, 50),
+        round_max: percentile(&mut rounds, 100),
+    }
+}
+
+fn main() {
+    let nodes: u32 = std::env::var("LOAD_REPORT_NODES").ok().and_then(|nodes| nodes.parse().ok()).unwrap_or(5000);
+    println!("# Master load report, {} nodes\n", nodes);
+    println!(
+        "{} warm-up and {} measured reporting rounds per model, 1% node churn, 0.5% link flaps.\n",
+        WARMUP_ROUNDS, MEASURED_ROUNDS
+    );
+    println!("| model | links | msgs/round | per msg, msgs/s | batch, msgs/s | batch round p50 | batch round max | memory/node | samples/node |");
+    println!("|---|---|---|---|---|---|---|---|---|");
+    for (name, model) in models() {
+        let conf = SyntheticTopologyConf::new(nodes, model);
+
+        let mut topology = SyntheticTopology::new(conf.clone());
+        let mut logic = VisualizationMasterLogic::new(SdnMonitorController::new());
+        let per_msg = run_ingest(&mut logic, &mut topology, false);
+        drop(logic);
+
+        let mut topology = SyntheticTopology::new(conf);
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        let controller = SdnMonitorController::new();
+        let mut logic = VisualizationMasterLogic::new(controller.clone());
+        let batch = run_ingest(&mut logic, &mut topology, true);
+        let store_bytes = ALLOCATED.load(Ordering::Relaxed).saturating_sub(before);
+        let samples = controller.connection_samples(SampleFilter::default()).count();
+
+        println!(
+            "| {} | {} | {} | {:.0} | {:.0} | {:?} | {:?} | {} B | {} |",
+            name,
+            topology.link_count(),
+            batch.msgs_per_round,
+            per_msg.msgs_per_sec,
+            batch.msgs_per_sec,
+            batch.round_p50,
+            batch.round_max,
+            store_bytes / nodes.max(1) as usize,
+            samples / nodes.max(1) as usize
+        );
+    }
+    // filling the rings would take thousands of rounds, their cap is reported instead
+    println!(
+        "\nMemory per node includes the connection samples recorded so far. The sample ring of a node holds up to {} samples of {} B, so once full it adds up to {} KiB per node.",
+        MAX_CONNECTION_SAMPLES,
+        std::mem::size_of::<ConnectionSample>(),
+        MAX_CONNECTION_SAMPLES * std::mem::size_of::<ConnectionSample>() / 1024
+    );
+
+    let (route, controller) = build_visualization_route();
+    let mut logic = VisualizationMasterLogic::new(controller);
+    let mut topology = SyntheticTopology::new(SyntheticTopologyConf::new(nodes, TopologyModel::ScaleFree { links_per_node: 4 }));
+    for _ in 0..WARMUP_ROUNDS {
+        let msgs = topology.next_round();
+        logic.process_agent_msgs(msgs, topology.now_ms());
+    }
+    let paths = [
+        String::from("/api/nodes/count"),
+        format!("/api/nodes/{}", nodes / 2),
+        String::from("/api/nodes?limit=100"),
+        String::from("/api/nodes?conns=false"),
+        String::from("/api/nodes"),
+        String::from("/api/regions"),
+        String::from("/api/export?format=dot&node=1&depth=2"),
+        String::from("/api/export?format=jgf"),
+    ];
+
+    println!("\n## HTTP API on the scale_free store\n");
+    println!("{} calls per route, response body read to the end.\n", API_CALLS);
+    println!("| route | status | body | p50 | max |");
+    println!("|---|---|---|---|---|");
+    let runtime = tokio::runtime::Builder::new_current_thread().build().expect("should build runtime");
+    runtime.block_on(async {
+        for path in paths.iter() {
+            let mut samples = vec![];
+            let mut last = (0, 0);
+            for _ in 0..API_CALLS {
+                let started = Instant::now();
+                let response = route.get_response(Request::builder().uri(path.parse::<Uri>().expect("should parse uri")).finish()).await;
+                let status = response.status().as_u16();
+                let body = response.into_body().into_bytes().await.expect("should read body");
+                samples.push(started.elapsed());
+                last = (status, body.len());
+            }
+            println!("| {} | {} | {} B | {:?} | {:?} |", path, last.0, last.1, percentile(&mut samples, 50), percentile(&mut samples, 100));
+        }
+    });
+}
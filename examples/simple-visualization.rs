@@ -11,12 +11,15 @@ use atm0s_sdn::{LayersSpreadRouterSyncBehavior, LayersSpreadRouterSyncBehaviorEv
 use atm0s_sdn::{ManualBehavior, ManualBehaviorConf, ManualBehaviorEvent, ManualHandlerEvent};
 use atm0s_sdn::{NodeAddrBuilder, UdpTransport};
 use atm0s_sdn_visualization::build_visualization_route;
+use atm0s_sdn_visualization::MasterReplicationConf;
 use atm0s_sdn_visualization::SdnMonitorController;
 use atm0s_sdn_visualization::VisualizationAgentBehaviour;
 use atm0s_sdn_visualization::VisualizationAgentBehaviourConf;
 use atm0s_sdn_visualization::VisualizationAgentBehaviourEvent;
 use atm0s_sdn_visualization::VisualizationAgentHandlerEvent;
+use atm0s_sdn_visualization::VisualizationAgentSdkEvent;
 use atm0s_sdn_visualization::VisualizationMasterBehaviour;
+use atm0s_sdn_visualization::VisualizationMasterBehaviourConf;
 use atm0s_sdn_visualization::VisualizationMasterBehaviourEvent;
 use atm0s_sdn_visualization::VisualizationMasterHandlerEvent;
 use clap::ArgAction;
@@ -29,6 +32,7 @@ use poem::EndpointExt;
 use poem::Route;
 use poem::Server;
 use reedline_repl_rs::{clap::Command, Error, Repl};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 #[derive(convert_enum::From, convert_enum::TryInto)]
@@ -52,6 +56,7 @@ enum NodeHandleEvent {
 #[derive(convert_enum::From, convert_enum::TryInto)]
 enum NodeSdkEvent {
     KeyValue(KeyValueSdkEvent),
+    VisualizationAgent(VisualizationAgentSdkEvent),
 }
 
 #[derive(Parser, Debug)]
@@ -109,14 +114,31 @@ async fn generate_sdn_plane(args: Args, controller: Option<SdnMonitorController>
     let key_value_sdk = KeyValueSdk::new();
     let key_value = KeyValueBehavior::new(args.node_id, 1000, Some(Box::new(key_value_sdk.clone())));
 
-    let visualization_agent = VisualizationAgentBehaviour::new(VisualizationAgentBehaviourConf {
-        node_id: args.node_id,
-        node_addr: node_addr.clone(),
+    let (visualization_agent, visualization_agent_sdk) = VisualizationAgentBehaviour::new(VisualizationAgentBehaviourConf {
+        labels: BTreeMap::from([(
+            String::from("role"),
+            String::from(if args.is_master {
+                "master"
+            } else {
+                "agent"
+            }),
+        )]),
+        report_host_stats: true,
+        ..VisualizationAgentBehaviourConf::new(args.node_id, node_addr.clone())
     });
 
+    visualization_agent_sdk.register_gauge("seeds");
+    visualization_agent_sdk.set_gauge("seeds", args.seeds.len() as i64);
+
     let plan_cfg = match controller {
         Some(controller) => {
-            let (visualization_master, _) = VisualizationMasterBehaviour::new(controller.clone());
+            let (visualization_master, _) = VisualizationMasterBehaviour::new_with_conf(
+                controller.clone(),
+                VisualizationMasterBehaviourConf {
+                    replication: Some(MasterReplicationConf::default()),
+                    ..Default::default()
+                },
+            );
             NetworkPlaneConfig {
                 router: Arc::new(router.clone()),
                 node_id: args.node_id,
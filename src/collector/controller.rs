@@ -1,18 +1,44 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use arc_swap::ArcSwap;
 use atm0s_sdn_identity::NodeId;
-use parking_lot::RwLock;
+use parking_lot::Mutex;
 
-use super::storage::{NodeConnectionData, NodeConnectionStorage, NodeData};
+use crate::identity::{AppMetric, HostStats};
 
+use super::{
+    events::{AlertThresholds, TimedTopologyEvent, TopologyEvent, TopologyEventFeed, TopologySubscription},
+    export::{ExportFormat, ExportScope},
+    graph::{Link, LinkMetric},
+    query::{NodeListPage, NodeListQuery},
+    region::{RegionDetail, RegionSummary},
+    samples::{ConnectionSamples, SampleFilter},
+    sink::{MetricsSink, MetricsSinks, SinkTrigger},
+    storage::{NodeAppMetrics, NodeConnectionData, NodeConnectionStorage, NodeData, NodeDetail},
+};
+
+/// Readers load the latest published storage snapshot without locking, writers are serialized
+/// and publish a new copy-on-write snapshot after each change.
 pub struct SdnMonitorController {
-    node_storage: Arc<RwLock<NodeConnectionStorage>>,
+    node_storage: Arc<ArcSwap<NodeConnectionStorage>>,
+    write_lock: Arc<Mutex<()>>,
+    feed: Arc<TopologyEventFeed>,
+    alerts_muted: Arc<AtomicBool>,
+    region_requests: Arc<Mutex<BTreeSet<String>>>,
+    sinks: Arc<Mutex<MetricsSinks>>,
 }
 
 impl Clone for SdnMonitorController {
     fn clone(&self) -> Self {
         Self {
             node_storage: self.node_storage.clone(),
+            write_lock: self.write_lock.clone(),
+            feed: self.feed.clone(),
+            alerts_muted: self.alerts_muted.clone(),
+            region_requests: self.region_requests.clone(),
+            sinks: self.sinks.clone(),
         }
     }
 }
@@ -20,27 +46,214 @@ impl Clone for SdnMonitorController {
 impl SdnMonitorController {
     pub fn new() -> SdnMonitorController {
         Self {
-            node_storage: Arc::new(RwLock::new(NodeConnectionStorage::new())),
+            node_storage: Arc::new(ArcSwap::from_pointee(NodeConnectionStorage::new())),
+            write_lock: Arc::new(Mutex::new(())),
+            feed: Arc::new(TopologyEventFeed::new()),
+            alerts_muted: Arc::new(AtomicBool::new(false)),
+            region_requests: Arc::new(Mutex::new(BTreeSet::new())),
+            sinks: Arc::new(Mutex::new(MetricsSinks::default())),
+        }
+    }
+
+    fn write<R, F: FnOnce(&mut NodeConnectionStorage) -> R>(&self, f: F) -> R {
+        let _guard = self.write_lock.lock();
+        let mut next = NodeConnectionStorage::clone(&self.node_storage.load());
+        let ret = f(&mut next);
+        self.node_storage.store(Arc::new(next));
+        ret
+    }
+
+    fn publish(&self, ts: u64, mut events: Vec<TopologyEvent>) {
+        if self.alerts_muted.load(Ordering::Relaxed) {
+            events.retain(|event| !matches!(event, TopologyEvent::AlertFired(_)));
         }
+        self.feed.publish(ts, events);
+    }
+
+    fn read(&self) -> Arc<NodeConnectionStorage> {
+        self.node_storage.load_full()
     }
 
     pub fn upsert_node(&mut self, node_id: NodeId, addr: String, now_ms: u64) {
-        self.node_storage.write().upsert_node(node_id, addr, now_ms);
+        let events = self.write(|storage| storage.upsert_node(node_id, addr, now_ms));
+        self.publish(now_ms, events);
     }
 
+    /// Events are recorded at the latest update time of the given connections
     pub fn update_node_conns(&mut self, node_id: NodeId, conns: Vec<NodeConnectionData>) {
-        self.node_storage.write().update_node_connection(node_id, conns);
+        let ts = conns.iter().map(|conn| conn.last_updated_at).max().unwrap_or_default();
+        let events = self.write(|storage| storage.update_node_connection(node_id, conns));
+        self.publish(ts, events);
+    }
+
+    pub fn check_offline_nodes(&mut self, now_ms: u64) {
+        let events = self.write(|storage| storage.check_offline_nodes(now_ms));
+        self.publish(now_ms, events);
+    }
+
+    /// Apply several writes with a single snapshot publish, collected events are published afterwards and recorded
+    /// at `now_ms`
+    pub(crate) fn update_batch<R, F: FnOnce(&mut NodeConnectionStorage, &mut Vec<TopologyEvent>) -> R>(&mut self, now_ms: u64, f: F) -> R {
+        let mut events = vec![];
+        let ret = self.write(|storage| f(storage, &mut events));
+        self.publish(now_ms, events);
+        ret
+    }
+
+    pub fn set_alert_thresholds(&mut self, thresholds: AlertThresholds) {
+        self.write(|storage| storage.set_alert_thresholds(thresholds));
+    }
+
+    /// Stop publishing `AlertFired` events, used by standby masters so only the leader alerts
+    pub fn set_alerts_muted(&self, muted: bool) {
+        self.alerts_muted.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn subscribe(&self) -> TopologySubscription {
+        self.feed.subscribe(None)
+    }
+
+    pub fn subscribe_filtered<F: Fn(&TopologyEvent) -> bool + Send + Sync + 'static>(&self, filter: F) -> TopologySubscription {
+        self.feed.subscribe(Some(Box::new(filter)))
+    }
+
+    /// Published events between `from_ms` and `to_ms`, up to `MAX_EVENT_HISTORY` are kept
+    pub fn event_history(&self, from_ms: u64, to_ms: u64) -> Vec<TimedTopologyEvent> {
+        self.feed.history(from_ms, to_ms)
+    }
+
+    pub fn count_subscribers(&self) -> usize {
+        self.feed.count_subscribers()
+    }
+
+    pub fn update_node_labels(&mut self, node_id: NodeId, labels: BTreeMap<String, String>) {
+        self.write(|storage| storage.update_node_labels(node_id, labels));
+    }
+
+    pub fn add_host_stats(&mut self, node_id: NodeId, ts: u64, stats: HostStats) {
+        self.write(|storage| storage.add_host_stats(node_id, ts, stats));
+    }
+
+    pub fn update_node_app_metrics(&mut self, node_id: NodeId, ts: u64, metrics: Vec<AppMetric>) {
+        self.write(|storage| storage.update_node_app_metrics(node_id, ts, metrics));
     }
 
     pub fn get_nodes(&self) -> Vec<NodeData> {
-        self.node_storage.read().list_node()
+        self.read().list_node()
+    }
+
+    /// All nodes from the latest snapshot, shared instead of deep cloned
+    pub fn get_nodes_shared(&self) -> Vec<Arc<NodeData>> {
+        self.read().list_node_shared()
+    }
+
+    pub fn get_nodes_where<F: Fn(&NodeData) -> bool>(&self, predicate: F) -> Vec<NodeData> {
+        self.read().list_node_where(predicate)
+    }
+
+    pub fn query_nodes(&self, query: &NodeListQuery, now_ms: u64) -> NodeListPage {
+        self.read().query_nodes(query, now_ms)
+    }
+
+    pub fn neighbors(&self, node_id: NodeId) -> Vec<NodeId> {
+        self.read().neighbors(node_id)
+    }
+
+    pub fn edge(&self, a: NodeId, b: NodeId) -> Vec<NodeConnectionData> {
+        self.read().edge(a, b)
+    }
+
+    pub fn worst_links(&self, n: usize, by_metric: LinkMetric) -> Vec<Link> {
+        self.read().worst_links(n, by_metric)
+    }
+
+    pub fn shortest_path(&self, a: NodeId, b: NodeId) -> Option<Vec<NodeId>> {
+        self.read().shortest_path(a, b)
+    }
+
+    /// Render the topology as DOT, GraphML or JGF, optionally only the subgraph around a node
+    pub fn export(&self, format: ExportFormat, scope: Option<ExportScope>) -> String {
+        self.read().export(format, scope)
+    }
+
+    /// Iterate recorded connection samples lazily over the current snapshot, later updates are not seen
+    pub fn connection_samples(&self, filter: SampleFilter) -> ConnectionSamples {
+        self.read().connection_samples(filter)
+    }
+
+    /// Push node and connection series to `sink` on each ingested batch or at an interval
+    pub fn add_metrics_sink<S: MetricsSink + 'static>(&self, sink: S, trigger: SinkTrigger) {
+        self.sinks.lock().add(Box::new(sink), trigger);
+    }
+
+    /// Batches of points the sinks dropped because their thread was behind, since start
+    pub fn metrics_sink_drops(&self) -> u64 {
+        self.sinks.lock().dropped()
+    }
+
+    /// Feed the series of the given nodes to the ingest sinks
+    pub(crate) fn emit_ingest_metrics(&self, node_ids: &[NodeId], now_ms: u64) {
+        let mut sinks = self.sinks.lock();
+        if !sinks.has_ingest_sinks() {
+            return;
+        }
+        let storage = self.read();
+        let nodes: Vec<Arc<NodeData>> = node_ids.iter().filter_map(|node_id| storage.get_node_shared(*node_id)).collect();
+        sinks.on_ingest(&nodes, now_ms);
+    }
+
+    pub(crate) fn flush_metrics_sinks(&self, now_ms: u64) {
+        self.sinks.lock().on_tick(|| self.read().list_node_shared(), now_ms);
     }
 
     pub fn get_node(&self, id: NodeId) -> Option<NodeData> {
-        self.node_storage.read().get_node(id)
+        self.read().get_node(id)
+    }
+
+    pub fn get_node_shared(&self, id: NodeId) -> Option<Arc<NodeData>> {
+        self.read().get_node_shared(id)
+    }
+
+    pub fn get_node_detail(&self, id: NodeId) -> Option<NodeDetail> {
+        self.read().get_node_detail(id)
+    }
+
+    pub fn get_node_app_metrics(&self, id: NodeId) -> Option<NodeAppMetrics> {
+        self.read().get_node_app_metrics(id)
     }
 
     pub fn count_nodes(&self) -> usize {
-        self.node_storage.read().count_node()
+        self.read().count_node()
+    }
+
+    pub fn merge_region_summary(&mut self, summary: RegionSummary) {
+        self.write(|storage| storage.merge_region_summary(summary));
+    }
+
+    pub fn merge_region_detail(&mut self, detail: RegionDetail) {
+        self.write(|storage| storage.merge_region_detail(detail));
+    }
+
+    pub fn get_regions(&self) -> Vec<RegionSummary> {
+        self.read().list_regions()
+    }
+
+    pub fn get_region(&self, region: &str) -> Option<RegionSummary> {
+        self.read().get_region(region)
+    }
+
+    /// Last region detail fetched from its aggregator, see `request_region_detail`
+    pub fn get_region_detail(&self, region: &str) -> Option<RegionDetail> {
+        self.read().get_region_detail(region)
+    }
+
+    /// Ask the master behaviour to fetch the region detail from its aggregator on the next tick, unless its copy is
+    /// younger than `REGION_DETAIL_TTL_MS`
+    pub fn request_region_detail(&self, region: &str) {
+        self.region_requests.lock().insert(region.to_string());
+    }
+
+    pub(crate) fn take_region_requests(&self) -> Vec<String> {
+        std::mem::take(&mut *self.region_requests.lock()).into_iter().collect()
     }
 }
@@ -0,0 +1,392 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Weak,
+    },
+};
+
+use async_notify::Notify;
+use atm0s_sdn_identity::NodeId;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::identity::{ConnectionMetric, ConnectionStatus};
+
+use super::storage::NodeConnectionData;
+
+pub const MAX_SUBSCRIPTION_QUEUE: usize = 1024;
+/// Published events kept for later lookup, metric updates are not kept
+pub const MAX_EVENT_HISTORY: usize = 1024;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub struct AlertThresholds {
+    pub latency_ms: u16,
+    pub loss_percent: u32,
+}
+
+impl Default for AlertThresholds {
+    fn default() -> Self {
+        Self { latency_ms: 500, loss_percent: 10 }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum AlertKind {
+    HighLatency(u16),
+    HighLoss(u32),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct TopologyAlert {
+    pub node_id: NodeId,
+    pub conn_id: u64,
+    pub dest: NodeId,
+    pub kind: AlertKind,
+}
+
+/// `LinkRemoved` is sent when the agent garbage collected the connection
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum TopologyEvent {
+    NodeJoined(NodeId),
+    NodeLeft(NodeId),
+    LinkUp { node_id: NodeId, conn_id: u64, dest: NodeId },
+    LinkDown { node_id: NodeId, conn_id: u64, dest: NodeId },
+    LinkRemoved { node_id: NodeId, conn_id: u64, dest: NodeId },
+    MetricUpdated { node_id: NodeId, conn_id: u64, dest: NodeId, metric: ConnectionMetric },
+    AlertFired(TopologyAlert),
+}
+
+impl TopologyEvent {
+    /// The node which reported the change
+    pub fn node_id(&self) -> NodeId {
+        match self {
+            TopologyEvent::NodeJoined(node_id) | TopologyEvent::NodeLeft(node_id) => *node_id,
+            TopologyEvent::LinkUp { node_id, .. } | TopologyEvent::LinkDown { node_id, .. } | TopologyEvent::LinkRemoved { node_id, .. } | TopologyEvent::MetricUpdated { node_id, .. } => *node_id,
+            TopologyEvent::AlertFired(alert) => alert.node_id,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct TimedTopologyEvent {
+    pub ts: u64,
+    pub event: TopologyEvent,
+}
+
+/// Events caused by applying `new` over the previously stored connection state
+pub fn diff_connection(node_id: NodeId, old: Option<&NodeConnectionData>, new: &NodeConnectionData, thresholds: &AlertThresholds) -> Vec<TopologyEvent> {
+    let (conn_id, dest) = (new.id, new.node_id);
+    let mut events = vec![];
+    let old_status = old.map(|old| &old.status);
+    if old_status != Some(&new.status) {
+        match new.status {
+            ConnectionStatus::CONNECTED => events.push(TopologyEvent::LinkUp { node_id, conn_id, dest }),
+            ConnectionStatus::DISCONNECTED => {
+                if old.is_some() {
+                    events.push(TopologyEvent::LinkDown { node_id, conn_id, dest })
+                }
+            }
+        }
+    }
+    let old_metric = old.map(|old| &old.metric);
+    if old_metric != Some(&new.metric) {
+        events.push(TopologyEvent::MetricUpdated {
+            node_id,
+            conn_id,
+            dest,
+            metric: new.metric.clone(),
+        });
+        let was_high_latency = old_metric.is_some_and(|metric| metric.latency >= thresholds.latency_ms);
+        if new.metric.latency >= thresholds.latency_ms && !was_high_latency {
+            events.push(TopologyEvent::AlertFired(TopologyAlert {
+                node_id,
+                conn_id,
+                dest,
+                kind: AlertKind::HighLatency(new.metric.latency),
+            }));
+        }
+        let was_high_loss = old_metric.is_some_and(|metric| metric.loss_percent >= thresholds.loss_percent);
+        if new.metric.loss_percent >= thresholds.loss_percent && !was_high_loss {
+            events.push(TopologyEvent::AlertFired(TopologyAlert {
+                node_id,
+                conn_id,
+                dest,
+                kind: AlertKind::HighLoss(new.metric.loss_percent),
+            }));
+        }
+    }
+    events
+}
+
+type EventFilter = Box<dyn Fn(&TopologyEvent) -> bool + Send + Sync>;
+
+struct Subscriber {
+    queue: Mutex<VecDeque<TopologyEvent>>,
+    notify: Notify,
+    filter: Option<EventFilter>,
+    lagged: AtomicU64,
+    closed: AtomicBool,
+}
+
+/// Fan-out of topology changes to every live subscription.
+pub struct TopologyEventFeed {
+    subscribers: Mutex<Vec<Weak<Subscriber>>>,
+    history: Mutex<VecDeque<TimedTopologyEvent>>,
+}
+
+impl TopologyEventFeed {
+    pub fn new() -> Self {
+        Self {
+            subscribers: Mutex::new(vec![]),
+            history: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn record(&self, ts: u64, events: &[TopologyEvent]) {
+        let mut history = self.history.lock();
+        for event in events.iter().filter(|event| !matches!(event, TopologyEvent::MetricUpdated { .. })) {
+            if history.len() >= MAX_EVENT_HISTORY {
+                history.pop_front();
+            }
+            history.push_back(TimedTopologyEvent { ts, event: event.clone() });
+        }
+    }
+
+    /// Recorded events published between `from` and `to` inclusive, oldest first
+    pub fn history(&self, from: u64, to: u64) -> Vec<TimedTopologyEvent> {
+        self.history.lock().iter().filter(|event| event.ts >= from && event.ts <= to).cloned().collect()
+    }
+
+    pub fn subscribe(&self, filter: Option<EventFilter>) -> TopologySubscription {
+        let subscriber = Arc::new(Subscriber {
+            queue: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            filter,
+            lagged: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        });
+        self.subscribers.lock().push(Arc::downgrade(&subscriber));
+        TopologySubscription { inner: subscriber }
+    }
+
+    /// Deliver `events` to the subscribers and record them in the history at `ts`
+    pub fn publish(&self, ts: u64, events: Vec<TopologyEvent>) {
+        if events.is_empty() {
+            return;
+        }
+        self.record(ts, &events);
+        let mut subscribers = self.subscribers.lock();
+        subscribers.retain(|subscriber| subscriber.strong_count() > 0);
+        for subscriber in subscribers.iter().filter_map(|subscriber| subscriber.upgrade()) {
+            let mut queue = subscriber.queue.lock();
+            let mut pushed = false;
+            for event in events.iter() {
+                let accepted = match &subscriber.filter {
+                    Some(filter) => filter(event),
+                    None => true,
+                };
+                if accepted {
+                    if queue.len() >= MAX_SUBSCRIPTION_QUEUE {
+                        queue.pop_front();
+                        subscriber.lagged.fetch_add(1, Ordering::Relaxed);
+                    }
+                    queue.push_back(event.clone());
+                    pushed = true;
+                }
+            }
+            drop(queue);
+            if pushed {
+                subscriber.notify.notify();
+            }
+        }
+    }
+
+    pub fn count_subscribers(&self) -> usize {
+        self.subscribers.lock().iter().filter(|subscriber| subscriber.strong_count() > 0).count()
+    }
+}
+
+impl Drop for TopologyEventFeed {
+    fn drop(&mut self) {
+        for subscriber in self.subscribers.lock().iter().filter_map(|subscriber| subscriber.upgrade()) {
+            subscriber.closed.store(true, Ordering::Release);
+            subscriber.notify.notify();
+        }
+    }
+}
+
+/// Receiving side of a topology subscription, dropping it unsubscribes.
+pub struct TopologySubscription {
+    inner: Arc<Subscriber>,
+}
+
+impl TopologySubscription {
+    /// Wait for the next event, returns None once the controller is dropped and all events are consumed
+    pub async fn recv(&mut self) -> Option<TopologyEvent> {
+        loop {
+            if let Some(event) = self.try_recv() {
+                return Some(event);
+            }
+            if self.inner.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            self.inner.notify.notified().await;
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Option<TopologyEvent> {
+        self.inner.queue.lock().pop_front()
+    }
+
+    /// Number of events dropped because the subscription queue was full
+    pub fn lagged(&self) -> u64 {
+        self.inner.lagged.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_deliver_events_to_matching_subscribers() {
+        let feed = TopologyEventFeed::new();
+        let mut all = feed.subscribe(None);
+        let mut only_node_2 = feed.subscribe(Some(Box::new(|event| event.node_id() == 2)));
+
+        feed.publish(0, vec![TopologyEvent::NodeJoined(1), TopologyEvent::NodeJoined(2)]);
+
+        assert_eq!(all.try_recv(), Some(TopologyEvent::NodeJoined(1)));
+        assert_eq!(all.try_recv(), Some(TopologyEvent::NodeJoined(2)));
+        assert_eq!(all.try_recv(), None);
+        assert_eq!(only_node_2.try_recv(), Some(TopologyEvent::NodeJoined(2)));
+        assert_eq!(only_node_2.try_recv(), None);
+    }
+
+    #[test]
+    fn history_should_keep_events_except_metric_updates() {
+        let feed = TopologyEventFeed::new();
+        feed.record(100, &[TopologyEvent::NodeJoined(1)]);
+        feed.record(
+            200,
+            &[
+                TopologyEvent::MetricUpdated {
+                    node_id: 1,
+                    conn_id: 1,
+                    dest: 2,
+                    metric: ConnectionMetric {
+                        latency: 1,
+                        bandwidth: 1,
+                        loss_percent: 0,
+                    },
+                },
+                TopologyEvent::LinkDown { node_id: 1, conn_id: 1, dest: 2 },
+            ],
+        );
+        assert_eq!(
+            feed.history(150, 300),
+            vec![TimedTopologyEvent {
+                ts: 200,
+                event: TopologyEvent::LinkDown { node_id: 1, conn_id: 1, dest: 2 },
+            }]
+        );
+
+        for node_id in 0..(MAX_EVENT_HISTORY as NodeId) {
+            feed.record(300, &[TopologyEvent::NodeLeft(node_id)]);
+        }
+        let history = feed.history(0, u64::MAX);
+        assert_eq!(history.len(), MAX_EVENT_HISTORY);
+        assert_eq!(history[0].event, TopologyEvent::NodeLeft(0));
+    }
+
+    #[test]
+    fn should_drop_oldest_events_when_subscriber_lags() {
+        let feed = TopologyEventFeed::new();
+        let mut subscription = feed.subscribe(None);
+
+        feed.publish(0, (0..(MAX_SUBSCRIPTION_QUEUE as NodeId + 2)).map(TopologyEvent::NodeJoined).collect());
+
+        assert_eq!(subscription.lagged(), 2);
+        assert_eq!(subscription.try_recv(), Some(TopologyEvent::NodeJoined(2)));
+    }
+
+    #[test]
+    fn should_forget_dropped_subscriptions() {
+        let feed = TopologyEventFeed::new();
+        let subscription = feed.subscribe(None);
+        assert_eq!(feed.count_subscribers(), 1);
+
+        drop(subscription);
+        feed.publish(0, vec![TopologyEvent::NodeLeft(1)]);
+        assert_eq!(feed.count_subscribers(), 0);
+    }
+
+    fn conn(status: ConnectionStatus, latency: u16, loss_percent: u32) -> NodeConnectionData {
+        NodeConnectionData {
+            id: 1,
+            node_id: 2,
+            protocol: 1,
+            addr: String::from("127.0.0.1"),
+            metric: ConnectionMetric {
+                latency,
+                bandwidth: 100,
+                loss_percent,
+            },
+            status,
+            last_updated_at: 0,
+            direction: 0,
+        }
+    }
+
+    #[test]
+    fn diff_connection_should_emit_link_and_metric_events() {
+        let thresholds = AlertThresholds::default();
+        let up = conn(ConnectionStatus::CONNECTED, 10, 0);
+        let down = conn(ConnectionStatus::DISCONNECTED, 10, 0);
+
+        assert_eq!(
+            diff_connection(1, None, &up, &thresholds),
+            vec![
+                TopologyEvent::LinkUp { node_id: 1, conn_id: 1, dest: 2 },
+                TopologyEvent::MetricUpdated {
+                    node_id: 1,
+                    conn_id: 1,
+                    dest: 2,
+                    metric: up.metric.clone()
+                },
+            ]
+        );
+        assert_eq!(diff_connection(1, Some(&up), &up, &thresholds), vec![]);
+        assert_eq!(diff_connection(1, Some(&up), &down, &thresholds), vec![TopologyEvent::LinkDown { node_id: 1, conn_id: 1, dest: 2 }]);
+    }
+
+    #[test]
+    fn diff_connection_should_fire_alert_only_when_crossing_threshold() {
+        let thresholds = AlertThresholds::default();
+        let good = conn(ConnectionStatus::CONNECTED, 10, 0);
+        let lossy = conn(ConnectionStatus::CONNECTED, 10, 50);
+        let lossier = conn(ConnectionStatus::CONNECTED, 10, 60);
+
+        let alerts = |events: Vec<TopologyEvent>| events.into_iter().filter(|event| matches!(event, TopologyEvent::AlertFired(_))).count();
+        assert_eq!(alerts(diff_connection(1, Some(&good), &lossy, &thresholds)), 1);
+        assert_eq!(alerts(diff_connection(1, Some(&lossy), &lossier, &thresholds)), 0);
+        assert_eq!(alerts(diff_connection(1, Some(&lossier), &good, &thresholds)), 0);
+    }
+
+    #[tokio::test]
+    async fn recv_should_wait_for_published_event_and_end_when_feed_dropped() {
+        let feed = Arc::new(TopologyEventFeed::new());
+        let mut subscription = feed.subscribe(None);
+
+        let publisher = feed.clone();
+        let task = tokio::spawn(async move {
+            publisher.publish(0, vec![TopologyEvent::NodeJoined(1)]);
+        });
+        assert_eq!(subscription.recv().await, Some(TopologyEvent::NodeJoined(1)));
+        task.await.expect("should publish");
+
+        drop(feed);
+        assert_eq!(subscription.recv().await, None);
+    }
+}
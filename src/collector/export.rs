@@ -0,0 +1,320 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fmt::Write,
+};
+
+use atm0s_sdn_identity::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::identity::ConnectionStatus;
+
+use super::storage::{NodeConnectionData, NodeData, NodeMap};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// Graphviz DOT, node labels become `label_<key>` attributes
+    Dot,
+    /// GraphML, loads in Gephi, yEd or networkx
+    Graphml,
+    /// JSON Graph Format v2
+    #[default]
+    Jgf,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Dot => "text/vnd.graphviz",
+            ExportFormat::Graphml => "application/graphml+xml",
+            ExportFormat::Jgf => "application/json",
+        }
+    }
+}
+
+/// Restrict an export to the nodes within `depth` hops of `node_id`, following links reported by either end
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ExportScope {
+    pub node_id: NodeId,
+    pub depth: usize,
+}
+
+struct Graph<'a> {
+    /// Selected node ids, with their data unless the node never reported
+    nodes: BTreeMap<NodeId, Option<&'a NodeData>>,
+    edges: Vec<(NodeId, &'a NodeConnectionData)>,
+}
+
+fn select<'a>(nodes: &'a NodeMap, scope: Option<ExportScope>) -> Graph<'a> {
+    let mut adjacency = HashMap::<NodeId, BTreeSet<NodeId>>::new();
+    for node in nodes.values() {
+        for conn in node.conns.iter() {
+            adjacency.entry(node.id).or_default().insert(conn.node_id);
+            adjacency.entry(conn.node_id).or_default().insert(node.id);
+        }
+    }
+    let selected: BTreeSet<NodeId> = match scope {
+        Some(scope) => {
+            let mut selected = BTreeSet::from([scope.node_id]);
+            let mut queue = VecDeque::from([(scope.node_id, 0)]);
+            while let Some((current, hops)) = queue.pop_front() {
+                if hops >= scope.depth {
+                    continue;
+                }
+                for next in adjacency.get(&current).into_iter().flatten() {
+                    if selected.insert(*next) {
+                        queue.push_back((*next, hops + 1));
+                    }
+                }
+            }
+            selected
+        }
+        None => nodes.keys().chain(adjacency.keys()).cloned().collect(),
+    };
+
+    let mut edges = vec![];
+    for id in selected.iter() {
+        if let Some(node) = nodes.get(id) {
+            edges.extend(node.conns.iter().filter(|conn| selected.contains(&conn.node_id)).map(|conn| (node.id, conn)));
+        }
+    }
+    Graph {
+        nodes: selected.into_iter().map(|id| (id, nodes.get(&id).map(|node| node.as_ref()))).collect(),
+        edges,
+    }
+}
+
+pub(super) fn status_str(status: &ConnectionStatus) -> &'static str {
+    match status {
+        ConnectionStatus::CONNECTED => "connected",
+        ConnectionStatus::DISCONNECTED => "disconnected",
+    }
+}
+
+/// Render the topology, or the subgraph selected by `scope`, in the given format
+pub fn export(nodes: &NodeMap, format: ExportFormat, scope: Option<ExportScope>) -> String {
+    let graph = select(nodes, scope);
+    match format {
+        ExportFormat::Dot => to_dot(&graph),
+        ExportFormat::Graphml => to_graphml(&graph),
+        ExportFormat::Jgf => to_jgf(&graph),
+    }
+}
+
+fn dot_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Label keys are prefixed with `label_`, so a label named like a DOT attribute, `label` or `color`, does not override it
+fn to_dot(graph: &Graph) -> String {
+    let mut out = String::from("digraph sdn {\n");
+    for (id, node) in graph.nodes.iter() {
+        let mut attrs = vec![];
+        if let Some(node) = node {
+            attrs.extend(node.labels.iter().map(|(key, value)| format!("\"label_{}\"=\"{}\"", dot_escape(key), dot_escape(value))));
+            attrs.push(format!("addr=\"{}\"", dot_escape(&node.addr)));
+        }
+        attrs.push(format!("label=\"{}\"", id));
+        let _ = writeln!(out, "  \"{}\" [{}];", id, attrs.join(", "));
+    }
+    for (src, conn) in graph.edges.iter() {
+        let _ = writeln!(
+            out,
+            "  \"{}\" -> \"{}\" [conn_id=\"{}\", protocol={}, status=\"{}\", latency={}, loss={}, bandwidth={}];",
+            src,
+            conn.node_id,
+            conn.id,
+            conn.protocol,
+            status_str(&conn.status),
+            conn.metric.latency,
+            conn.metric.loss_percent,
+            conn.metric.bandwidth
+        );
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+fn to_graphml(graph: &Graph) -> String {
+    let label_keys: BTreeSet<&String> = graph.nodes.values().flatten().flat_map(|node| node.labels.keys()).collect();
+    let label_ids: BTreeMap<&String, String> = label_keys.iter().enumerate().map(|(index, key)| (*key, format!("l{}", index))).collect();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    out.push_str("  <key id=\"addr\" for=\"node\" attr.name=\"addr\" attr.type=\"string\"/>\n");
+    for (key, id) in label_ids.iter() {
+        let _ = writeln!(out, "  <key id=\"{}\" for=\"node\" attr.name=\"{}\" attr.type=\"string\"/>", id, xml_escape(key));
+    }
+    out.push_str("  <key id=\"conn_id\" for=\"edge\" attr.name=\"conn_id\" attr.type=\"string\"/>\n");
+    out.push_str("  <key id=\"protocol\" for=\"edge\" attr.name=\"protocol\" attr.type=\"int\"/>\n");
+    out.push_str("  <key id=\"status\" for=\"edge\" attr.name=\"status\" attr.type=\"string\"/>\n");
+    out.push_str("  <key id=\"latency\" for=\"edge\" attr.name=\"latency\" attr.type=\"int\"/>\n");
+    out.push_str("  <key id=\"loss\" for=\"edge\" attr.name=\"loss\" attr.type=\"int\"/>\n");
+    out.push_str("  <key id=\"bandwidth\" for=\"edge\" attr.name=\"bandwidth\" attr.type=\"long\"/>\n");
+    out.push_str("  <graph id=\"sdn\" edgedefault=\"directed\">\n");
+    for (id, node) in graph.nodes.iter() {
+        let _ = write!(out, "    <node id=\"n{}\">", id);
+        if let Some(node) = node {
+            let _ = write!(out, "<data key=\"addr\">{}</data>", xml_escape(&node.addr));
+            for (key, value) in node.labels.iter() {
+                let _ = write!(out, "<data key=\"{}\">{}</data>", label_ids[key], xml_escape(value));
+            }
+        }
+        out.push_str("</node>\n");
+    }
+    for (index, (src, conn)) in graph.edges.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\"><data key=\"conn_id\">{}</data><data key=\"protocol\">{}</data><data key=\"status\">{}</data><data key=\"latency\">{}</data><data key=\"loss\">{}</data><data key=\"bandwidth\">{}</data></edge>",
+            index,
+            src,
+            conn.node_id,
+            conn.id,
+            conn.protocol,
+            status_str(&conn.status),
+            conn.metric.latency,
+            conn.metric.loss_percent,
+            conn.metric.bandwidth
+        );
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+fn to_jgf(graph: &Graph) -> String {
+    let nodes: serde_json::Map<String, serde_json::Value> = graph
+        .nodes
+        .iter()
+        .map(|(id, node)| {
+            let metadata = match node {
+                Some(node) => serde_json::json!({ "addr": node.addr, "labels": node.labels }),
+                None => serde_json::json!({}),
+            };
+            (id.to_string(), serde_json::json!({ "label": id.to_string(), "metadata": metadata }))
+        })
+        .collect();
+    let edges: Vec<serde_json::Value> = graph
+        .edges
+        .iter()
+        .map(|(src, conn)| {
+            serde_json::json!({
+                "source": src.to_string(),
+                "target": conn.node_id.to_string(),
+                "relation": "connection",
+                "directed": true,
+                "metadata": {
+                    "conn_id": conn.id.to_string(),
+                    "protocol": conn.protocol,
+                    "status": status_str(&conn.status),
+                    "latency": conn.metric.latency,
+                    "loss": conn.metric.loss_percent,
+                    "bandwidth": conn.metric.bandwidth,
+                },
+            })
+        })
+        .collect();
+    serde_json::json!({ "graph": { "id": "sdn", "directed": true, "nodes": nodes, "edges": edges } }).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::identity::ConnectionMetric;
+
+    use super::*;
+
+    fn conn(dest: NodeId, status: ConnectionStatus) -> NodeConnectionData {
+        NodeConnectionData {
+            id: dest as u64,
+            node_id: dest,
+            protocol: 1,
+            addr: format!("/ip4/127.0.0.{}", dest),
+            metric: ConnectionMetric {
+                latency: 10,
+                loss_percent: 2,
+                bandwidth: 100,
+            },
+            status,
+            last_updated_at: 0,
+            direction: 0,
+        }
+    }
+
+    // 1 -> 2 -> 3 -> 4, node 4 never reported
+    fn chain() -> NodeMap {
+        let mut nodes = NodeMap::new();
+        for (id, dest) in [(1, 2), (2, 3), (3, 4)] {
+            let mut node = NodeData::new(id, format!("/ip4/127.0.0.{}", id), 0);
+            node.labels.insert(String::from("zone"), format!("z\"{}<", id));
+            node.conns = vec![conn(dest, ConnectionStatus::CONNECTED)].into();
+            nodes.insert(id, Arc::new(node));
+        }
+        nodes
+    }
+
+    #[test]
+    fn dot_should_render_labels_and_edge_metrics() {
+        let scope = ExportScope { node_id: 1, depth: 1 };
+        assert_eq!(
+            export(&chain(), ExportFormat::Dot, Some(scope)),
+            concat!(
+                "digraph sdn {\n",
+                "  \"1\" [\"label_zone\"=\"z\\\"1<\", addr=\"/ip4/127.0.0.1\", label=\"1\"];\n",
+                "  \"2\" [\"label_zone\"=\"z\\\"2<\", addr=\"/ip4/127.0.0.2\", label=\"2\"];\n",
+                "  \"1\" -> \"2\" [conn_id=\"2\", protocol=1, status=\"connected\", latency=10, loss=2, bandwidth=100];\n",
+                "}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn dot_label_named_like_an_attribute_should_not_override_it() {
+        let mut nodes = NodeMap::new();
+        let mut node = NodeData::new(1, String::from("/ip4/127.0.0.1"), 0);
+        node.labels.insert(String::from("label"), String::from("edge"));
+        nodes.insert(1, Arc::new(node));
+        assert_eq!(
+            export(&nodes, ExportFormat::Dot, None),
+            "digraph sdn {\n  \"1\" [\"label_label\"=\"edge\", addr=\"/ip4/127.0.0.1\", label=\"1\"];\n}\n"
+        );
+    }
+
+    #[test]
+    fn subgraph_should_follow_links_in_both_directions() {
+        let nodes = chain();
+        let graph = select(&nodes, Some(ExportScope { node_id: 3, depth: 1 }));
+        assert_eq!(graph.nodes.keys().cloned().collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!(graph.edges.iter().map(|(src, conn)| (*src, conn.node_id)).collect::<Vec<_>>(), vec![(2, 3), (3, 4)]);
+
+        let all = select(&nodes, None);
+        assert_eq!(all.nodes.keys().cloned().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        assert!(all.nodes[&4].is_none());
+        assert_eq!(all.edges.len(), 3);
+    }
+
+    #[test]
+    fn graphml_should_declare_keys_and_escape_values() {
+        let out = export(&chain(), ExportFormat::Graphml, None);
+        assert!(out.contains("<key id=\"l0\" for=\"node\" attr.name=\"zone\" attr.type=\"string\"/>"));
+        assert!(out.contains("<node id=\"n1\"><data key=\"addr\">/ip4/127.0.0.1</data><data key=\"l0\">z&quot;1&lt;</data></node>"));
+        assert!(out.contains("<node id=\"n4\"></node>"));
+        assert!(out.contains("<edge id=\"e2\" source=\"n3\" target=\"n4\">"));
+        assert!(out.ends_with("</graph>\n</graphml>\n"));
+    }
+
+    #[test]
+    fn jgf_should_be_valid_json_graph() {
+        let out: serde_json::Value = serde_json::from_str(&export(&chain(), ExportFormat::Jgf, None)).expect("should be json");
+        let graph = &out["graph"];
+        assert_eq!(graph["nodes"].as_object().map(|nodes| nodes.len()), Some(4));
+        assert_eq!(graph["nodes"]["2"]["metadata"]["labels"]["zone"], "z\"2<");
+        assert_eq!(graph["edges"][0]["source"], "1");
+        assert_eq!(graph["edges"][0]["target"], "2");
+        assert_eq!(graph["edges"][0]["metadata"]["status"], "connected");
+        assert_eq!(graph["edges"][0]["metadata"]["latency"], 10);
+    }
+}
@@ -0,0 +1,464 @@
+//! Grafana SimpleJSON datasource contract over the stored connection samples and topology events.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use atm0s_sdn_identity::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::identity::ConnectionStatus;
+
+use super::{
+    events::{AlertKind, TimedTopologyEvent, TopologyEvent},
+    samples::{ConnectionSample, SampleFilter},
+    SdnMonitorController,
+};
+
+/// Targets returned by `/search` at most
+pub const GRAFANA_SEARCH_LIMIT: usize = 1000;
+
+const METRICS: [&str; 4] = ["latency", "loss", "bandwidth", "up"];
+
+#[derive(Debug, PartialEq, Eq, Clone, Default, Deserialize)]
+pub struct GrafanaSearchRequest {
+    #[serde(default)]
+    pub target: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+pub struct GrafanaRange {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrafanaQueryTarget {
+    #[serde(default)]
+    pub target: String,
+    #[serde(default)]
+    pub hide: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrafanaQueryRequest {
+    pub range: GrafanaRange,
+    pub targets: Vec<GrafanaQueryTarget>,
+    #[serde(default)]
+    pub max_data_points: Option<usize>,
+}
+
+/// `datapoints` are `[value, timestamp_ms]` pairs
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct GrafanaSeries {
+    pub target: String,
+    pub datapoints: Vec<(i64, u64)>,
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct GrafanaAnnotationRequest {
+    pub range: GrafanaRange,
+    /// Echoed back in every annotation, its `query` is a comma separated list of event kinds
+    pub annotation: serde_json::Value,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct GrafanaAnnotation {
+    pub annotation: serde_json::Value,
+    pub time: u64,
+    pub title: String,
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+/// `latency{src=1,dst=2}`, labels are optional and `conn` picks one of parallel connections
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GrafanaTarget {
+    pub metric: &'static str,
+    pub src: Option<NodeId>,
+    pub dst: Option<NodeId>,
+    pub conn: Option<u64>,
+}
+
+impl GrafanaTarget {
+    pub fn parse(target: &str) -> Result<Self, String> {
+        let target = target.trim();
+        let (name, labels) = match target.split_once('{') {
+            Some((name, rest)) => (name.trim(), rest.strip_suffix('}').ok_or_else(|| format!("invalid target {}, missing }}", target))?),
+            None => (target, ""),
+        };
+        let metric = METRICS
+            .iter()
+            .find(|metric| **metric == name)
+            .ok_or_else(|| format!("unknown metric {}, expected one of {}", name, METRICS.join(", ")))?;
+        let mut parsed = Self {
+            metric,
+            src: None,
+            dst: None,
+            conn: None,
+        };
+        for label in labels.split(',').map(|label| label.trim()).filter(|label| !label.is_empty()) {
+            let (key, value) = label.split_once('=').ok_or_else(|| format!("invalid label {}", label))?;
+            let value = value.trim().trim_matches('"');
+            let invalid = || format!("invalid value of label {}", label);
+            match key.trim() {
+                "src" => parsed.src = Some(value.parse().map_err(|_| invalid())?),
+                "dst" => parsed.dst = Some(value.parse().map_err(|_| invalid())?),
+                "conn" => parsed.conn = Some(value.parse().map_err(|_| invalid())?),
+                key => return Err(format!("unknown label {}, expected src, dst or conn", key)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn matches(&self, sample: &ConnectionSample) -> bool {
+        self.src.iter().all(|src| sample.src == *src) && self.dst.iter().all(|dst| sample.dst == *dst) && self.conn.iter().all(|conn| sample.conn_id == *conn)
+    }
+
+    fn value(&self, sample: &ConnectionSample) -> i64 {
+        match self.metric {
+            "latency" => sample.latency as i64,
+            "loss" => sample.loss as i64,
+            "bandwidth" => sample.bandwidth as i64,
+            _ => (sample.status == ConnectionStatus::CONNECTED) as i64,
+        }
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let (year, month_from_march) = match month {
+        1 | 2 => (year - 1, month + 9),
+        _ => (year, month - 3),
+    };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * month_from_march + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Parse the RFC 3339 timestamps Grafana sends, e.g. 2016-10-31T06:33:44.866Z, or plain epoch milliseconds
+pub fn parse_time_ms(value: &str) -> Option<u64> {
+    if let Ok(ms) = value.parse::<u64>() {
+        return Some(ms);
+    }
+    let num = |from: usize, to: usize| value.get(from..to).and_then(|part| part.parse::<i64>().ok());
+    let bytes = value.as_bytes();
+    if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || !matches!(bytes[10], b'T' | b't' | b' ') || bytes[13] != b':' || bytes[16] != b':' {
+        return None;
+    }
+    let (year, month, day) = (num(0, 4)?, num(5, 7)?, num(8, 10)?);
+    let (hour, minute, second) = (num(11, 13)?, num(14, 16)?, num(17, 19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut rest = &value[19..];
+    let mut millis = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(|byte| byte.is_ascii_digit()).count();
+        if digits == 0 {
+            return None;
+        }
+        millis = format!("{:0<3}", &fraction[..digits.min(3)]).parse::<i64>().ok()?;
+        rest = &fraction[digits..];
+    }
+    let offset_secs = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            sign * (rest[1..3].parse::<i64>().ok()? * 3600 + rest[4..6].parse::<i64>().ok()? * 60)
+        }
+        _ => return None,
+    };
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset_secs;
+    u64::try_from(secs * 1000 + millis).ok()
+}
+
+fn parse_range(range: &GrafanaRange) -> Result<(u64, u64), String> {
+    let from = parse_time_ms(&range.from).ok_or_else(|| format!("invalid range from {}", range.from))?;
+    let to = parse_time_ms(&range.to).ok_or_else(|| format!("invalid range to {}", range.to))?;
+    Ok((from, to))
+}
+
+/// Metric names, then one target per connected pair the collector knows, containing `filter`
+pub fn search(controller: &SdnMonitorController, filter: &str) -> Vec<String> {
+    let mut pairs = BTreeSet::new();
+    for node in controller.get_nodes_shared() {
+        pairs.extend(node.conns.iter().map(|conn| (node.id, conn.node_id)));
+    }
+    METRICS
+        .iter()
+        .map(|metric| metric.to_string())
+        .chain(METRICS.iter().flat_map(|metric| pairs.iter().map(move |(src, dst)| format!("{}{{src={},dst={}}}", metric, src, dst))))
+        .filter(|target| target.contains(filter))
+        .take(GRAFANA_SEARCH_LIMIT)
+        .collect()
+}
+
+/// Keep every n-th point so a series has at most `max` points, the last one is always kept
+fn downsample(points: Vec<(i64, u64)>, max: usize) -> Vec<(i64, u64)> {
+    if max == 0 || points.len() <= max {
+        return points;
+    }
+    let step = (points.len() - 1) / max + 1;
+    let mut kept: Vec<(i64, u64)> = points.into_iter().rev().step_by(step).collect();
+    kept.reverse();
+    kept
+}
+
+/// Points of a target per (src, dst, conn_id)
+type PointsByConn = BTreeMap<(NodeId, NodeId, u64), Vec<(i64, u64)>>;
+
+/// One series per matching connection, named after the pair and with `conn` when the pair has parallel connections.
+/// The samples are scanned once for all targets.
+pub fn query(controller: &SdnMonitorController, request: &GrafanaQueryRequest) -> Result<Vec<GrafanaSeries>, String> {
+    let (from, to) = parse_range(&request.range)?;
+    let targets = request
+        .targets
+        .iter()
+        .filter(|target| !target.hide && !target.target.is_empty())
+        .map(|target| GrafanaTarget::parse(&target.target))
+        .collect::<Result<Vec<_>, _>>()?;
+    if targets.is_empty() {
+        return Ok(vec![]);
+    }
+    // only the nodes of the targets are scanned when each of them names one
+    let nodes = targets.iter().map(|target| target.src.or(target.dst)).collect::<Option<BTreeSet<NodeId>>>();
+    let filter = SampleFilter {
+        from: Some(from),
+        to: Some(to),
+        nodes,
+    };
+    let mut by_target: Vec<PointsByConn> = vec![BTreeMap::new(); targets.len()];
+    for sample in controller.connection_samples(filter) {
+        for (parsed, by_conn) in targets.iter().zip(by_target.iter_mut()).filter(|(parsed, _)| parsed.matches(&sample)) {
+            by_conn.entry((sample.src, sample.dst, sample.conn_id)).or_default().push((parsed.value(&sample), sample.ts));
+        }
+    }
+
+    let mut series = vec![];
+    for (parsed, by_conn) in targets.iter().zip(by_target) {
+        let mut per_pair: BTreeMap<(NodeId, NodeId), usize> = BTreeMap::new();
+        for (src, dst, _) in by_conn.keys() {
+            *per_pair.entry((*src, *dst)).or_default() += 1;
+        }
+        for ((src, dst, conn), mut points) in by_conn {
+            points.sort_by_key(|(_, ts)| *ts);
+            let name = match per_pair[&(src, dst)] {
+                1 => format!("{}{{src={},dst={}}}", parsed.metric, src, dst),
+                _ => format!("{}{{src={},dst={},conn={}}}", parsed.metric, src, dst, conn),
+            };
+            series.push(GrafanaSeries {
+                target: name,
+                datapoints: downsample(points, request.max_data_points.unwrap_or(0)),
+            });
+        }
+    }
+    Ok(series)
+}
+
+fn describe(event: &TopologyEvent) -> Option<(&'static str, String, String)> {
+    let link =
+        |title: &'static str, kind: &'static str, node_id: &NodeId, conn_id: &u64, dest: &NodeId| Some((kind, title.to_string(), format!("node {} connection {} to {}", node_id, conn_id, dest)));
+    match event {
+        TopologyEvent::NodeJoined(node_id) => Some(("node_joined", String::from("Node joined"), format!("node {}", node_id))),
+        TopologyEvent::NodeLeft(node_id) => Some(("node_left", String::from("Node left"), format!("node {}", node_id))),
+        TopologyEvent::LinkUp { node_id, conn_id, dest } => link("Link up", "link_up", node_id, conn_id, dest),
+        TopologyEvent::LinkDown { node_id, conn_id, dest } => link("Link down", "link_down", node_id, conn_id, dest),
+        TopologyEvent::LinkRemoved { node_id, conn_id, dest } => link("Link removed", "link_removed", node_id, conn_id, dest),
+        TopologyEvent::MetricUpdated { .. } => None,
+        TopologyEvent::AlertFired(alert) => {
+            let reason = match alert.kind {
+                AlertKind::HighLatency(latency) => format!("latency {}ms", latency),
+                AlertKind::HighLoss(loss) => format!("loss {}%", loss),
+            };
+            Some((
+                "alert",
+                String::from("Alert"),
+                format!("node {} connection {} to {}: {}", alert.node_id, alert.conn_id, alert.dest, reason),
+            ))
+        }
+    }
+}
+
+fn to_annotation(annotation: &serde_json::Value, kinds: &Option<BTreeSet<String>>, event: &TimedTopologyEvent) -> Option<GrafanaAnnotation> {
+    let (kind, title, text) = describe(&event.event)?;
+    if kinds.as_ref().is_some_and(|kinds| !kinds.contains(kind)) {
+        return None;
+    }
+    Some(GrafanaAnnotation {
+        annotation: annotation.clone(),
+        time: event.ts,
+        title,
+        text,
+        tags: vec![kind.to_string(), format!("node:{}", event.event.node_id())],
+    })
+}
+
+/// Topology events in range, optionally only the kinds listed in the annotation query, e.g. `link_down,alert`
+pub fn annotations(controller: &SdnMonitorController, request: &GrafanaAnnotationRequest) -> Result<Vec<GrafanaAnnotation>, String> {
+    let (from, to) = parse_range(&request.range)?;
+    let kinds: Option<BTreeSet<String>> = request
+        .annotation
+        .get("query")
+        .and_then(|query| query.as_str())
+        .map(|query| query.split(',').map(|kind| kind.trim().to_string()).filter(|kind| !kind.is_empty()).collect::<BTreeSet<_>>())
+        .filter(|kinds| !kinds.is_empty());
+    Ok(controller
+        .event_history(from, to)
+        .iter()
+        .filter_map(|event| to_annotation(&request.annotation, &kinds, event))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{collector::NodeConnectionData, identity::ConnectionMetric};
+
+    use super::*;
+
+    #[test]
+    fn target_should_parse_metric_and_labels() {
+        assert_eq!(
+            GrafanaTarget::parse("latency{src=1, dst=\"2\"}"),
+            Ok(GrafanaTarget {
+                metric: "latency",
+                src: Some(1),
+                dst: Some(2),
+                conn: None,
+            })
+        );
+        assert_eq!(GrafanaTarget::parse("up").map(|target| target.metric), Ok("up"));
+        assert!(GrafanaTarget::parse("jitter{src=1}").is_err());
+        assert!(GrafanaTarget::parse("latency{zone=eu}").is_err());
+        assert!(GrafanaTarget::parse("latency{src=1").is_err());
+    }
+
+    #[test]
+    fn time_should_parse_rfc3339() {
+        assert_eq!(parse_time_ms("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_time_ms("2016-10-31T06:33:44.866Z"), Some(1477895624866));
+        assert_eq!(parse_time_ms("2016-10-31T08:33:44.8+02:00"), Some(1477895624800));
+        assert_eq!(parse_time_ms("1477895624866"), Some(1477895624866));
+        assert_eq!(parse_time_ms("2016-13-31T06:33:44Z"), None);
+        assert_eq!(parse_time_ms("yesterday"), None);
+    }
+
+    #[test]
+    fn downsample_should_keep_last_point() {
+        let points: Vec<(i64, u64)> = (0..10).map(|ts| (ts as i64, ts)).collect();
+        assert_eq!(downsample(points.clone(), 4), vec![(0, 0), (3, 3), (6, 6), (9, 9)]);
+        assert_eq!(downsample(points.clone(), 0), points);
+    }
+
+    fn report(controller: &mut SdnMonitorController, conn_id: u64, dest: NodeId, latency: u16, ts: u64) {
+        controller.update_node_conns(
+            1,
+            vec![NodeConnectionData {
+                id: conn_id,
+                node_id: dest,
+                protocol: 1,
+                addr: String::from("/ip4/127.0.0.2"),
+                metric: ConnectionMetric {
+                    latency,
+                    loss_percent: 0,
+                    bandwidth: 100,
+                },
+                status: ConnectionStatus::CONNECTED,
+                last_updated_at: ts,
+                direction: 0,
+            }],
+        );
+    }
+
+    fn range(from: u64, to: u64) -> GrafanaRange {
+        GrafanaRange {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    #[test]
+    fn query_should_return_series_per_connection() {
+        let mut controller = SdnMonitorController::new();
+        controller.upsert_node(1, String::from("/ip4/127.0.0.1"), 0);
+        report(&mut controller, 10, 2, 5, 1000);
+        report(&mut controller, 10, 2, 7, 2000);
+        report(&mut controller, 11, 3, 9, 2000);
+        report(&mut controller, 12, 3, 11, 3000);
+
+        let request = |target: &str, from: u64| GrafanaQueryRequest {
+            range: range(from, 5000),
+            targets: vec![GrafanaQueryTarget {
+                target: target.to_string(),
+                hide: false,
+            }],
+            max_data_points: None,
+        };
+        assert_eq!(
+            query(&controller, &request("latency{src=1,dst=2}", 0)),
+            Ok(vec![GrafanaSeries {
+                target: String::from("latency{src=1,dst=2}"),
+                datapoints: vec![(5, 1000), (7, 2000)],
+            }])
+        );
+        let parallel = query(&controller, &request("latency{dst=3}", 0)).expect("should query");
+        assert_eq!(
+            parallel.iter().map(|series| series.target.as_str()).collect::<Vec<_>>(),
+            vec!["latency{src=1,dst=3,conn=11}", "latency{src=1,dst=3,conn=12}"]
+        );
+        assert_eq!(query(&controller, &request("latency", 1500)).map(|series| series.len()), Ok(3));
+        let mut both = request("latency{src=1,dst=2}", 0);
+        both.targets.push(GrafanaQueryTarget {
+            target: String::from("loss{dst=3}"),
+            hide: false,
+        });
+        assert_eq!(
+            query(&controller, &both).map(|series| series.into_iter().map(|series| series.target).collect::<Vec<_>>()),
+            Ok(vec![
+                String::from("latency{src=1,dst=2}"),
+                String::from("loss{src=1,dst=3,conn=11}"),
+                String::from("loss{src=1,dst=3,conn=12}")
+            ])
+        );
+        assert!(query(&controller, &request("latency{src=x}", 0)).is_err());
+
+        assert_eq!(
+            search(&controller, "dst=3"),
+            vec!["latency{src=1,dst=3}", "loss{src=1,dst=3}", "bandwidth{src=1,dst=3}", "up{src=1,dst=3}"]
+        );
+        assert_eq!(search(&controller, "").len(), 4 + 4 * 2);
+    }
+
+    #[test]
+    fn annotations_should_filter_by_kind() {
+        let controller = SdnMonitorController::new();
+        let annotation = serde_json::json!({ "name": "topology", "query": "link_down, alert" });
+        let event = |event: TopologyEvent| TimedTopologyEvent { ts: 100, event };
+        let kinds = Some(BTreeSet::from([String::from("link_down"), String::from("alert")]));
+
+        assert_eq!(to_annotation(&annotation, &kinds, &event(TopologyEvent::NodeJoined(1))), None);
+        let down = to_annotation(&annotation, &kinds, &event(TopologyEvent::LinkDown { node_id: 1, conn_id: 5, dest: 2 })).expect("should annotate");
+        assert_eq!(down.title, "Link down");
+        assert_eq!(down.text, "node 1 connection 5 to 2");
+        assert_eq!(down.tags, vec!["link_down", "node:1"]);
+        assert_eq!(down.annotation, annotation);
+
+        let request = GrafanaAnnotationRequest {
+            range: range(0, u64::MAX),
+            annotation: serde_json::json!({ "name": "topology" }),
+        };
+        let mut writer = controller.clone();
+        writer.upsert_node(1, String::from("/ip4/127.0.0.1"), 1000);
+        let found = annotations(&controller, &request).expect("should list");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].title, "Node joined");
+        assert_eq!(found[0].time, 1000);
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use atm0s_sdn_identity::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::identity::ConnectionStatus;
+
+use super::storage::{NodeConnectionData, NodeData, NodeMap};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkMetric {
+    /// Highest latency first
+    Latency,
+    /// Highest loss first
+    Loss,
+    /// Lowest bandwidth first
+    Bandwidth,
+}
+
+/// A connection as reported by the `src` node
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Link {
+    pub src: NodeId,
+    pub conn: NodeConnectionData,
+}
+
+fn connected(node: &NodeData) -> impl Iterator<Item = &NodeConnectionData> {
+    node.conns.iter().filter(|conn| conn.status == ConnectionStatus::CONNECTED)
+}
+
+/// Nodes which `node_id` reports a live connection to
+pub fn neighbors(nodes: &NodeMap, node_id: NodeId) -> Vec<NodeId> {
+    let mut ret_val: Vec<NodeId> = match nodes.get(&node_id) {
+        Some(node) => connected(node).map(|conn| conn.node_id).collect(),
+        None => vec![],
+    };
+    ret_val.sort_unstable();
+    ret_val.dedup();
+    ret_val
+}
+
+/// Connections reported by `a` towards `b`, one per protocol and direction
+pub fn edge(nodes: &NodeMap, a: NodeId, b: NodeId) -> Vec<NodeConnectionData> {
+    match nodes.get(&a) {
+        Some(node) => node.conns.iter().filter(|conn| conn.node_id == b).cloned().collect(),
+        None => vec![],
+    }
+}
+
+/// The `n` worst live links by the given metric, only the returned links are cloned
+pub fn worst_links(nodes: &NodeMap, n: usize, by_metric: LinkMetric) -> Vec<Link> {
+    let mut links: Vec<(u64, NodeId, &NodeConnectionData)> = nodes
+        .values()
+        .flat_map(|node| connected(node).map(move |conn| (node.id, conn)))
+        .map(|(src, conn)| {
+            let badness = match by_metric {
+                LinkMetric::Latency => conn.metric.latency as u64,
+                LinkMetric::Loss => conn.metric.loss_percent as u64,
+                LinkMetric::Bandwidth => u32::MAX as u64 - conn.metric.bandwidth as u64,
+            };
+            (badness, src, conn)
+        })
+        .collect();
+    links.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.id.cmp(&b.2.id)));
+    links.into_iter().take(n).map(|(_, src, conn)| Link { src, conn: conn.clone() }).collect()
+}
+
+/// Lowest total latency path from `a` to `b` over live links, including both ends
+pub fn shortest_path(nodes: &NodeMap, a: NodeId, b: NodeId) -> Option<Vec<NodeId>> {
+    if !nodes.contains_key(&a) || !nodes.contains_key(&b) {
+        return None;
+    }
+    let mut dist = HashMap::<NodeId, u64>::new();
+    let mut prev = HashMap::<NodeId, NodeId>::new();
+    let mut heap = BinaryHeap::new();
+    dist.insert(a, 0);
+    heap.push(Reverse((0u64, a)));
+    while let Some(Reverse((cost, current))) = heap.pop() {
+        if current == b {
+            break;
+        }
+        if dist.get(&current).is_some_and(|best| cost > *best) {
+            continue;
+        }
+        let node = match nodes.get(&current) {
+            Some(node) => node,
+            None => continue,
+        };
+        for conn in connected(node) {
+            // a zero latency link still counts as one hop
+            let next_cost = cost + conn.metric.latency.max(1) as u64;
+            let improved = match dist.get(&conn.node_id) {
+                Some(best) => next_cost < *best,
+                None => true,
+            };
+            if improved {
+                dist.insert(conn.node_id, next_cost);
+                prev.insert(conn.node_id, current);
+                heap.push(Reverse((next_cost, conn.node_id)));
+            }
+        }
+    }
+    if !dist.contains_key(&b) {
+        return None;
+    }
+    let mut path = vec![b];
+    let mut current = b;
+    while current != a {
+        current = *prev.get(&current)?;
+        path.push(current);
+    }
+    path.reverse();
+    Some(path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::identity::ConnectionMetric;
+
+    use super::*;
+
+    fn link(dest: NodeId, latency: u16, loss_percent: u32, status: ConnectionStatus) -> NodeConnectionData {
+        NodeConnectionData {
+            id: dest as u64,
+            node_id: dest,
+            protocol: 1,
+            addr: String::from("127.0.0.1"),
+            metric: ConnectionMetric {
+                latency,
+                bandwidth: 1000 - latency as u32,
+                loss_percent,
+            },
+            status,
+            last_updated_at: 0,
+            direction: 0,
+        }
+    }
+
+    /// 1 -10- 2 -10- 3, 1 -50- 3, 3 -x- 4 (disconnected)
+    fn graph() -> NodeMap {
+        let mut nodes = NodeMap::new();
+        let mut add = |id: NodeId, conns: Vec<NodeConnectionData>| {
+            let mut node = NodeData::new(id, String::from("127.0.0.1"), 0);
+            node.conns = conns.into();
+            nodes.insert(id, Arc::new(node));
+        };
+        add(1, vec![link(2, 10, 0, ConnectionStatus::CONNECTED), link(3, 50, 5, ConnectionStatus::CONNECTED)]);
+        add(2, vec![link(1, 10, 0, ConnectionStatus::CONNECTED), link(3, 10, 1, ConnectionStatus::CONNECTED)]);
+        add(
+            3,
+            vec![
+                link(2, 10, 1, ConnectionStatus::CONNECTED),
+                link(1, 50, 5, ConnectionStatus::CONNECTED),
+                link(4, 1, 90, ConnectionStatus::DISCONNECTED),
+            ],
+        );
+        add(4, vec![]);
+        nodes
+    }
+
+    #[test]
+    fn should_list_neighbors_and_edges() {
+        let nodes = graph();
+
+        assert_eq!(neighbors(&nodes, 3), vec![1, 2]);
+        assert_eq!(neighbors(&nodes, 5), Vec::<NodeId>::new());
+        assert_eq!(edge(&nodes, 1, 3).len(), 1);
+        assert_eq!(edge(&nodes, 1, 4).len(), 0);
+    }
+
+    #[test]
+    fn should_find_worst_live_links() {
+        let nodes = graph();
+
+        let by_latency: Vec<(NodeId, NodeId)> = worst_links(&nodes, 2, LinkMetric::Latency).into_iter().map(|link| (link.src, link.conn.node_id)).collect();
+        assert_eq!(by_latency, vec![(1, 3), (3, 1)]);
+
+        let by_loss = worst_links(&nodes, 10, LinkMetric::Loss);
+        assert_eq!(by_loss.len(), 6);
+        assert_eq!(by_loss[0].conn.metric.loss_percent, 5);
+    }
+
+    #[test]
+    fn should_find_lowest_latency_path() {
+        let nodes = graph();
+
+        assert_eq!(shortest_path(&nodes, 1, 3), Some(vec![1, 2, 3]));
+        assert_eq!(shortest_path(&nodes, 1, 1), Some(vec![1]));
+        assert_eq!(shortest_path(&nodes, 1, 4), None);
+        assert_eq!(shortest_path(&nodes, 1, 5), None);
+    }
+}
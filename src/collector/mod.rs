@@ -1,13 +1,35 @@
 mod controller;
+mod events;
+mod export;
+mod grafana;
+mod graph;
+mod query;
+mod region;
+mod samples;
+mod selector;
+mod sink;
 mod storage;
 
+use atm0s_sdn_identity::NodeId;
 pub use controller::SdnMonitorController;
+pub use events::{AlertKind, AlertThresholds, TimedTopologyEvent, TopologyAlert, TopologyEvent, TopologySubscription, MAX_EVENT_HISTORY};
+pub use export::{ExportFormat, ExportScope};
+pub use grafana::{GrafanaAnnotation, GrafanaAnnotationRequest, GrafanaQueryRequest, GrafanaQueryTarget, GrafanaRange, GrafanaSearchRequest, GrafanaSeries, GrafanaTarget, GRAFANA_SEARCH_LIMIT};
+pub use graph::{Link, LinkMetric};
 use poem::{
     get, handler,
     http::StatusCode,
-    web::{Data, Json, Path},
-    EndpointExt, Response, Route,
+    post,
+    web::{Data, Json, Path, Query},
+    Body, EndpointExt, Response, Route,
 };
+pub use query::{NodeListPage, NodeListQuery, NodeSortKey};
+pub use region::{InterRegionLink, RegionDetail, RegionHealth, RegionSummary};
+pub use samples::{ConnectionSample, ConnectionSamples, SampleFilter, SampleFormat, MAX_CONNECTION_SAMPLES};
+pub use selector::LabelSelector;
+pub use sink::{node_points, InfluxHttpSink, InfluxUdpSink, MetricPoint, MetricsSink, SinkTrigger, StatsdSink, StatsdTagStyle, DEFAULT_MAX_DATAGRAM_BYTES};
+#[cfg(feature = "otlp")]
+pub use sink::{OtlpSink, OTLP_LABEL_PREFIX};
 
 #[cfg(not(feature = "embed"))]
 use poem::endpoint::StaticFilesEndpoint;
@@ -17,16 +39,60 @@ use poem::endpoint::{EmbeddedFileEndpoint, EmbeddedFilesEndpoint};
 
 use rust_embed::RustEmbed;
 use serde::{Deserialize, Serialize};
-pub use storage::{NodeConnectionData, NodeData};
+#[cfg(not(feature = "testing"))]
+pub(crate) use storage::NodeConnectionStorage;
+/// Store behind the controller, public with the `testing` feature to benchmark writes against it
+#[cfg(feature = "testing")]
+pub use storage::NodeConnectionStorage;
+pub use storage::{HostStatsSample, NodeAppMetrics, NodeConnectionData, NodeConnections, NodeData, NodeDetail};
 
 #[cfg(feature = "embed")]
 #[derive(RustEmbed)]
 #[folder = "public"]
 pub struct Files;
 
+use crate::util::now_ms;
+
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct NetworkGraphNode {
     pub nodes: Vec<NodeData>,
+    pub total: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default, Deserialize)]
+pub struct NodesQuery {
+    pub selector: Option<String>,
+    pub online: Option<bool>,
+    pub min_id: Option<u32>,
+    pub max_id: Option<u32>,
+    pub has_disconnected: Option<bool>,
+    pub sort: Option<NodeSortKey>,
+    pub order: Option<String>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    pub conns: Option<bool>,
+}
+
+impl NodesQuery {
+    pub fn to_list_query(self) -> Result<NodeListQuery, String> {
+        let descending = match self.order.as_deref() {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(order) => return Err(format!("invalid order {}, expected asc or desc", order)),
+        };
+        Ok(NodeListQuery {
+            selector: LabelSelector::parse(self.selector.as_deref().unwrap_or(""))?,
+            online_only: self.online.unwrap_or(false),
+            min_id: self.min_id,
+            max_id: self.max_id,
+            has_disconnected: self.has_disconnected.unwrap_or(false),
+            sort: self.sort.unwrap_or_default(),
+            descending,
+            offset: self.offset.unwrap_or(0),
+            limit: self.limit,
+            omit_conns: !self.conns.unwrap_or(true),
+        })
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -35,15 +101,23 @@ pub struct CountResponse {
 }
 
 #[handler]
-fn fetch_all_nodes(Data(controller): Data<&SdnMonitorController>) -> Json<NetworkGraphNode> {
-    let nodes = controller.get_nodes();
-    let data = NetworkGraphNode { nodes };
-    Json(data)
+fn fetch_all_nodes(Query(query): Query<NodesQuery>, Data(controller): Data<&SdnMonitorController>) -> Response {
+    let query = match query.to_list_query() {
+        Ok(query) => query,
+        Err(msg) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(serde_json::to_string(&serde_json::json!({ "msg": msg })).unwrap())
+        }
+    };
+    let page = controller.query_nodes(&query, now_ms());
+    let data = NetworkGraphNode { nodes: page.nodes, total: page.total };
+    Response::builder().status(StatusCode::OK).content_type("application/json").body(serde_json::to_string(&data).unwrap())
 }
 
 #[handler]
 fn get_node(Path(id): Path<u32>, Data(controller): Data<&SdnMonitorController>) -> Response {
-    match controller.get_node(id) {
+    match controller.get_node_detail(id) {
         Some(node) => Response::builder().status(StatusCode::OK).body(serde_json::to_string(&node).unwrap()),
         None => Response::builder()
             .status(StatusCode::NOT_FOUND)
@@ -51,18 +125,189 @@ fn get_node(Path(id): Path<u32>, Data(controller): Data<&SdnMonitorController>)
     }
 }
 
+#[handler]
+fn get_node_metrics(Path(id): Path<u32>, Data(controller): Data<&SdnMonitorController>) -> Response {
+    match controller.get_node_app_metrics(id) {
+        Some(metrics) => Response::builder().status(StatusCode::OK).body(serde_json::to_string(&metrics).unwrap()),
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(serde_json::to_string(&serde_json::json!({ "msg": "Item not found" })).unwrap()),
+    }
+}
+
 #[handler]
 fn count_node(Data(controller): Data<&SdnMonitorController>) -> Json<CountResponse> {
     let count = controller.count_nodes();
     Json(CountResponse { count })
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Default, Deserialize)]
+pub struct ExportQuery {
+    pub format: Option<ExportFormat>,
+    /// Only export the subgraph around this node
+    pub node: Option<NodeId>,
+    /// Hops around `node`, defaults to 1
+    pub depth: Option<usize>,
+}
+
+#[handler]
+fn export_topology(Query(query): Query<ExportQuery>, Data(controller): Data<&SdnMonitorController>) -> Response {
+    let format = query.format.unwrap_or_default();
+    let scope = match query.node {
+        Some(node_id) if controller.get_node_shared(node_id).is_none() => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(serde_json::to_string(&serde_json::json!({ "msg": "Item not found" })).unwrap())
+        }
+        Some(node_id) => Some(ExportScope {
+            node_id,
+            depth: query.depth.unwrap_or(1),
+        }),
+        None => None,
+    };
+    Response::builder().status(StatusCode::OK).content_type(format.content_type()).body(controller.export(format, scope))
+}
+
+/// Rows rendered per chunk of a streamed connection export
+pub const EXPORT_ROWS_PER_CHUNK: usize = 512;
+
+#[derive(Debug, PartialEq, Eq, Clone, Default, Deserialize)]
+pub struct ConnectionExportQuery {
+    pub format: Option<SampleFormat>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    /// Comma separated node ids, matching either end of a connection
+    pub nodes: Option<String>,
+}
+
+impl ConnectionExportQuery {
+    pub fn to_filter(&self) -> Result<SampleFilter, String> {
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from > to {
+                return Err(format!("invalid range, from {} is after to {}", from, to));
+            }
+        }
+        let nodes = match self.nodes.as_deref() {
+            Some(nodes) => Some(
+                nodes
+                    .split(',')
+                    .map(|id| id.trim().parse::<u32>().map_err(|_| format!("invalid node id {}", id)))
+                    .collect::<Result<_, _>>()?,
+            ),
+            None => None,
+        };
+        Ok(SampleFilter { from: self.from, to: self.to, nodes })
+    }
+}
+
+/// Streams the recorded connection samples from a snapshot, rows are rendered as the client reads them
+#[handler]
+fn export_connections(Query(query): Query<ConnectionExportQuery>, Data(controller): Data<&SdnMonitorController>) -> Response {
+    let filter = match query.to_filter() {
+        Ok(filter) => filter,
+        Err(msg) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(serde_json::to_string(&serde_json::json!({ "msg": msg })).unwrap())
+        }
+    };
+    let format = query.format.unwrap_or_default();
+    let chunks = format.render_chunks(controller.connection_samples(filter), EXPORT_ROWS_PER_CHUNK);
+    Response::builder()
+        .status(StatusCode::OK)
+        .content_type(format.content_type())
+        .body(Body::from_bytes_stream(futures_util::stream::iter(chunks.map(Ok::<_, std::io::Error>))))
+}
+
+/// Datasource health check of the Grafana SimpleJSON contract
+#[handler]
+fn grafana_health() -> &'static str {
+    "OK"
+}
+
+#[handler]
+fn grafana_search(Json(request): Json<GrafanaSearchRequest>, Data(controller): Data<&SdnMonitorController>) -> Json<Vec<String>> {
+    Json(grafana::search(controller, &request.target))
+}
+
+#[handler]
+fn grafana_query(Json(request): Json<GrafanaQueryRequest>, Data(controller): Data<&SdnMonitorController>) -> Response {
+    match grafana::query(controller, &request) {
+        Ok(series) => Response::builder()
+            .status(StatusCode::OK)
+            .content_type("application/json")
+            .body(serde_json::to_string(&series).unwrap()),
+        Err(msg) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(serde_json::to_string(&serde_json::json!({ "msg": msg })).unwrap()),
+    }
+}
+
+#[handler]
+fn grafana_annotations(Json(request): Json<GrafanaAnnotationRequest>, Data(controller): Data<&SdnMonitorController>) -> Response {
+    match grafana::annotations(controller, &request) {
+        Ok(annotations) => Response::builder()
+            .status(StatusCode::OK)
+            .content_type("application/json")
+            .body(serde_json::to_string(&annotations).unwrap()),
+        Err(msg) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(serde_json::to_string(&serde_json::json!({ "msg": msg })).unwrap()),
+    }
+}
+
+/// Region details fetched longer ago than this, on the master clock, are fetched again from the aggregator
+pub const REGION_DETAIL_TTL_MS: u64 = 10000;
+
+#[handler]
+fn fetch_regions(Data(controller): Data<&SdnMonitorController>) -> Json<Vec<RegionSummary>> {
+    Json(controller.get_regions())
+}
+
+#[handler]
+fn get_region(Path(region): Path<String>, Data(controller): Data<&SdnMonitorController>) -> Response {
+    match controller.get_region(&region) {
+        Some(summary) => Response::builder().status(StatusCode::OK).body(serde_json::to_string(&summary).unwrap()),
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(serde_json::to_string(&serde_json::json!({ "msg": "Item not found" })).unwrap()),
+    }
+}
+
+/// Drill-down into a region, proxied to its aggregator. The master refreshes a missing or stale copy, the call
+/// answers 202 until the first one arrives and a stale copy is still returned meanwhile.
+#[handler]
+fn get_region_nodes(Path(region): Path<String>, Data(controller): Data<&SdnMonitorController>) -> Response {
+    if controller.get_region(&region).is_none() {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(serde_json::to_string(&serde_json::json!({ "msg": "Item not found" })).unwrap());
+    }
+    controller.request_region_detail(&region);
+    match controller.get_region_detail(&region) {
+        Some(detail) => Response::builder().status(StatusCode::OK).body(serde_json::to_string(&detail).unwrap()),
+        None => Response::builder()
+            .status(StatusCode::ACCEPTED)
+            .body(serde_json::to_string(&serde_json::json!({ "msg": "Region detail requested" })).unwrap()),
+    }
+}
+
 pub fn build_visualization_route() -> (Route, SdnMonitorController) {
     let controller = SdnMonitorController::new();
     let route = Route::new()
         .at("/api/nodes", get(fetch_all_nodes).data(controller.clone()))
         .at("/api/nodes/:id", get(get_node).data(controller.clone()))
-        .at("/api/nodes/count", get(count_node).data(controller.clone()));
+        .at("/api/nodes/:id/metrics", get(get_node_metrics).data(controller.clone()))
+        .at("/api/nodes/count", get(count_node).data(controller.clone()))
+        .at("/api/export", get(export_topology).data(controller.clone()))
+        .at("/api/export/connections", get(export_connections).data(controller.clone()))
+        .at("/api/grafana", get(grafana_health))
+        .at("/api/grafana/search", post(grafana_search).data(controller.clone()))
+        .at("/api/grafana/query", post(grafana_query).data(controller.clone()))
+        .at("/api/grafana/annotations", post(grafana_annotations).data(controller.clone()))
+        .at("/api/regions", get(fetch_regions).data(controller.clone()))
+        .at("/api/regions/:region", get(get_region).data(controller.clone()))
+        .at("/api/regions/:region/nodes", get(get_region_nodes).data(controller.clone()));
 
     #[cfg(not(feature = "embed"))]
     let route = route.nest("/", StaticFilesEndpoint::new("./public/").show_files_listing());
@@ -74,3 +319,57 @@ pub fn build_visualization_route() -> (Route, SdnMonitorController) {
 
     (route, controller)
 }
+
+#[cfg(test)]
+mod test {
+    use poem::{http::Uri, Endpoint, Request};
+
+    use crate::identity::{ConnectionMetric, ConnectionStatus};
+
+    use super::*;
+
+    async fn get(route: &impl Endpoint, path: &str) -> (StatusCode, Option<String>, String) {
+        let response = route.get_response(Request::builder().uri(path.parse::<Uri>().expect("should parse uri")).finish()).await;
+        let status = response.status();
+        let content_type = response.content_type().map(|content_type| content_type.to_string());
+        let body = response.into_body().into_string().await.expect("should read body");
+        (status, content_type, body)
+    }
+
+    #[tokio::test]
+    async fn connection_export_should_stream_the_filtered_samples() {
+        let (route, mut controller) = build_visualization_route();
+        let conn = |dest: NodeId, ts: u64| NodeConnectionData {
+            id: dest as u64,
+            node_id: dest,
+            protocol: 1,
+            addr: String::new(),
+            metric: ConnectionMetric {
+                latency: 10,
+                loss_percent: 2,
+                bandwidth: 100,
+            },
+            status: ConnectionStatus::CONNECTED,
+            last_updated_at: ts,
+            direction: 0,
+        };
+        controller.upsert_node(1, String::from("/ip4/127.0.0.1"), 0);
+        controller.update_node_conns(1, vec![conn(2, 100), conn(3, 100)]);
+        controller.update_node_conns(1, vec![conn(2, 200)]);
+
+        let (status, content_type, body) = get(&route, "/api/export/connections?nodes=2&from=150").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.as_deref(), Some("text/csv"));
+        assert_eq!(body, "timestamp,src,dst,protocol,direction,latency,bandwidth,loss,status\n200,1,2,1,0,10,100,2,connected\n");
+
+        let (status, content_type, body) = get(&route, "/api/export/connections?format=ndjson&nodes=3").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.as_deref(), Some("application/x-ndjson"));
+        assert_eq!(body.lines().count(), 1);
+
+        let (status, _, _) = get(&route, "/api/export/connections?from=200&to=100").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _, _) = get(&route, "/api/export/connections?nodes=x").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,221 @@
+use atm0s_sdn_identity::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::identity::{ConnectionStatus, CONNECTION_TIMEOUT_MS};
+
+use super::{
+    selector::LabelSelector,
+    storage::{NodeConnections, NodeData},
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeSortKey {
+    #[default]
+    Id,
+    LastPing,
+    Degree,
+    WorstLoss,
+}
+
+/// Filters, sorting and pagination applied when listing nodes.
+#[derive(Debug, Clone, Default)]
+pub struct NodeListQuery {
+    pub selector: LabelSelector,
+    /// Only nodes which pinged within `CONNECTION_TIMEOUT_MS`
+    pub online_only: bool,
+    pub min_id: Option<NodeId>,
+    pub max_id: Option<NodeId>,
+    /// Only nodes having at least one disconnected connection
+    pub has_disconnected: bool,
+    pub sort: NodeSortKey,
+    pub descending: bool,
+    pub offset: usize,
+    pub limit: Option<usize>,
+    /// Return nodes without their `conns` list
+    pub omit_conns: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct NodeListPage {
+    /// Number of nodes matching the filters, before pagination
+    pub total: usize,
+    pub nodes: Vec<NodeData>,
+}
+
+impl NodeListQuery {
+    pub fn matches(&self, node: &NodeData, now_ms: u64) -> bool {
+        if self.online_only && !is_online(node, now_ms) {
+            return false;
+        }
+        if self.min_id.is_some_and(|min_id| node.id < min_id) || self.max_id.is_some_and(|max_id| node.id > max_id) {
+            return false;
+        }
+        if self.has_disconnected && !node.conns.iter().any(|conn| conn.status == ConnectionStatus::DISCONNECTED) {
+            return false;
+        }
+        self.selector.matches(&node.labels)
+    }
+
+    pub fn sort_value(&self, node: &NodeData) -> u64 {
+        match self.sort {
+            NodeSortKey::Id => node.id as u64,
+            NodeSortKey::LastPing => node.last_ping_ts,
+            NodeSortKey::Degree => degree(node) as u64,
+            NodeSortKey::WorstLoss => worst_loss(node) as u64,
+        }
+    }
+
+    /// Filter, sort and paginate nodes, only the returned page is cloned
+    pub fn apply<'a, I: Iterator<Item = &'a NodeData>>(&self, nodes: I, now_ms: u64) -> NodeListPage {
+        let mut matched: Vec<(u64, &NodeData)> = nodes.filter(|node| self.matches(node, now_ms)).map(|node| (self.sort_value(node), node)).collect();
+        matched.sort_by(|(a_value, a), (b_value, b)| {
+            let ordering = a_value.cmp(b_value).then(a.id.cmp(&b.id));
+            if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        let total = matched.len();
+        let nodes = matched
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .map(|(_, node)| {
+                if self.omit_conns {
+                    NodeData {
+                        conns: NodeConnections::new(),
+                        ..node.clone()
+                    }
+                } else {
+                    node.clone()
+                }
+            })
+            .collect();
+        NodeListPage { total, nodes }
+    }
+}
+
+pub fn is_online(node: &NodeData, now_ms: u64) -> bool {
+    now_ms.saturating_sub(node.last_ping_ts) <= CONNECTION_TIMEOUT_MS
+}
+
+/// Number of connected connections of the node
+pub fn degree(node: &NodeData) -> usize {
+    node.conns.iter().filter(|conn| conn.status == ConnectionStatus::CONNECTED).count()
+}
+
+pub fn worst_loss(node: &NodeData) -> u32 {
+    node.conns.iter().map(|conn| conn.metric.loss_percent).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{collector::NodeConnectionData, identity::ConnectionMetric};
+
+    use super::*;
+
+    fn node(id: NodeId, last_ping_ts: u64, losses: &[(u32, ConnectionStatus)]) -> NodeData {
+        let mut node = NodeData::new(id, String::from("127.0.0.1"), last_ping_ts);
+        for (index, (loss_percent, status)) in losses.iter().enumerate() {
+            node.conns.insert(NodeConnectionData {
+                id: index as u64,
+                node_id: 100 + index as NodeId,
+                protocol: 1,
+                addr: String::from("127.0.0.1"),
+                metric: ConnectionMetric {
+                    latency: 1,
+                    bandwidth: 100,
+                    loss_percent: *loss_percent,
+                },
+                status: status.clone(),
+                last_updated_at: last_ping_ts,
+                direction: 0,
+            });
+        }
+        node
+    }
+
+    fn ids(page: &NodeListPage) -> Vec<NodeId> {
+        page.nodes.iter().map(|node| node.id).collect()
+    }
+
+    #[test]
+    fn should_paginate_sorted_by_id_by_default() {
+        let nodes = [node(3, 0, &[]), node(1, 0, &[]), node(2, 0, &[])];
+        let query = NodeListQuery {
+            offset: 1,
+            limit: Some(1),
+            ..Default::default()
+        };
+
+        let page = query.apply(nodes.iter(), 0);
+        assert_eq!(page.total, 3);
+        assert_eq!(ids(&page), vec![2]);
+    }
+
+    #[test]
+    fn should_sort_by_degree_and_worst_loss() {
+        let nodes = [
+            node(1, 0, &[(5, ConnectionStatus::CONNECTED)]),
+            node(2, 0, &[(1, ConnectionStatus::CONNECTED), (2, ConnectionStatus::CONNECTED)]),
+            node(3, 0, &[(50, ConnectionStatus::DISCONNECTED)]),
+        ];
+
+        let by_degree = NodeListQuery {
+            sort: NodeSortKey::Degree,
+            descending: true,
+            ..Default::default()
+        };
+        assert_eq!(ids(&by_degree.apply(nodes.iter(), 0)), vec![2, 1, 3]);
+
+        let by_loss = NodeListQuery {
+            sort: NodeSortKey::WorstLoss,
+            descending: true,
+            ..Default::default()
+        };
+        assert_eq!(ids(&by_loss.apply(nodes.iter(), 0)), vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn should_filter_online_id_range_and_disconnected() {
+        let nodes = [
+            node(1, 0, &[(0, ConnectionStatus::DISCONNECTED)]),
+            node(2, CONNECTION_TIMEOUT_MS, &[(0, ConnectionStatus::CONNECTED)]),
+            node(3, CONNECTION_TIMEOUT_MS, &[(0, ConnectionStatus::DISCONNECTED)]),
+        ];
+        let now_ms = CONNECTION_TIMEOUT_MS + 1;
+
+        let online = NodeListQuery {
+            online_only: true,
+            ..Default::default()
+        };
+        assert_eq!(ids(&online.apply(nodes.iter(), now_ms)), vec![2, 3]);
+
+        let range = NodeListQuery {
+            min_id: Some(2),
+            max_id: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(ids(&range.apply(nodes.iter(), now_ms)), vec![2]);
+
+        let disconnected = NodeListQuery {
+            has_disconnected: true,
+            ..Default::default()
+        };
+        assert_eq!(ids(&disconnected.apply(nodes.iter(), now_ms)), vec![1, 3]);
+    }
+
+    #[test]
+    fn should_omit_conns_when_requested() {
+        let nodes = [node(1, 0, &[(0, ConnectionStatus::CONNECTED)])];
+        let query = NodeListQuery {
+            omit_conns: true,
+            ..Default::default()
+        };
+
+        let page = query.apply(nodes.iter(), 0);
+        assert_eq!(page.nodes[0].conns.len(), 0);
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+
+use atm0s_sdn_identity::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::identity::{ConnectionMetric, ConnectionStatus};
+
+use super::storage::NodeData;
+
+/// Health counters of the nodes an aggregator covers
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct RegionHealth {
+    pub nodes: u32,
+    pub online: u32,
+    pub links: u32,
+    pub links_down: u32,
+    /// Over connected links only
+    pub avg_latency_ms: u32,
+    pub max_loss_percent: u32,
+}
+
+/// A connection from a node of the region to a node outside of it
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct InterRegionLink {
+    pub node_id: NodeId,
+    pub conn_id: u64,
+    pub dest: NodeId,
+    pub status: ConnectionStatus,
+    pub metric: ConnectionMetric,
+}
+
+/// Pre-aggregated state an aggregator master sends upstream. Large summaries are split in parts sharing the same
+/// `ts`, each carrying a share of the links.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct RegionSummary {
+    pub region: String,
+    pub aggregator: NodeId,
+    pub ts: u64,
+    pub health: RegionHealth,
+    pub links: Vec<InterRegionLink>,
+}
+
+/// Full node state of a region, fetched from its aggregator on demand and split in parts like `RegionSummary`
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct RegionDetail {
+    pub region: String,
+    pub ts: u64,
+    pub nodes: Vec<NodeData>,
+}
+
+impl RegionSummary {
+    /// Keep the newest summary, parts of the same one are appended without the links already known
+    pub fn merge(&mut self, part: RegionSummary) {
+        if part.ts > self.ts {
+            *self = part;
+        } else if part.ts == self.ts {
+            let mut known: HashSet<(NodeId, u64)> = self.links.iter().map(|link| (link.node_id, link.conn_id)).collect();
+            self.links.extend(part.links.into_iter().filter(|link| known.insert((link.node_id, link.conn_id))));
+        }
+    }
+}
+
+impl RegionDetail {
+    /// Same as `RegionSummary::merge`, nodes already known are skipped
+    pub fn merge(&mut self, part: RegionDetail) {
+        if part.ts > self.ts {
+            *self = part;
+        } else if part.ts == self.ts {
+            let mut known: HashSet<NodeId> = self.nodes.iter().map(|node| node.id).collect();
+            self.nodes.extend(part.nodes.into_iter().filter(|node| known.insert(node.id)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn summary(ts: u64, dests: &[NodeId]) -> RegionSummary {
+        RegionSummary {
+            region: String::from("eu"),
+            aggregator: 1,
+            ts,
+            health: RegionHealth::default(),
+            links: dests
+                .iter()
+                .map(|dest| InterRegionLink {
+                    node_id: 1,
+                    conn_id: *dest as u64,
+                    dest: *dest,
+                    status: ConnectionStatus::CONNECTED,
+                    metric: ConnectionMetric {
+                        latency: 10,
+                        bandwidth: 100,
+                        loss_percent: 0,
+                    },
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn summary_parts_should_append_and_newer_replace() {
+        let mut current = summary(100, &[2]);
+        current.merge(summary(100, &[3]));
+        assert_eq!(current, summary(100, &[2, 3]));
+
+        current.merge(summary(50, &[4]));
+        assert_eq!(current.links.len(), 2);
+
+        current.merge(summary(200, &[5]));
+        assert_eq!(current, summary(200, &[5]));
+    }
+
+    #[test]
+    fn parts_received_twice_should_not_duplicate_links_or_nodes() {
+        let mut current = summary(100, &[2, 3]);
+        current.merge(summary(100, &[3, 4]));
+        assert_eq!(current, summary(100, &[2, 3, 4]));
+
+        let detail = |ids: &[NodeId]| RegionDetail {
+            region: String::from("eu"),
+            ts: 100,
+            nodes: ids.iter().map(|id| NodeData::new(*id, String::new(), 100)).collect(),
+        };
+        let mut current = detail(&[1, 2]);
+        current.merge(detail(&[2, 3]));
+        assert_eq!(current, detail(&[1, 2, 3]));
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fmt::Write,
+    sync::Arc,
+};
+
+use atm0s_sdn_identity::NodeId;
+use serde::{Deserialize, Serialize};
+
+use super::export::status_str;
+use crate::identity::ConnectionStatus;
+
+/// Connection samples kept per reporting node, the oldest are dropped first
+pub const MAX_CONNECTION_SAMPLES: usize = 4096;
+
+/// One connection report as received from the agent of `src`
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ConnectionSample {
+    pub ts: u64,
+    pub src: NodeId,
+    pub dst: NodeId,
+    pub conn_id: u64,
+    pub protocol: u8,
+    pub direction: u8,
+    pub latency: u16,
+    pub bandwidth: u32,
+    pub loss: u32,
+    pub status: ConnectionStatus,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+impl SampleFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            SampleFormat::Csv => "text/csv",
+            SampleFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    fn header(&self) -> Option<&'static str> {
+        match self {
+            SampleFormat::Csv => Some("timestamp,src,dst,protocol,direction,latency,bandwidth,loss,status\n"),
+            SampleFormat::Ndjson => None,
+        }
+    }
+
+    fn write_row(&self, out: &mut String, sample: &ConnectionSample) {
+        let status = status_str(&sample.status);
+        let _ = match self {
+            SampleFormat::Csv => writeln!(
+                out,
+                "{},{},{},{},{},{},{},{},{}",
+                sample.ts, sample.src, sample.dst, sample.protocol, sample.direction, sample.latency, sample.bandwidth, sample.loss, status
+            ),
+            SampleFormat::Ndjson => writeln!(
+                out,
+                "{{\"timestamp\":{},\"src\":{},\"dst\":{},\"protocol\":{},\"direction\":{},\"latency\":{},\"bandwidth\":{},\"loss\":{},\"status\":\"{}\"}}",
+                sample.ts, sample.src, sample.dst, sample.protocol, sample.direction, sample.latency, sample.bandwidth, sample.loss, status
+            ),
+        };
+    }
+
+    /// Render samples lazily, `rows` per chunk, the CSV header goes with the first chunk
+    pub fn render_chunks(self, mut samples: ConnectionSamples, rows: usize) -> impl Iterator<Item = String> + Send + 'static {
+        let mut header = self.header();
+        std::iter::from_fn(move || {
+            let mut chunk = String::from(header.take().unwrap_or_default());
+            for sample in samples.by_ref().take(rows) {
+                self.write_row(&mut chunk, &sample);
+            }
+            (!chunk.is_empty()).then_some(chunk)
+        })
+    }
+}
+
+/// Inclusive time range, a sample matches `nodes` if either end is in it
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SampleFilter {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub nodes: Option<BTreeSet<NodeId>>,
+}
+
+impl SampleFilter {
+    pub fn matches(&self, sample: &ConnectionSample) -> bool {
+        self.from.iter().all(|from| sample.ts >= *from) && self.to.iter().all(|to| sample.ts <= *to) && self.nodes.iter().all(|nodes| nodes.contains(&sample.src) || nodes.contains(&sample.dst))
+    }
+}
+
+/// Iterates the samples of a storage snapshot without copying them, node by node in id order
+pub struct ConnectionSamples {
+    series: Vec<Arc<VecDeque<ConnectionSample>>>,
+    filter: SampleFilter,
+    series_index: usize,
+    sample_index: usize,
+}
+
+impl ConnectionSamples {
+    pub(crate) fn new(series: Vec<Arc<VecDeque<ConnectionSample>>>, filter: SampleFilter) -> Self {
+        Self {
+            series,
+            filter,
+            series_index: 0,
+            sample_index: 0,
+        }
+    }
+}
+
+impl Iterator for ConnectionSamples {
+    type Item = ConnectionSample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(series) = self.series.get(self.series_index) {
+            match series.get(self.sample_index) {
+                Some(sample) => {
+                    self.sample_index += 1;
+                    if self.filter.matches(sample) {
+                        return Some(sample.clone());
+                    }
+                }
+                None => {
+                    self.series_index += 1;
+                    self.sample_index = 0;
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample(ts: u64, src: NodeId, dst: NodeId) -> ConnectionSample {
+        ConnectionSample {
+            ts,
+            src,
+            dst,
+            conn_id: dst as u64,
+            protocol: 1,
+            direction: 0,
+            latency: 10,
+            bandwidth: 100,
+            loss: 2,
+            status: ConnectionStatus::CONNECTED,
+        }
+    }
+
+    fn samples(filter: SampleFilter) -> ConnectionSamples {
+        let series = vec![
+            Arc::new(VecDeque::from([sample(100, 1, 2), sample(200, 1, 3)])),
+            Arc::new(VecDeque::new()),
+            Arc::new(VecDeque::from([sample(150, 3, 1), sample(300, 3, 4)])),
+        ];
+        ConnectionSamples::new(series, filter)
+    }
+
+    #[test]
+    fn filter_should_match_time_range_and_either_end() {
+        let filter = SampleFilter {
+            from: Some(150),
+            to: Some(300),
+            nodes: Some(BTreeSet::from([3])),
+        };
+        let found: Vec<(u64, NodeId)> = samples(filter).map(|sample| (sample.ts, sample.src)).collect();
+        assert_eq!(found, vec![(200, 1), (150, 3), (300, 3)]);
+        assert_eq!(samples(SampleFilter::default()).count(), 4);
+    }
+
+    #[test]
+    fn csv_should_stream_header_then_rows_in_chunks() {
+        let chunks: Vec<String> = SampleFormat::Csv.render_chunks(samples(SampleFilter::default()), 3).collect();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            chunks[0],
+            "timestamp,src,dst,protocol,direction,latency,bandwidth,loss,status\n100,1,2,1,0,10,100,2,connected\n200,1,3,1,0,10,100,2,connected\n150,3,1,1,0,10,100,2,connected\n"
+        );
+        assert_eq!(chunks[1], "300,3,4,1,0,10,100,2,connected\n");
+
+        let empty = SampleFilter {
+            from: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(SampleFormat::Csv.render_chunks(samples(empty.clone()), 3).count(), 1);
+        assert_eq!(SampleFormat::Ndjson.render_chunks(samples(empty), 3).count(), 0);
+    }
+
+    #[test]
+    fn ndjson_rows_should_be_json_lines() {
+        let body: String = SampleFormat::Ndjson.render_chunks(samples(SampleFilter::default()), 2).collect();
+        let rows: Vec<serde_json::Value> = body.lines().map(|line| serde_json::from_str(line).expect("should be json")).collect();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[3]["timestamp"], 300);
+        assert_eq!(rows[3]["dst"], 4);
+        assert_eq!(rows[3]["status"], "connected");
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum LabelRequirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+}
+
+/// Label selector in the form `region=eu,role!=gateway,zone`, every requirement must match.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct LabelSelector {
+    requirements: Vec<LabelRequirement>,
+}
+
+impl LabelSelector {
+    pub fn parse(input: &str) -> Result<LabelSelector, String> {
+        let mut requirements = vec![];
+        for part in input.split(',').map(|part| part.trim()).filter(|part| !part.is_empty()) {
+            let requirement = if let Some((key, value)) = part.split_once("!=") {
+                LabelRequirement::NotEquals(parse_key(key)?, value.trim().to_string())
+            } else if let Some((key, value)) = part.split_once('=') {
+                LabelRequirement::Equals(parse_key(key)?, value.trim().to_string())
+            } else {
+                LabelRequirement::Exists(parse_key(part)?)
+            };
+            requirements.push(requirement);
+        }
+        Ok(Self { requirements })
+    }
+
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.requirements.iter().all(|requirement| match requirement {
+            LabelRequirement::Equals(key, value) => labels.get(key) == Some(value),
+            LabelRequirement::NotEquals(key, value) => labels.get(key) != Some(value),
+            LabelRequirement::Exists(key) => labels.contains_key(key),
+        })
+    }
+}
+
+fn parse_key(key: &str) -> Result<String, String> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(String::from("label selector has an empty key"));
+    }
+    Ok(key.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn should_parse_all_requirement_kinds() {
+        let selector = LabelSelector::parse("region=eu, role!=gateway,zone").expect("should parse");
+
+        assert_eq!(
+            selector.requirements,
+            vec![
+                LabelRequirement::Equals("region".to_string(), "eu".to_string()),
+                LabelRequirement::NotEquals("role".to_string(), "gateway".to_string()),
+                LabelRequirement::Exists("zone".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_reject_empty_key() {
+        assert!(LabelSelector::parse("=eu").is_err());
+    }
+
+    #[test]
+    fn should_match_only_when_all_requirements_match() {
+        let selector = LabelSelector::parse("region=eu,role=gateway").expect("should parse");
+
+        assert!(selector.matches(&labels(&[("region", "eu"), ("role", "gateway"), ("zone", "a")])));
+        assert!(!selector.matches(&labels(&[("region", "eu"), ("role", "relay")])));
+        assert!(!selector.matches(&labels(&[("region", "eu")])));
+    }
+
+    #[test]
+    fn should_match_not_equals_and_exists() {
+        let selector = LabelSelector::parse("role!=gateway,zone").expect("should parse");
+
+        assert!(selector.matches(&labels(&[("zone", "a")])));
+        assert!(!selector.matches(&labels(&[("zone", "a"), ("role", "gateway")])));
+        assert!(!selector.matches(&labels(&[("role", "relay")])));
+    }
+
+    #[test]
+    fn empty_selector_should_match_everything() {
+        let selector = LabelSelector::parse("").expect("should parse");
+
+        assert!(selector.matches(&BTreeMap::new()));
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+/// Plain http endpoint, enough for pushing to a local receiver or an agent like Telegraf
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct HttpEndpoint {
+    pub host: String,
+    pub port: u16,
+    /// Path with query, e.g. /api/v2/write?bucket=sdn
+    pub path: String,
+}
+
+impl HttpEndpoint {
+    pub fn parse(endpoint: &str, default_path: &str) -> Result<Self, String> {
+        let rest = endpoint.strip_prefix("http://").ok_or_else(|| format!("invalid endpoint {}, only http:// is supported", endpoint))?;
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(index) if rest[index..].starts_with('/') => (&rest[..index], rest[index..].to_string()),
+            Some(index) => (&rest[..index], format!("{}{}", default_path, &rest[index..])),
+            None => (rest, default_path.to_string()),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse::<u16>().map_err(|_| format!("invalid port in endpoint {}", endpoint))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in endpoint {}", endpoint));
+        }
+        Ok(Self { host: host.to_string(), port, path })
+    }
+
+    pub fn request_head(&self, content_type: &str, content_length: usize, headers: &[(String, String)]) -> String {
+        let mut head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.path, self.host, self.port, content_type, content_length
+        );
+        for (key, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    /// Blocking POST, fails on a non 2xx answer
+    pub fn post(&self, content_type: &str, headers: &[(String, String)], body: &[u8], timeout: Duration) -> io::Result<()> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("can not resolve {}", self.host)))?;
+        let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        stream.write_all(self.request_head(content_type, body.len(), headers).as_bytes())?;
+        stream.write_all(body)?;
+        stream.flush()?;
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        check_status(&response)
+    }
+}
+
+pub(crate) fn check_status(response: &[u8]) -> io::Result<()> {
+    let status_line = response.split(|byte| *byte == b'\n').next().map(String::from_utf8_lossy).unwrap_or_default();
+    match status_line.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok()) {
+        Some(code) if (200..300).contains(&code) => Ok(()),
+        Some(code) => Err(io::Error::other(format!("receiver answered {}", code))),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid receiver response")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn endpoint_should_parse_host_port_and_path() {
+        assert_eq!(
+            HttpEndpoint::parse("http://collector:4318/v1/metrics", "/"),
+            Ok(HttpEndpoint {
+                host: String::from("collector"),
+                port: 4318,
+                path: String::from("/v1/metrics"),
+            })
+        );
+        assert_eq!(
+            HttpEndpoint::parse("http://collector", "/write").map(|endpoint| (endpoint.port, endpoint.path)),
+            Ok((80, String::from("/write")))
+        );
+        assert_eq!(
+            HttpEndpoint::parse("http://influx:8086?db=sdn", "/write").map(|endpoint| endpoint.path),
+            Ok(String::from("/write?db=sdn"))
+        );
+        assert!(HttpEndpoint::parse("https://collector:4318/v1/metrics", "/").is_err());
+        assert!(HttpEndpoint::parse("http://collector:port/v1/metrics", "/").is_err());
+        assert!(HttpEndpoint::parse("http://:80/", "/").is_err());
+    }
+
+    #[test]
+    fn status_should_accept_only_2xx() {
+        assert!(check_status(b"HTTP/1.1 204 No Content\r\n\r\n").is_ok());
+        assert!(check_status(b"HTTP/1.1 400 Bad Request\r\n\r\n").is_err());
+        assert!(check_status(b"").is_err());
+    }
+}
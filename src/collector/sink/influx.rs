@@ -0,0 +1,196 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+use super::{pack_lines, HttpEndpoint, MetricPoint, MetricsSink, DEFAULT_MAX_DATAGRAM_BYTES};
+
+fn escape(value: &str, special: &[char]) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || special.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// `measurement,tag=value field=1i timestamp`, timestamps in nanoseconds
+pub(crate) fn to_line(point: &MetricPoint) -> String {
+    let mut line = escape(point.measurement, &[',', ' ']);
+    for (key, value) in point.tags.iter().filter(|(_, value)| !value.is_empty()) {
+        line.push(',');
+        line.push_str(&escape(key, &[',', '=', ' ']));
+        line.push('=');
+        line.push_str(&escape(value, &[',', '=', ' ']));
+    }
+    let fields: Vec<String> = point.fields.iter().map(|(key, value)| format!("{}={}i", escape(key, &[',', '=', ' ']), value)).collect();
+    format!("{} {} {}", line, fields.join(","), point.ts_ms * 1_000_000)
+}
+
+/// InfluxDB line protocol over UDP, e.g. to the Telegraf socket_listener or the InfluxDB 1.x UDP service
+pub struct InfluxUdpSink {
+    socket: UdpSocket,
+    max_datagram_bytes: usize,
+}
+
+impl InfluxUdpSink {
+    pub fn new<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(Self {
+            socket: connect_udp(addr)?,
+            max_datagram_bytes: DEFAULT_MAX_DATAGRAM_BYTES,
+        })
+    }
+
+    pub fn with_max_datagram_bytes(mut self, max_datagram_bytes: usize) -> Self {
+        self.max_datagram_bytes = max_datagram_bytes;
+        self
+    }
+}
+
+impl MetricsSink for InfluxUdpSink {
+    fn send(&mut self, points: &[MetricPoint]) -> io::Result<()> {
+        let lines: Vec<String> = points.iter().map(to_line).collect();
+        for datagram in pack_lines(&lines, self.max_datagram_bytes) {
+            self.socket.send(&datagram)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bind an ephemeral socket of the same family as `addr` and connect it
+pub(crate) fn connect_udp<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
+    let addr = addr.to_socket_addrs()?.next().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no udp address"))?;
+    let local: SocketAddr = match addr {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse().expect("should parse"),
+        SocketAddr::V6(_) => "[::]:0".parse().expect("should parse"),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(addr)?;
+    Ok(socket)
+}
+
+/// InfluxDB line protocol over HTTP. The endpoint defaults to the 1.x `/write` path, give the full path for
+/// 2.x, e.g. http://127.0.0.1:8086/api/v2/write?org=ops&bucket=sdn
+pub struct InfluxHttpSink {
+    endpoint: HttpEndpoint,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+}
+
+impl InfluxHttpSink {
+    pub fn new(endpoint: &str) -> Result<Self, String> {
+        Ok(Self {
+            endpoint: HttpEndpoint::parse(endpoint, "/write")?,
+            headers: vec![],
+            timeout: Duration::from_millis(5000),
+        })
+    }
+
+    /// Sent as `Authorization: Token <token>`
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.headers.push((String::from("Authorization"), format!("Token {}", token)));
+        self
+    }
+
+    pub fn with_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.timeout = Duration::from_millis(timeout_ms);
+        self
+    }
+}
+
+impl MetricsSink for InfluxHttpSink {
+    fn send(&mut self, points: &[MetricPoint]) -> io::Result<()> {
+        let mut body = String::new();
+        for point in points {
+            body.push_str(&to_line(point));
+            body.push('\n');
+        }
+        self.endpoint.post("text/plain; charset=utf-8", &self.headers, body.as_bytes(), self.timeout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::{super::node_points, super::test::node, *};
+
+    #[test]
+    fn line_should_escape_tags_and_mark_integers() {
+        let point = MetricPoint {
+            measurement: "sdn node",
+            tags: vec![(String::from("zone"), String::from("a=b,c d")), (String::from("empty"), String::new())],
+            fields: vec![("online", 1), ("latency", -2)],
+            ts_ms: 3,
+        };
+        assert_eq!(to_line(&point), "sdn\\ node,zone=a\\=b\\,c\\ d online=1i,latency=-2i 3000000");
+    }
+
+    #[test]
+    fn udp_sink_should_send_lines_to_listener() {
+        let listener = UdpSocket::bind("127.0.0.1:0").expect("should bind");
+        listener.set_read_timeout(Some(Duration::from_secs(5))).expect("should set timeout");
+        let mut sink = InfluxUdpSink::new(listener.local_addr().expect("should have addr")).expect("should create sink");
+        sink.send(&node_points(&node(), 2000)).expect("should send");
+
+        let mut buf = [0; 2048];
+        let len = listener.recv(&mut buf).expect("should receive");
+        let lines: Vec<&str> = std::str::from_utf8(&buf[..len]).expect("should be utf8").lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "sdn_node,node_id=1,region=eu online=1i,connections=1i,dropped_reports=0i 2000000000",
+                "sdn_connection,node_id=1,region=eu,peer=2,conn_id=5,protocol=1,direction=0 latency=12i,loss=3i,bandwidth=100i,up=1i 2000000000"
+            ]
+        );
+    }
+
+    #[test]
+    fn udp_sink_should_split_datagrams() {
+        let listener = UdpSocket::bind("127.0.0.1:0").expect("should bind");
+        listener.set_read_timeout(Some(Duration::from_secs(5))).expect("should set timeout");
+        let mut sink = InfluxUdpSink::new(listener.local_addr().expect("should have addr"))
+            .expect("should create sink")
+            .with_max_datagram_bytes(100);
+        sink.send(&node_points(&node(), 2000)).expect("should send");
+
+        let mut buf = [0; 2048];
+        for measurement in ["sdn_node,", "sdn_connection,"] {
+            let len = listener.recv(&mut buf).expect("should receive");
+            assert!(std::str::from_utf8(&buf[..len]).expect("should be utf8").starts_with(measurement));
+        }
+    }
+
+    #[test]
+    fn http_sink_should_post_lines_with_token() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("should bind");
+        let endpoint = format!("http://{}/api/v2/write?bucket=sdn", listener.local_addr().expect("should have addr"));
+        let receiver = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("should accept");
+            let mut buf = vec![];
+            let mut chunk = [0; 4096];
+            // the connection line is the last one of the body
+            while !buf.ends_with(b"up=1i 2000000000\n") {
+                let read = stream.read(&mut chunk).expect("should read");
+                buf.extend_from_slice(&chunk[..read]);
+            }
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").expect("should answer");
+            String::from_utf8(buf).expect("should be utf8")
+        });
+
+        let mut sink = InfluxHttpSink::new(&endpoint).expect("should create sink").with_token("secret");
+        sink.send(&node_points(&node(), 2000)).expect("should send");
+
+        let request = receiver.join().expect("should receive");
+        assert!(request.starts_with("POST /api/v2/write?bucket=sdn HTTP/1.1\r\n"));
+        assert!(request.contains("Authorization: Token secret\r\n"));
+        assert!(request.ends_with("latency=12i,loss=3i,bandwidth=100i,up=1i 2000000000\n"));
+    }
+}
@@ -0,0 +1,326 @@
+//! Push per-node and per-connection series to external metrics systems. Sinks are registered on the
+//! controller and driven by the master ingest worker, each sink sends from its own thread so blocking IO
+//! stalls neither the SDN nor the ingest.
+
+mod http;
+mod influx;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod statsd;
+
+use std::{
+    io,
+    sync::{
+        mpsc::{sync_channel, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+};
+
+use crate::identity::{ConnectionStatus, CONNECTION_TIMEOUT_MS};
+
+use super::NodeData;
+
+pub(crate) use http::HttpEndpoint;
+pub use influx::{InfluxHttpSink, InfluxUdpSink};
+#[cfg(feature = "otlp")]
+pub use otlp::{OtlpSink, OTLP_LABEL_PREFIX};
+pub use statsd::{StatsdSink, StatsdTagStyle};
+
+/// Largest UDP datagram the built-in sinks send, fits a usual MTU
+pub const DEFAULT_MAX_DATAGRAM_BYTES: usize = 1400;
+/// Batches of points waiting for a sink thread, newer ones are dropped while it is full
+pub const SINK_QUEUE_BATCHES: usize = 16;
+
+/// One sample of a series, rendered by each sink in its own protocol
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MetricPoint {
+    pub measurement: &'static str,
+    pub tags: Vec<(String, String)>,
+    pub fields: Vec<(&'static str, i64)>,
+    pub ts_ms: u64,
+}
+
+pub trait MetricsSink: Send {
+    fn send(&mut self, points: &[MetricPoint]) -> io::Result<()>;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SinkTrigger {
+    /// Series of the nodes touched by each ingested batch
+    OnIngest,
+    /// Series of all nodes every given milliseconds, checked on master ticks
+    Interval(u64),
+}
+
+/// `sdn_node` and one `sdn_connection` point per connection, tagged with the node labels
+pub fn node_points(node: &NodeData, now_ms: u64) -> Vec<MetricPoint> {
+    let mut tags = vec![(String::from("node_id"), node.id.to_string())];
+    tags.extend(node.labels.iter().map(|(key, value)| (key.clone(), value.clone())));
+    let online = now_ms.saturating_sub(node.last_ping_ts) <= CONNECTION_TIMEOUT_MS;
+
+    let mut points = Vec::with_capacity(node.conns.len() + 1);
+    points.push(MetricPoint {
+        measurement: "sdn_node",
+        tags: tags.clone(),
+        fields: vec![("online", online as i64), ("connections", node.conns.len() as i64), ("dropped_reports", node.dropped_reports as i64)],
+        ts_ms: now_ms,
+    });
+    for conn in node.conns.iter() {
+        let mut conn_tags = tags.clone();
+        conn_tags.push((String::from("peer"), conn.node_id.to_string()));
+        conn_tags.push((String::from("conn_id"), conn.id.to_string()));
+        conn_tags.push((String::from("protocol"), conn.protocol.to_string()));
+        conn_tags.push((String::from("direction"), conn.direction.to_string()));
+        points.push(MetricPoint {
+            measurement: "sdn_connection",
+            tags: conn_tags,
+            fields: vec![
+                ("latency", conn.metric.latency as i64),
+                ("loss", conn.metric.loss_percent as i64),
+                ("bandwidth", conn.metric.bandwidth as i64),
+                ("up", (conn.status == ConnectionStatus::CONNECTED) as i64),
+            ],
+            ts_ms: now_ms,
+        });
+    }
+    points
+}
+
+/// Pack newline terminated lines into datagrams of at most `max_bytes`, a longer line goes alone
+pub(crate) fn pack_lines(lines: &[String], max_bytes: usize) -> Vec<Vec<u8>> {
+    let mut datagrams = vec![];
+    let mut current: Vec<u8> = vec![];
+    for line in lines {
+        if !current.is_empty() && current.len() + line.len() + 1 > max_bytes {
+            datagrams.push(std::mem::take(&mut current));
+        }
+        current.extend_from_slice(line.as_bytes());
+        current.push(b'\n');
+    }
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+    datagrams
+}
+
+struct SinkEntry {
+    // the sink thread stops once this is dropped
+    sender: SyncSender<Arc<Vec<MetricPoint>>>,
+    trigger: SinkTrigger,
+    last_flush_ms: Option<u64>,
+    dropped: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct MetricsSinks {
+    entries: Vec<SinkEntry>,
+}
+
+impl MetricsSinks {
+    pub fn add(&mut self, mut sink: Box<dyn MetricsSink>, trigger: SinkTrigger) {
+        let (sender, receiver) = sync_channel::<Arc<Vec<MetricPoint>>>(SINK_QUEUE_BATCHES);
+        thread::Builder::new()
+            .name("visualization-sink".to_string())
+            .spawn(move || {
+                while let Ok(points) = receiver.recv() {
+                    if let Err(err) = sink.send(&points) {
+                        log::warn!("[MetricsSinks] send {} points failed {:?}", points.len(), err);
+                    }
+                }
+            })
+            .expect("should spawn metrics sink thread");
+        self.entries.push(SinkEntry {
+            sender,
+            trigger,
+            last_flush_ms: None,
+            dropped: 0,
+        });
+    }
+
+    pub fn has_ingest_sinks(&self) -> bool {
+        self.entries.iter().any(|entry| entry.trigger == SinkTrigger::OnIngest)
+    }
+
+    /// Batches dropped because a sink thread was behind, since start
+    pub fn dropped(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.dropped).sum()
+    }
+
+    fn send(entry: &mut SinkEntry, points: &Arc<Vec<MetricPoint>>) {
+        if points.is_empty() {
+            return;
+        }
+        match entry.sender.try_send(points.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                entry.dropped += 1;
+                log::warn!("[MetricsSinks] sink is behind, drop {} points", points.len());
+            }
+            Err(TrySendError::Disconnected(_)) => log::warn!("[MetricsSinks] sink thread stopped, drop {} points", points.len()),
+        }
+    }
+
+    pub fn on_ingest(&mut self, nodes: &[Arc<NodeData>], now_ms: u64) {
+        let points: Arc<Vec<MetricPoint>> = Arc::new(nodes.iter().flat_map(|node| node_points(node, now_ms)).collect());
+        for entry in self.entries.iter_mut().filter(|entry| entry.trigger == SinkTrigger::OnIngest) {
+            Self::send(entry, &points);
+        }
+    }
+
+    /// Flush interval sinks which are due, `nodes` is only called if one is
+    pub fn on_tick<F: FnOnce() -> Vec<Arc<NodeData>>>(&mut self, nodes: F, now_ms: u64) {
+        let mut due = self
+            .entries
+            .iter_mut()
+            .filter(|entry| match entry.trigger {
+                SinkTrigger::Interval(interval_ms) => entry.last_flush_ms.iter().all(|last| now_ms >= last + interval_ms),
+                SinkTrigger::OnIngest => false,
+            })
+            .peekable();
+        if due.peek().is_none() {
+            return;
+        }
+        let points: Arc<Vec<MetricPoint>> = Arc::new(nodes().iter().flat_map(|node| node_points(node, now_ms)).collect());
+        for entry in due {
+            entry.last_flush_ms = Some(now_ms);
+            Self::send(entry, &points);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::collections::BTreeMap;
+
+    use parking_lot::Mutex;
+
+    use crate::{collector::NodeConnectionData, identity::ConnectionMetric};
+
+    use super::*;
+
+    /// Keeps everything it receives, shared so tests can look after handing it over
+    #[derive(Clone, Default)]
+    pub struct MemorySink {
+        pub points: Arc<Mutex<Vec<MetricPoint>>>,
+    }
+
+    impl MemorySink {
+        /// Wait for the sink thread to deliver `count` points, they are sent asynchronously
+        pub fn wait_len(&self, count: usize) -> usize {
+            for _ in 0..500 {
+                if self.points.lock().len() >= count {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(2));
+            }
+            self.points.lock().len()
+        }
+    }
+
+    impl MetricsSink for MemorySink {
+        fn send(&mut self, points: &[MetricPoint]) -> io::Result<()> {
+            self.points.lock().extend_from_slice(points);
+            Ok(())
+        }
+    }
+
+    /// Blocks every send until the test drops the other end
+    struct StuckSink {
+        release: std::sync::mpsc::Receiver<()>,
+        sent: Arc<Mutex<usize>>,
+    }
+
+    impl MetricsSink for StuckSink {
+        fn send(&mut self, _points: &[MetricPoint]) -> io::Result<()> {
+            let _ = self.release.recv();
+            *self.sent.lock() += 1;
+            Ok(())
+        }
+    }
+
+    pub fn node() -> NodeData {
+        let mut node = NodeData::new(1, String::from("/ip4/127.0.0.1"), 1000);
+        node.labels = BTreeMap::from([(String::from("region"), String::from("eu"))]);
+        node.conns = vec![NodeConnectionData {
+            id: 5,
+            node_id: 2,
+            protocol: 1,
+            addr: String::from("/ip4/127.0.0.2"),
+            metric: ConnectionMetric {
+                latency: 12,
+                loss_percent: 3,
+                bandwidth: 100,
+            },
+            status: ConnectionStatus::CONNECTED,
+            last_updated_at: 1000,
+            direction: 0,
+        }]
+        .into();
+        node
+    }
+
+    #[test]
+    fn node_points_should_tag_labels_and_connection() {
+        let points = node_points(&node(), 2000);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].measurement, "sdn_node");
+        assert_eq!(points[0].tags, vec![(String::from("node_id"), String::from("1")), (String::from("region"), String::from("eu"))]);
+        assert_eq!(points[0].fields, vec![("online", 1), ("connections", 1), ("dropped_reports", 0)]);
+        assert_eq!(points[1].measurement, "sdn_connection");
+        assert_eq!(points[1].tags[2], (String::from("peer"), String::from("2")));
+        assert_eq!(points[1].fields, vec![("latency", 12), ("loss", 3), ("bandwidth", 100), ("up", 1)]);
+    }
+
+    #[test]
+    fn pack_lines_should_respect_datagram_size() {
+        let lines = vec![String::from("aaaa"), String::from("bbbb"), String::from("cccccccccc"), String::from("d")];
+        assert_eq!(pack_lines(&lines, 10), vec![b"aaaa\nbbbb\n".to_vec(), b"cccccccccc\n".to_vec(), b"d\n".to_vec()]);
+    }
+
+    #[test]
+    fn interval_sinks_should_flush_when_due() {
+        let ingest = MemorySink::default();
+        let interval = MemorySink::default();
+        let mut sinks = MetricsSinks::default();
+        sinks.add(Box::new(ingest.clone()), SinkTrigger::OnIngest);
+        sinks.add(Box::new(interval.clone()), SinkTrigger::Interval(1000));
+        let nodes = vec![Arc::new(node())];
+
+        sinks.on_ingest(&nodes, 1000);
+        assert_eq!(ingest.wait_len(2), 2);
+        assert_eq!(interval.points.lock().len(), 0);
+
+        sinks.on_tick(|| nodes.clone(), 1000);
+        sinks.on_tick(|| panic!("should not collect before due"), 1500);
+        sinks.on_tick(|| nodes.clone(), 2000);
+        assert_eq!(interval.wait_len(4), 4);
+        assert_eq!(ingest.points.lock().len(), 2);
+    }
+
+    #[test]
+    fn slow_sink_should_drop_batches_instead_of_blocking_ingest() {
+        let (release, stuck) = std::sync::mpsc::channel();
+        let sent = Arc::new(Mutex::new(0));
+        let mut sinks = MetricsSinks::default();
+        sinks.add(Box::new(StuckSink { release: stuck, sent: sent.clone() }), SinkTrigger::OnIngest);
+        let nodes = vec![Arc::new(node())];
+
+        let batches = SINK_QUEUE_BATCHES as u64 + 4;
+        for _ in 0..batches {
+            sinks.on_ingest(&nodes, 1000);
+        }
+        // the thread holds at most one batch, the queue the next ones
+        assert!(sinks.dropped() >= 3);
+
+        drop(release);
+        let expected = (batches - sinks.dropped()) as usize;
+        for _ in 0..500 {
+            if *sent.lock() == expected {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        assert_eq!(*sent.lock(), expected);
+    }
+}
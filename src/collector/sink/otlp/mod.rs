@@ -0,0 +1,331 @@
+//! Node and connection series as OTLP over HTTP/protobuf, for an OpenTelemetry collector.
+
+mod proto;
+
+use std::{collections::HashMap, io, time::Duration};
+
+use self::proto::{AttrValue, DataPoint, ProtoWriter};
+use super::{HttpEndpoint, MetricPoint, MetricsSink};
+
+/// Node point fields sent as gauges: field, metric name, description, unit
+const NODE_GAUGES: [(&str, &str, &str, &str); 2] = [
+    ("online", "sdn.node.online", "1 if the node pinged recently", "1"),
+    ("connections", "sdn.node.connections", "Connections reported by the node", "{connection}"),
+];
+/// Connection point fields, all sent as gauges
+const CONNECTION_GAUGES: [(&str, &str, &str, &str); 4] = [
+    ("latency", "sdn.connection.latency", "Connection round trip latency", "ms"),
+    ("loss", "sdn.connection.loss", "Connection packet loss", "%"),
+    ("bandwidth", "sdn.connection.bandwidth", "Connection bandwidth", "kbit/s"),
+    ("up", "sdn.connection.up", "1 if the connection is connected", "1"),
+];
+/// Connection tags which become data point attributes, the others are the node tags
+const CONNECTION_ATTRIBUTES: [(&str, &str); 4] = [
+    ("conn_id", "sdn.conn.id"),
+    ("peer", "sdn.conn.peer"),
+    ("protocol", "sdn.conn.protocol"),
+    ("direction", "sdn.conn.direction"),
+];
+/// Prefix of the node label resource attributes, so a label never overrides `service.name` or `sdn.node.id`
+pub const OTLP_LABEL_PREFIX: &str = "sdn.label.";
+
+fn field(point: &MetricPoint, name: &str) -> Option<i64> {
+    point.fields.iter().find(|(key, _)| *key == name).map(|(_, value)| *value)
+}
+
+fn tag<'a>(point: &'a MetricPoint, name: &str) -> Option<&'a str> {
+    point.tags.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+/// A `sdn_node` point and the `sdn_connection` points following it, as built by `node_points`
+struct NodeSeries<'a> {
+    node: &'a MetricPoint,
+    conns: Vec<&'a MetricPoint>,
+}
+
+fn group_by_node(points: &[MetricPoint]) -> Vec<NodeSeries<'_>> {
+    let mut series: Vec<NodeSeries> = vec![];
+    for point in points {
+        match point.measurement {
+            "sdn_node" => series.push(NodeSeries { node: point, conns: vec![] }),
+            "sdn_connection" => match series.last_mut() {
+                Some(last) if tag(last.node, "node_id") == tag(point, "node_id") => last.conns.push(point),
+                _ => log::warn!("[OtlpSink] skip connection point without its node point"),
+            },
+            _ => {}
+        }
+    }
+    series
+}
+
+/// OTLP over HTTP/protobuf, one ResourceMetrics per node with its labels as `sdn.label.<key>` resource attributes.
+/// Only plain http is supported, the endpoint defaults to the `/v1/metrics` path.
+pub struct OtlpSink {
+    endpoint: HttpEndpoint,
+    service_name: String,
+    timeout: Duration,
+    // start time and last value of the cumulative dropped reports counter, by node id tag
+    counter_starts: HashMap<String, (u64, i64)>,
+}
+
+impl OtlpSink {
+    pub fn new(endpoint: &str) -> Result<Self, String> {
+        Ok(Self {
+            endpoint: HttpEndpoint::parse(endpoint, "/v1/metrics")?,
+            service_name: String::from("atm0s-sdn"),
+            timeout: Duration::from_millis(5000),
+            counter_starts: HashMap::new(),
+        })
+    }
+
+    /// `service.name` resource attribute of every node, defaults to atm0s-sdn
+    pub fn with_service_name(mut self, service_name: &str) -> Self {
+        self.service_name = service_name.to_string();
+        self
+    }
+
+    pub fn with_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.timeout = Duration::from_millis(timeout_ms);
+        self
+    }
+
+    /// The counter starts when the sink first sees the node, and again when the value goes down after an agent restart
+    fn counter_start(&mut self, node_id: &str, value: i64, ts_ms: u64) -> u64 {
+        let start = self.counter_starts.entry(node_id.to_string()).or_insert((ts_ms, value));
+        if value < start.1 {
+            start.0 = ts_ms;
+        }
+        start.1 = value;
+        start.0
+    }
+
+    /// Build an ExportMetricsServiceRequest
+    fn encode_request(&mut self, points: &[MetricPoint]) -> Vec<u8> {
+        let mut request = ProtoWriter::default();
+        for series in group_by_node(points) {
+            let node = series.node;
+            let node_id = tag(node, "node_id").unwrap_or_default();
+            let time_unix_nano = node.ts_ms * 1_000_000;
+            let dropped_reports = field(node, "dropped_reports").unwrap_or_default();
+            let start_time_unix_nano = self.counter_start(node_id, dropped_reports, node.ts_ms) * 1_000_000;
+            request.message(1, |resource_metrics| {
+                resource_metrics.message(1, |resource| {
+                    proto::attribute(resource, 1, "service.name", &AttrValue::Str(&self.service_name));
+                    proto::attribute(resource, 1, "sdn.node.id", &AttrValue::Int(node_id.parse().unwrap_or_default()));
+                    for (key, value) in node.tags.iter().filter(|(key, _)| key != "node_id") {
+                        proto::attribute(resource, 1, &format!("{}{}", OTLP_LABEL_PREFIX, key), &AttrValue::Str(value));
+                    }
+                });
+                resource_metrics.message(2, |scope_metrics| {
+                    scope_metrics.message(1, |scope| {
+                        scope.string(1, env!("CARGO_PKG_NAME"));
+                        scope.string(2, env!("CARGO_PKG_VERSION"));
+                    });
+                    let point = |value: i64| DataPoint { attributes: vec![], value };
+                    for (name, metric, description, unit) in NODE_GAUGES {
+                        proto::gauge(scope_metrics, metric, description, unit, &[point(field(node, name).unwrap_or_default())], time_unix_nano);
+                    }
+                    proto::counter(
+                        scope_metrics,
+                        "sdn.node.dropped_reports",
+                        "Reports dropped by the agent queue",
+                        "{report}",
+                        &[point(dropped_reports)],
+                        start_time_unix_nano,
+                        time_unix_nano,
+                    );
+                    write_connection_metrics(scope_metrics, node, &series.conns, time_unix_nano);
+                });
+            });
+        }
+        request.into_bytes()
+    }
+}
+
+/// Connection points carry the node tags first, then their own
+fn connection_attributes<'a>(node: &MetricPoint, conn: &'a MetricPoint) -> Vec<(&'static str, AttrValue<'a>)> {
+    let own = &conn.tags[node.tags.len().min(conn.tags.len())..];
+    CONNECTION_ATTRIBUTES
+        .iter()
+        .filter_map(|(name, attribute)| {
+            let value = own.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())?;
+            // connection ids are u64, they may not fit an int attribute
+            Some(match value.parse::<i64>() {
+                Ok(value) if *name != "conn_id" => (*attribute, AttrValue::Int(value)),
+                _ => (*attribute, AttrValue::Str(value)),
+            })
+        })
+        .collect()
+}
+
+fn write_connection_metrics(w: &mut ProtoWriter, node: &MetricPoint, conns: &[&MetricPoint], time_unix_nano: u64) {
+    if conns.is_empty() {
+        return;
+    }
+    for (name, metric, description, unit) in CONNECTION_GAUGES {
+        let points: Vec<DataPoint> = conns
+            .iter()
+            .map(|conn| DataPoint {
+                attributes: connection_attributes(node, conn),
+                value: field(conn, name).unwrap_or_default(),
+            })
+            .collect();
+        proto::gauge(w, metric, description, unit, &points, time_unix_nano);
+    }
+}
+
+impl MetricsSink for OtlpSink {
+    fn send(&mut self, points: &[MetricPoint]) -> io::Result<()> {
+        let body = self.encode_request(points);
+        self.endpoint.post("application/x-protobuf", &[], &body, self.timeout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::BTreeMap,
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use crate::collector::NodeData;
+
+    use super::{
+        super::{node_points, test::node},
+        proto::decode,
+        *,
+    };
+
+    /// Accept one request, answer with `status` and return the request head and body
+    fn mock_receiver(status: &'static str) -> (String, thread::JoinHandle<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("should bind");
+        let endpoint = format!("http://{}/v1/metrics", listener.local_addr().expect("should have addr"));
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("should accept");
+            let mut buf = vec![];
+            let mut chunk = [0; 4096];
+            let (head, body_start, len) = loop {
+                let read = stream.read(&mut chunk).expect("should read");
+                buf.extend_from_slice(&chunk[..read]);
+                if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+                    let head = String::from_utf8_lossy(&buf[..end]).to_string();
+                    let len = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .and_then(|len| len.parse::<usize>().ok())
+                        .expect("should have content length");
+                    break (head, end + 4, len);
+                }
+            };
+            while buf.len() < body_start + len {
+                let read = stream.read(&mut chunk).expect("should read");
+                buf.extend_from_slice(&chunk[..read]);
+            }
+            stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).as_bytes()).expect("should answer");
+            (head, buf[body_start..].to_vec())
+        });
+        (endpoint, handle)
+    }
+
+    fn attributes(values: &[decode::Value]) -> BTreeMap<String, decode::Value> {
+        values
+            .iter()
+            .map(|kv| {
+                let kv = kv.fields();
+                let value = decode::get(&kv, 2)[0].fields().remove(0).1;
+                (decode::get(&kv, 1)[0].str().to_string(), value)
+            })
+            .collect()
+    }
+
+    /// Metrics of the first ResourceMetrics of a request
+    fn metrics(body: &[u8]) -> Vec<Vec<(u32, decode::Value)>> {
+        let resource_metrics = decode::get(&decode::fields(body), 1)[0].fields();
+        decode::get(&decode::get(&resource_metrics, 2)[0].fields(), 2).iter().map(|metric| metric.fields()).collect()
+    }
+
+    #[test]
+    fn send_should_post_protobuf_with_node_resources() {
+        let (endpoint, receiver) = mock_receiver("200 OK");
+        let mut sink = OtlpSink::new(&endpoint).expect("should create sink");
+        sink.send(&node_points(&node(), 2000)).expect("should send");
+
+        let (head, body) = receiver.join().expect("should receive");
+        assert!(head.starts_with("POST /v1/metrics HTTP/1.1"));
+        assert!(head.contains("Content-Type: application/x-protobuf"));
+
+        let resource_metrics = decode::get(&decode::fields(&body), 1);
+        assert_eq!(resource_metrics.len(), 1);
+        let resource = attributes(&decode::get(&decode::get(&resource_metrics[0].fields(), 1)[0].fields(), 1));
+        assert_eq!(resource["service.name"], decode::Value::Bytes(b"atm0s-sdn".to_vec()));
+        assert_eq!(resource["sdn.node.id"], decode::Value::Varint(1));
+        assert_eq!(resource["sdn.label.region"], decode::Value::Bytes(b"eu".to_vec()));
+
+        let metrics = metrics(&body);
+        let names: Vec<String> = metrics.iter().map(|metric| decode::get(metric, 1)[0].str().to_string()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "sdn.node.online",
+                "sdn.node.connections",
+                "sdn.node.dropped_reports",
+                "sdn.connection.latency",
+                "sdn.connection.loss",
+                "sdn.connection.bandwidth",
+                "sdn.connection.up"
+            ]
+        );
+        let latency = decode::get(&decode::get(&metrics[3], 5)[0].fields(), 1)[0].fields();
+        assert_eq!(decode::get(&latency, 3)[0], decode::Value::Fixed64(2000 * 1_000_000));
+        assert_eq!(decode::get(&latency, 6)[0], decode::Value::Fixed64(12));
+        let point_attrs = attributes(&decode::get(&latency, 7));
+        assert_eq!(point_attrs["sdn.conn.peer"], decode::Value::Varint(2));
+        assert_eq!(point_attrs["sdn.conn.id"], decode::Value::Bytes(b"5".to_vec()));
+    }
+
+    #[test]
+    fn labels_should_not_override_reserved_resource_attributes() {
+        let mut labeled = node();
+        labeled.labels.insert(String::from("service.name"), String::from("spoofed"));
+        labeled.labels.insert(String::from("sdn.node.id"), String::from("9"));
+        let mut sink = OtlpSink::new("http://127.0.0.1:4318").expect("should create sink");
+        let body = sink.encode_request(&node_points(&labeled, 2000));
+
+        let resource_metrics = decode::get(&decode::fields(&body), 1)[0].fields();
+        let resource = attributes(&decode::get(&decode::get(&resource_metrics, 1)[0].fields(), 1));
+        assert_eq!(resource["service.name"], decode::Value::Bytes(b"atm0s-sdn".to_vec()));
+        assert_eq!(resource["sdn.node.id"], decode::Value::Varint(1));
+        assert_eq!(resource["sdn.label.service.name"], decode::Value::Bytes(b"spoofed".to_vec()));
+        assert_eq!(resource["sdn.label.sdn.node.id"], decode::Value::Bytes(b"9".to_vec()));
+    }
+
+    #[test]
+    fn dropped_reports_counter_should_keep_its_start_until_reset() {
+        let mut sink = OtlpSink::new("http://127.0.0.1:4318").expect("should create sink");
+        let mut dropped = node();
+        let start_time = |sink: &mut OtlpSink, node: &NodeData, now_ms: u64| {
+            let metrics = metrics(&sink.encode_request(&node_points(node, now_ms)));
+            let sum = decode::get(&metrics[2], 7)[0].fields();
+            decode::get(&decode::get(&sum, 1)[0].fields(), 2)[0].clone()
+        };
+
+        dropped.dropped_reports = 3;
+        assert_eq!(start_time(&mut sink, &dropped, 2000), decode::Value::Fixed64(2000 * 1_000_000));
+        dropped.dropped_reports = 5;
+        assert_eq!(start_time(&mut sink, &dropped, 3000), decode::Value::Fixed64(2000 * 1_000_000));
+        // the agent restarted
+        dropped.dropped_reports = 1;
+        assert_eq!(start_time(&mut sink, &dropped, 4000), decode::Value::Fixed64(4000 * 1_000_000));
+    }
+
+    #[test]
+    fn send_should_fail_on_error_status() {
+        let (endpoint, receiver) = mock_receiver("503 Service Unavailable");
+        let mut sink = OtlpSink::new(&endpoint).expect("should create sink");
+        let err = sink.send(&node_points(&node(), 2000)).expect_err("should fail");
+        assert!(err.to_string().contains("503"));
+        receiver.join().expect("should receive");
+    }
+}
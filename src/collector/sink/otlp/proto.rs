@@ -0,0 +1,232 @@
+//! Minimal protobuf encoding of the OTLP metrics messages we send, field numbers follow
+//! opentelemetry/proto/metrics/v1/metrics.proto and opentelemetry/proto/common/v1/common.proto.
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+
+/// AggregationTemporality.AGGREGATION_TEMPORALITY_CUMULATIVE
+const TEMPORALITY_CUMULATIVE: u64 = 2;
+
+#[derive(Default)]
+pub(super) struct ProtoWriter {
+    buf: Vec<u8>,
+}
+
+impl ProtoWriter {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire: u8) {
+        self.varint(((field as u64) << 3) | wire as u64);
+    }
+
+    pub fn uint64(&mut self, field: u32, value: u64) {
+        self.key(field, WIRE_VARINT);
+        self.varint(value);
+    }
+
+    pub fn bool(&mut self, field: u32, value: bool) {
+        self.uint64(field, value as u64);
+    }
+
+    pub fn fixed64(&mut self, field: u32, value: u64) {
+        self.key(field, WIRE_FIXED64);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn sfixed64(&mut self, field: u32, value: i64) {
+        self.fixed64(field, value as u64);
+    }
+
+    pub fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.key(field, WIRE_LEN);
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    pub fn string(&mut self, field: u32, value: &str) {
+        self.bytes(field, value.as_bytes());
+    }
+
+    pub fn message<F: FnOnce(&mut ProtoWriter)>(&mut self, field: u32, f: F) {
+        let mut inner = ProtoWriter::default();
+        f(&mut inner);
+        self.bytes(field, &inner.buf);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub(super) enum AttrValue<'a> {
+    Str(&'a str),
+    Int(i64),
+}
+
+/// KeyValue { key = 1, value = 2 }, AnyValue { string_value = 1, int_value = 3 }
+pub(super) fn attribute(w: &mut ProtoWriter, field: u32, key: &str, value: &AttrValue) {
+    w.message(field, |kv| {
+        kv.string(1, key);
+        kv.message(2, |any| match value {
+            AttrValue::Str(value) => any.string(1, value),
+            AttrValue::Int(value) => any.uint64(3, *value as u64),
+        });
+    });
+}
+
+pub(super) struct DataPoint<'a> {
+    pub attributes: Vec<(&'a str, AttrValue<'a>)>,
+    pub value: i64,
+}
+
+/// NumberDataPoint { attributes = 7, start_time_unix_nano = 2, time_unix_nano = 3, as_int = 6 }
+fn data_point(w: &mut ProtoWriter, point: &DataPoint, start_time_unix_nano: Option<u64>, time_unix_nano: u64) {
+    w.message(1, |dp| {
+        for (key, value) in point.attributes.iter() {
+            attribute(dp, 7, key, value);
+        }
+        if let Some(start_time_unix_nano) = start_time_unix_nano {
+            dp.fixed64(2, start_time_unix_nano);
+        }
+        dp.fixed64(3, time_unix_nano);
+        dp.sfixed64(6, point.value);
+    });
+}
+
+/// Metric { name = 1, description = 2, unit = 3, gauge = 5 }, written in ScopeMetrics.metrics = 2
+pub(super) fn gauge(w: &mut ProtoWriter, name: &str, description: &str, unit: &str, points: &[DataPoint], time_unix_nano: u64) {
+    w.message(2, |metric| {
+        metric.string(1, name);
+        metric.string(2, description);
+        metric.string(3, unit);
+        metric.message(5, |gauge| {
+            for point in points {
+                data_point(gauge, point, None, time_unix_nano);
+            }
+        });
+    });
+}
+
+/// Metric with a monotonic cumulative Sum = 7, counting since `start_time_unix_nano`
+pub(super) fn counter(w: &mut ProtoWriter, name: &str, description: &str, unit: &str, points: &[DataPoint], start_time_unix_nano: u64, time_unix_nano: u64) {
+    w.message(2, |metric| {
+        metric.string(1, name);
+        metric.string(2, description);
+        metric.string(3, unit);
+        metric.message(7, |sum| {
+            for point in points {
+                data_point(sum, point, Some(start_time_unix_nano), time_unix_nano);
+            }
+            sum.uint64(2, TEMPORALITY_CUMULATIVE);
+            sum.bool(3, true);
+        });
+    });
+}
+
+#[cfg(test)]
+pub(super) mod decode {
+    //! Just enough of a protobuf reader to check what we encode
+
+    #[derive(Debug, PartialEq, Clone)]
+    pub enum Value {
+        Varint(u64),
+        Fixed64(u64),
+        Bytes(Vec<u8>),
+    }
+
+    impl Value {
+        pub fn fields(&self) -> Vec<(u32, Value)> {
+            match self {
+                Value::Bytes(bytes) => fields(bytes),
+                _ => panic!("not a message"),
+            }
+        }
+
+        pub fn str(&self) -> &str {
+            match self {
+                Value::Bytes(bytes) => std::str::from_utf8(bytes).expect("should be utf8"),
+                _ => panic!("not a string"),
+            }
+        }
+    }
+
+    fn varint(buf: &[u8], pos: &mut usize) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = buf[*pos];
+            *pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    pub fn fields(buf: &[u8]) -> Vec<(u32, Value)> {
+        let mut pos = 0;
+        let mut out = vec![];
+        while pos < buf.len() {
+            let key = varint(buf, &mut pos);
+            let value = match key & 7 {
+                0 => Value::Varint(varint(buf, &mut pos)),
+                1 => {
+                    pos += 8;
+                    Value::Fixed64(u64::from_le_bytes(buf[pos - 8..pos].try_into().expect("should have 8 bytes")))
+                }
+                2 => {
+                    let len = varint(buf, &mut pos) as usize;
+                    pos += len;
+                    Value::Bytes(buf[pos - len..pos].to_vec())
+                }
+                wire => panic!("unexpected wire type {}", wire),
+            };
+            out.push(((key >> 3) as u32, value));
+        }
+        out
+    }
+
+    /// All values of `field`
+    pub fn get(fields: &[(u32, Value)], field: u32) -> Vec<Value> {
+        fields.iter().filter(|(number, _)| *number == field).map(|(_, value)| value.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_encode_varints_and_nested_messages() {
+        let mut w = ProtoWriter::default();
+        w.uint64(1, 300);
+        w.message(2, |inner| inner.string(1, "ab"));
+        w.sfixed64(3, -1);
+        assert_eq!(
+            w.into_bytes(),
+            vec![0x08, 0xac, 0x02, 0x12, 0x04, 0x0a, 0x02, b'a', b'b', 0x19, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn attribute_should_round_trip() {
+        let mut w = ProtoWriter::default();
+        attribute(&mut w, 1, "zone", &AttrValue::Str("eu"));
+        attribute(&mut w, 1, "id", &AttrValue::Int(7));
+        let attrs = decode::get(&decode::fields(&w.into_bytes()), 1);
+        assert_eq!(attrs.len(), 2);
+        let first = attrs[0].fields();
+        assert_eq!(decode::get(&first, 1)[0].str(), "zone");
+        assert_eq!(decode::get(&decode::get(&first, 2)[0].fields(), 1)[0].str(), "eu");
+        let second = attrs[1].fields();
+        assert_eq!(decode::get(&decode::get(&second, 2)[0].fields(), 3)[0], decode::Value::Varint(7));
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+};
+
+use super::{influx::connect_udp, pack_lines, MetricPoint, MetricsSink, DEFAULT_MAX_DATAGRAM_BYTES};
+
+/// Plain StatsD has no tags, pick the extension of the receiving agent
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum StatsdTagStyle {
+    /// `name:1|g|#key:value`, DogStatsD and Telegraf with datadog_extensions
+    #[default]
+    Datadog,
+    /// `name,key=value:1|g`, Telegraf statsd input
+    Influx,
+}
+
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if matches!(c, ':' | '|' | ',' | '=' | '#' | '@' | ' ' | '\n') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Every field is sent as a gauge named `<prefix><measurement>.<field>`
+pub(crate) fn to_lines(point: &MetricPoint, prefix: &str, style: StatsdTagStyle) -> Vec<String> {
+    let tags: Vec<(String, String)> = point.tags.iter().map(|(key, value)| (sanitize(key), sanitize(value))).collect();
+    point
+        .fields
+        .iter()
+        .map(|(field, value)| {
+            let name = format!("{}{}.{}", prefix, point.measurement, field);
+            match style {
+                StatsdTagStyle::Datadog => {
+                    let tags: Vec<String> = tags.iter().map(|(key, value)| format!("{}:{}", key, value)).collect();
+                    format!("{}:{}|g|#{}", name, value, tags.join(","))
+                }
+                StatsdTagStyle::Influx => {
+                    let tags: String = tags.iter().map(|(key, value)| format!(",{}={}", key, value)).collect();
+                    format!("{}{}:{}|g", name, tags, value)
+                }
+            }
+        })
+        .collect()
+}
+
+/// StatsD gauges over UDP, the agent aggregates and timestamps them
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+    style: StatsdTagStyle,
+    max_datagram_bytes: usize,
+}
+
+impl StatsdSink {
+    pub fn new<A: ToSocketAddrs>(addr: A, style: StatsdTagStyle) -> io::Result<Self> {
+        Ok(Self {
+            socket: connect_udp(addr)?,
+            prefix: String::new(),
+            style,
+            max_datagram_bytes: DEFAULT_MAX_DATAGRAM_BYTES,
+        })
+    }
+
+    /// Prepended as is to every metric name, e.g. `atm0s.`
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    pub fn with_max_datagram_bytes(mut self, max_datagram_bytes: usize) -> Self {
+        self.max_datagram_bytes = max_datagram_bytes;
+        self
+    }
+}
+
+impl MetricsSink for StatsdSink {
+    fn send(&mut self, points: &[MetricPoint]) -> io::Result<()> {
+        let lines: Vec<String> = points.iter().flat_map(|point| to_lines(point, &self.prefix, self.style)).collect();
+        for datagram in pack_lines(&lines, self.max_datagram_bytes) {
+            self.socket.send(&datagram)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{super::node_points, super::test::node, *};
+
+    fn point() -> MetricPoint {
+        MetricPoint {
+            measurement: "sdn_node",
+            tags: vec![(String::from("node_id"), String::from("1")), (String::from("zone"), String::from("a:b|c"))],
+            fields: vec![("online", 1), ("connections", 2)],
+            ts_ms: 0,
+        }
+    }
+
+    #[test]
+    fn lines_should_follow_tag_style() {
+        assert_eq!(
+            to_lines(&point(), "atm0s.", StatsdTagStyle::Datadog),
+            vec!["atm0s.sdn_node.online:1|g|#node_id:1,zone:a_b_c", "atm0s.sdn_node.connections:2|g|#node_id:1,zone:a_b_c"]
+        );
+        assert_eq!(to_lines(&point(), "", StatsdTagStyle::Influx)[0], "sdn_node.online,node_id=1,zone=a_b_c:1|g");
+    }
+
+    #[test]
+    fn sink_should_send_gauges_to_listener() {
+        let listener = UdpSocket::bind("127.0.0.1:0").expect("should bind");
+        listener.set_read_timeout(Some(Duration::from_secs(5))).expect("should set timeout");
+        let mut sink = StatsdSink::new(listener.local_addr().expect("should have addr"), StatsdTagStyle::Influx).expect("should create sink");
+        sink.send(&node_points(&node(), 2000)).expect("should send");
+
+        let mut buf = [0; 2048];
+        let len = listener.recv(&mut buf).expect("should receive");
+        let lines: Vec<&str> = std::str::from_utf8(&buf[..len]).expect("should be utf8").lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[0], "sdn_node.online,node_id=1,region=eu:1|g");
+        assert_eq!(lines[3], "sdn_connection.latency,node_id=1,region=eu,peer=2,conn_id=5,protocol=1,direction=0:12|g");
+    }
+}
@@ -1,9 +1,25 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+
 use atm0s_sdn_identity::NodeId;
-use atm0s_sdn_utils::hashmap::HashMap;
+use im::{HashMap, HashSet, OrdMap};
 use log::error;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::identity::{AppMetric, ConnectionMetric, ConnectionStatus, HostStats, CONNECTION_TIMEOUT_MS};
 
-use crate::identity::{ConnectionMetric, ConnectionStatus};
+use super::{
+    events::{diff_connection, AlertThresholds, TopologyEvent},
+    export::{self, ExportFormat, ExportScope},
+    graph::{self, Link, LinkMetric},
+    query::{NodeListPage, NodeListQuery},
+    region::{RegionDetail, RegionSummary},
+    samples::{ConnectionSample, ConnectionSamples, SampleFilter, MAX_CONNECTION_SAMPLES},
+};
+
+pub const MAX_HOST_STATS_SAMPLES: usize = 120;
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct NodeConnectionData {
@@ -17,12 +33,102 @@ pub struct NodeConnectionData {
     pub direction: u8,
 }
 
+/// Connections of a node keyed by connection id and iterated in id order. Cloning is O(1) and an update
+/// only copies the touched path, so storage snapshots stay cheap for nodes with thousands of connections.
+/// Serialized as a plain list.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct NodeConnections(OrdMap<u64, NodeConnectionData>);
+
+impl NodeConnections {
+    pub fn new() -> Self {
+        Self(OrdMap::new())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&NodeConnectionData> {
+        self.0.get(&id)
+    }
+
+    /// Insert or replace the connection with the same id, returning the previous one
+    pub fn insert(&mut self, conn: NodeConnectionData) -> Option<NodeConnectionData> {
+        self.0.insert(conn.id, conn)
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<NodeConnectionData> {
+        self.0.remove(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &NodeConnectionData> {
+        self.0.values()
+    }
+
+    /// Connections in id order, as the `Vec` which `NodeData.conns` used to be
+    pub fn to_vec(&self) -> Vec<NodeConnectionData> {
+        self.0.values().cloned().collect()
+    }
+}
+
+impl From<Vec<NodeConnectionData>> for NodeConnections {
+    fn from(conns: Vec<NodeConnectionData>) -> Self {
+        conns.into_iter().collect()
+    }
+}
+
+impl FromIterator<NodeConnectionData> for NodeConnections {
+    fn from_iter<I: IntoIterator<Item = NodeConnectionData>>(iter: I) -> Self {
+        Self(iter.into_iter().map(|conn| (conn.id, conn)).collect())
+    }
+}
+
+impl Serialize for NodeConnections {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeConnections {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<NodeConnectionData>::deserialize(deserializer).map(Self::from)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct NodeData {
     pub id: NodeId,
     pub addr: String,
     pub last_ping_ts: u64,
-    pub conns: Vec<NodeConnectionData>,
+    pub labels: BTreeMap<String, String>,
+    pub conns: NodeConnections,
+    /// Reports the agent dropped because its outgoing queue was full
+    #[serde(default)]
+    pub dropped_reports: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct HostStatsSample {
+    pub ts: u64,
+    pub stats: HostStats,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct NodeAppMetrics {
+    pub ts: u64,
+    pub metrics: Vec<AppMetric>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct NodeDetail {
+    #[serde(flatten)]
+    pub node: NodeData,
+    pub host_stats: Vec<HostStatsSample>,
+    pub app_metrics: Vec<AppMetric>,
 }
 
 impl NodeData {
@@ -31,13 +137,20 @@ impl NodeData {
             id: node_id,
             addr,
             last_ping_ts,
-            conns: vec![],
+            labels: BTreeMap::new(),
+            conns: NodeConnections::new(),
+            dropped_reports: 0,
         }
     }
 
+    /// Newest agent timestamp carried by the node state, copies held by different masters are ordered by it
+    pub fn version(&self) -> u64 {
+        self.conns.iter().map(|conn| conn.last_updated_at).fold(self.last_ping_ts, u64::max)
+    }
+
     pub fn dump(&self) {
         println!("===================================================================");
-        println!("Node info: id {}, addr: {}, last_ping: {}", self.id, self.addr, self.last_ping_ts);
+        println!("Node info: id {}, addr: {}, last_ping: {}, labels: {:?}", self.id, self.addr, self.last_ping_ts, self.labels);
         for conn in self.conns.iter() {
             println!(
                 "- dest conn_id: {}, node: {}, dest addr: {}, direction: {}, status: {}, latency: {}ms, bandwidth: {}kbps, loss: {}%, last updated at: {}",
@@ -55,76 +168,355 @@ impl NodeData {
     }
 }
 
+/// Node entries by id, a persistent map which clones in O(1) and copies only the touched path on update
+pub(crate) type NodeMap = HashMap<NodeId, Arc<NodeData>>;
+
+/// Cloning is O(1), the maps are persistent and every node entry is shared until it is modified, which lets the
+/// controller publish copy-on-write snapshots.
+#[derive(Clone)]
 pub struct NodeConnectionStorage {
-    nodes: HashMap<NodeId, NodeData>,
+    nodes: NodeMap,
+    host_stats: HashMap<NodeId, Arc<VecDeque<HostStatsSample>>>,
+    conn_samples: HashMap<NodeId, Arc<VecDeque<ConnectionSample>>>,
+    app_metrics: HashMap<NodeId, Arc<NodeAppMetrics>>,
+    online_nodes: HashSet<NodeId>,
+    alert_thresholds: AlertThresholds,
+    regions: HashMap<String, Arc<RegionSummary>>,
+    region_details: HashMap<String, Arc<RegionDetail>>,
 }
 
 impl NodeConnectionStorage {
     pub fn new() -> NodeConnectionStorage {
-        Self { nodes: HashMap::new() }
+        Self {
+            nodes: HashMap::new(),
+            host_stats: HashMap::new(),
+            conn_samples: HashMap::new(),
+            app_metrics: HashMap::new(),
+            online_nodes: HashSet::new(),
+            alert_thresholds: AlertThresholds::default(),
+            regions: HashMap::new(),
+            region_details: HashMap::new(),
+        }
     }
 
-    pub fn upsert_node(&mut self, node_id: NodeId, addr: String, last_ping_ts: u64) {
+    pub fn set_alert_thresholds(&mut self, thresholds: AlertThresholds) {
+        self.alert_thresholds = thresholds;
+    }
+
+    pub fn upsert_node(&mut self, node_id: NodeId, addr: String, last_ping_ts: u64) -> Vec<TopologyEvent> {
+        let mut events = vec![];
+        if self.online_nodes.insert(node_id).is_none() {
+            events.push(TopologyEvent::NodeJoined(node_id));
+        }
         match self.nodes.get_mut(&node_id) {
             Some(node) => {
                 if last_ping_ts > node.last_ping_ts {
-                    node.last_ping_ts = last_ping_ts;
+                    Arc::make_mut(node).last_ping_ts = last_ping_ts;
                 }
             }
             None => {
                 let node = NodeData::new(node_id, addr, last_ping_ts);
-                self.nodes.insert(node_id, node);
+                self.nodes.insert(node_id, Arc::new(node));
             }
         }
+        events
+    }
+
+    /// Mark nodes without ping for `CONNECTION_TIMEOUT_MS` as left, their host stats series is dropped
+    pub fn check_offline_nodes(&mut self, now_ms: u64) -> Vec<TopologyEvent> {
+        let nodes = &self.nodes;
+        let host_stats = &mut self.host_stats;
+        let mut events = vec![];
+        self.online_nodes.retain(|node_id| match nodes.get(node_id) {
+            Some(node) if now_ms.saturating_sub(node.last_ping_ts) <= CONNECTION_TIMEOUT_MS => true,
+            _ => {
+                host_stats.remove(node_id);
+                events.push(TopologyEvent::NodeLeft(*node_id));
+                false
+            }
+        });
+        events
     }
 
-    pub fn update_node_connection(&mut self, node_id: NodeId, conns: Vec<NodeConnectionData>) {
+    pub fn update_node_connection(&mut self, node_id: NodeId, conns: Vec<NodeConnectionData>) -> Vec<TopologyEvent> {
+        let mut events = vec![];
+        let thresholds = self.alert_thresholds;
         match self.nodes.get_mut(&node_id) {
             Some(node) => {
-                let mut tmp = HashMap::<u64, NodeConnectionData>::new();
-                while let Some(conn) = node.conns.pop() {
-                    tmp.insert(conn.id, conn);
-                }
+                let node = Arc::make_mut(node);
+                // the shared sample ring is only copied when a report brings new samples
+                let mut new_samples = vec![];
                 for conn in conns {
-                    match tmp.get_mut(&conn.id) {
-                        Some(conn_tmp) => {
-                            conn_tmp.metric = conn.metric;
-                            conn_tmp.status = conn.status;
-                            conn_tmp.last_updated_at = conn.last_updated_at;
-                        }
-                        None => {
-                            tmp.insert(conn.id, conn.clone());
-                        }
+                    let old = node.conns.get(conn.id);
+                    // retransmitted reports may arrive after newer ones
+                    if old.is_some_and(|old| old.last_updated_at > conn.last_updated_at) {
+                        continue;
+                    }
+                    if old.iter().all(|old| old.last_updated_at < conn.last_updated_at) {
+                        new_samples.push(ConnectionSample {
+                            ts: conn.last_updated_at,
+                            src: node_id,
+                            dst: conn.node_id,
+                            conn_id: conn.id,
+                            protocol: conn.protocol,
+                            direction: conn.direction,
+                            latency: conn.metric.latency,
+                            bandwidth: conn.metric.bandwidth,
+                            loss: conn.metric.loss_percent,
+                            status: conn.status.clone(),
+                        });
                     }
+                    events.append(&mut diff_connection(node_id, old, &conn, &thresholds));
+                    let updated = match old {
+                        Some(old) => NodeConnectionData {
+                            metric: conn.metric,
+                            status: conn.status,
+                            last_updated_at: conn.last_updated_at,
+                            ..old.clone()
+                        },
+                        None => conn,
+                    };
+                    node.conns.insert(updated);
                 }
-                for (_, data) in tmp.iter() {
-                    node.conns.push(data.clone());
+                if !new_samples.is_empty() {
+                    let samples = Arc::make_mut(self.conn_samples.entry(node_id).or_default());
+                    samples.extend(new_samples);
+                    let overflow = samples.len().saturating_sub(MAX_CONNECTION_SAMPLES);
+                    samples.drain(..overflow);
                 }
             }
             None => {
                 error!("[VisualizationMaster][NodeConnectionStorage] node not found");
             }
         };
+        events
+    }
+
+    /// Delete connections the agent reported as removed
+    pub fn remove_node_connections(&mut self, node_id: NodeId, ids: &[u64]) -> Vec<TopologyEvent> {
+        let mut events = vec![];
+        match self.nodes.get_mut(&node_id) {
+            Some(node) => {
+                if !ids.iter().any(|id| node.conns.get(*id).is_some()) {
+                    return events;
+                }
+                let node = Arc::make_mut(node);
+                for id in ids {
+                    if let Some(conn) = node.conns.remove(*id) {
+                        events.push(TopologyEvent::LinkRemoved {
+                            node_id,
+                            conn_id: conn.id,
+                            dest: conn.node_id,
+                        });
+                    }
+                }
+            }
+            None => {
+                error!("[VisualizationMaster][NodeConnectionStorage] node not found");
+            }
+        }
+        events
+    }
+
+    /// Replace the node by a copy replicated from another master if that copy is newer, last writer wins
+    pub fn merge_replicated_node(&mut self, node: NodeData, now_ms: u64) -> Vec<TopologyEvent> {
+        let mut events = vec![];
+        let node_id = node.id;
+        let old = self.nodes.get(&node_id);
+        if old.is_some_and(|old| old.version() >= node.version()) {
+            return events;
+        }
+        if now_ms.saturating_sub(node.last_ping_ts) <= CONNECTION_TIMEOUT_MS && self.online_nodes.insert(node_id).is_none() {
+            events.push(TopologyEvent::NodeJoined(node_id));
+        }
+        let old_conns = old.map(|old| old.conns.clone()).unwrap_or_default();
+        for conn in node.conns.iter() {
+            events.append(&mut diff_connection(node_id, old_conns.get(conn.id), conn, &self.alert_thresholds));
+        }
+        for conn in old_conns.iter() {
+            if node.conns.get(conn.id).is_none() {
+                events.push(TopologyEvent::LinkRemoved {
+                    node_id,
+                    conn_id: conn.id,
+                    dest: conn.node_id,
+                });
+            }
+        }
+        self.nodes.insert(node_id, Arc::new(node));
+        events
+    }
+
+    /// Connection samples of the snapshot matching `filter`, oldest first per reporting node
+    pub fn connection_samples(&self, filter: SampleFilter) -> ConnectionSamples {
+        let mut node_ids: Vec<&NodeId> = self.conn_samples.keys().collect();
+        node_ids.sort_unstable();
+        ConnectionSamples::new(node_ids.into_iter().map(|node_id| self.conn_samples[node_id].clone()).collect(), filter)
+    }
+
+    pub fn update_node_labels(&mut self, node_id: NodeId, labels: BTreeMap<String, String>) {
+        match self.nodes.get_mut(&node_id) {
+            Some(node) => Arc::make_mut(node).labels = labels,
+            None => {
+                error!("[VisualizationMaster][NodeConnectionStorage] node not found");
+            }
+        }
+    }
+
+    pub fn update_node_dropped_reports(&mut self, node_id: NodeId, count: u64) {
+        match self.nodes.get_mut(&node_id) {
+            Some(node) => {
+                if count > node.dropped_reports {
+                    Arc::make_mut(node).dropped_reports = count;
+                }
+            }
+            None => {
+                error!("[VisualizationMaster][NodeConnectionStorage] node not found");
+            }
+        }
+    }
+
+    pub fn add_host_stats(&mut self, node_id: NodeId, ts: u64, stats: HostStats) {
+        if !self.nodes.contains_key(&node_id) {
+            error!("[VisualizationMaster][NodeConnectionStorage] node not found");
+            return;
+        }
+        let series = self.host_stats.entry(node_id).or_default();
+        if let Some(last) = series.back() {
+            if last.ts >= ts {
+                return;
+            }
+        }
+        let series = Arc::make_mut(series);
+        series.push_back(HostStatsSample { ts, stats });
+        while series.len() > MAX_HOST_STATS_SAMPLES {
+            series.pop_front();
+        }
+    }
+
+    pub fn update_node_app_metrics(&mut self, node_id: NodeId, ts: u64, metrics: Vec<AppMetric>) {
+        if !self.nodes.contains_key(&node_id) {
+            error!("[VisualizationMaster][NodeConnectionStorage] node not found");
+            return;
+        }
+        let current = self.app_metrics.entry(node_id).or_default();
+        if ts >= current.ts {
+            *current = Arc::new(NodeAppMetrics { ts, metrics });
+        }
+    }
+
+    pub fn get_node_app_metrics(&self, node_id: NodeId) -> Option<NodeAppMetrics> {
+        if !self.nodes.contains_key(&node_id) {
+            return None;
+        }
+        Some(self.app_metrics.get(&node_id).map(|current| current.as_ref().clone()).unwrap_or_default())
     }
 
     pub fn list_node(&self) -> Vec<NodeData> {
-        self.nodes.values().into_iter().map(|data| data.clone()).collect()
+        self.nodes.values().map(|data| data.as_ref().clone()).collect()
+    }
+
+    /// List nodes without deep cloning them
+    pub fn list_node_shared(&self) -> Vec<Arc<NodeData>> {
+        self.nodes.values().cloned().collect()
+    }
+
+    pub fn list_node_where<F: Fn(&NodeData) -> bool>(&self, predicate: F) -> Vec<NodeData> {
+        self.nodes.values().filter(|data| predicate(data)).map(|data| data.as_ref().clone()).collect()
+    }
+
+    pub fn query_nodes(&self, query: &NodeListQuery, now_ms: u64) -> NodeListPage {
+        query.apply(self.nodes.values().map(|data| data.as_ref()), now_ms)
+    }
+
+    pub fn neighbors(&self, node_id: NodeId) -> Vec<NodeId> {
+        graph::neighbors(&self.nodes, node_id)
+    }
+
+    pub fn edge(&self, a: NodeId, b: NodeId) -> Vec<NodeConnectionData> {
+        graph::edge(&self.nodes, a, b)
+    }
+
+    pub fn worst_links(&self, n: usize, by_metric: LinkMetric) -> Vec<Link> {
+        graph::worst_links(&self.nodes, n, by_metric)
+    }
+
+    pub fn shortest_path(&self, a: NodeId, b: NodeId) -> Option<Vec<NodeId>> {
+        graph::shortest_path(&self.nodes, a, b)
+    }
+
+    pub fn export(&self, format: ExportFormat, scope: Option<ExportScope>) -> String {
+        export::export(&self.nodes, format, scope)
     }
 
     pub fn get_node(&self, id: NodeId) -> Option<NodeData> {
         match self.nodes.get(&id) {
-            Some(node) => Some(node.clone()),
+            Some(node) => Some(node.as_ref().clone()),
             None => None,
         }
     }
 
+    pub fn get_node_shared(&self, id: NodeId) -> Option<Arc<NodeData>> {
+        self.nodes.get(&id).cloned()
+    }
+
+    pub fn get_node_detail(&self, id: NodeId) -> Option<NodeDetail> {
+        let node = self.nodes.get(&id)?;
+        let host_stats = match self.host_stats.get(&id) {
+            Some(series) => series.iter().cloned().collect(),
+            None => vec![],
+        };
+        let app_metrics = match self.app_metrics.get(&id) {
+            Some(current) => current.metrics.clone(),
+            None => vec![],
+        };
+        Some(NodeDetail {
+            node: node.as_ref().clone(),
+            host_stats,
+            app_metrics,
+        })
+    }
+
     pub fn count_node(&self) -> usize {
         self.nodes.len()
     }
+
+    pub fn merge_region_summary(&mut self, summary: RegionSummary) {
+        match self.regions.get_mut(&summary.region) {
+            Some(current) => Arc::make_mut(current).merge(summary),
+            None => {
+                self.regions.insert(summary.region.clone(), Arc::new(summary));
+            }
+        }
+    }
+
+    pub fn merge_region_detail(&mut self, detail: RegionDetail) {
+        match self.region_details.get_mut(&detail.region) {
+            Some(current) => Arc::make_mut(current).merge(detail),
+            None => {
+                self.region_details.insert(detail.region.clone(), Arc::new(detail));
+            }
+        }
+    }
+
+    pub fn list_regions(&self) -> Vec<RegionSummary> {
+        let mut regions: Vec<RegionSummary> = self.regions.values().map(|region| region.as_ref().clone()).collect();
+        regions.sort_by(|a, b| a.region.cmp(&b.region));
+        regions
+    }
+
+    pub fn get_region(&self, region: &str) -> Option<RegionSummary> {
+        self.regions.get(region).map(|region| region.as_ref().clone())
+    }
+
+    pub fn get_region_detail(&self, region: &str) -> Option<RegionDetail> {
+        self.region_details.get(region).map(|detail| detail.as_ref().clone())
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::identity::AppMetricValue;
+
     use super::*;
 
     #[test]
@@ -137,7 +529,7 @@ mod test {
         storage.upsert_node(node_id.clone(), addr.clone(), last_ping_ts);
 
         assert_eq!(storage.nodes.len(), 1);
-        assert_eq!(storage.nodes.get(&node_id), Some(&NodeData::new(node_id, addr, last_ping_ts)));
+        assert_eq!(storage.nodes.get(&node_id), Some(&Arc::new(NodeData::new(node_id, addr, last_ping_ts))));
     }
 
     #[test]
@@ -152,7 +544,7 @@ mod test {
         storage.upsert_node(node_id.clone(), addr.clone(), last_ping_ts2);
 
         assert_eq!(storage.nodes.len(), 1);
-        assert_eq!(storage.nodes.get(&node_id), Some(&NodeData::new(node_id, addr, last_ping_ts2)));
+        assert_eq!(storage.nodes.get(&node_id), Some(&Arc::new(NodeData::new(node_id, addr, last_ping_ts2))));
     }
 
     #[test]
@@ -168,7 +560,7 @@ mod test {
         storage.upsert_node(node_id.clone(), addr.clone(), last_ping_ts1);
 
         assert_eq!(storage.nodes.len(), 1);
-        assert_eq!(storage.nodes.get(&node_id), Some(&NodeData::new(node_id, addr, last_ping_ts2)));
+        assert_eq!(storage.nodes.get(&node_id), Some(&Arc::new(NodeData::new(node_id, addr, last_ping_ts2))));
     }
 
     #[test]
@@ -213,15 +605,45 @@ mod test {
         assert_eq!(storage.nodes.len(), 1);
         assert_eq!(
             storage.nodes.get(&node_id),
-            Some(&NodeData {
+            Some(&Arc::new(NodeData {
                 id: node_id,
                 addr: addr.clone(),
                 last_ping_ts,
-                conns: vec![conn2],
-            })
+                labels: BTreeMap::new(),
+                dropped_reports: 0,
+                conns: NodeConnections::from(vec![conn2]),
+            }))
         );
     }
 
+    #[test]
+    fn test_update_node_connection_keeps_order_and_ignores_stale_reports() {
+        let mut storage = NodeConnectionStorage::new();
+        let conn = |id: u64, latency: u16| NodeConnectionData {
+            id,
+            node_id: 2,
+            protocol: 1,
+            addr: String::from("127.0.0.1"),
+            metric: ConnectionMetric {
+                latency,
+                loss_percent: 0,
+                bandwidth: 100,
+            },
+            status: ConnectionStatus::CONNECTED,
+            last_updated_at: 1,
+            direction: 0,
+        };
+
+        storage.upsert_node(1, String::from("127.0.0.1"), 0);
+        storage.update_node_connection(1, vec![conn(3, 1), conn(1, 1)]);
+        storage.update_node_connection(1, vec![conn(2, 1), conn(3, 5)]);
+        storage.update_node_connection(1, vec![NodeConnectionData { last_updated_at: 0, ..conn(3, 9) }]);
+
+        let node = storage.get_node(1).expect("should have node");
+        let ids: Vec<(u64, u16)> = node.conns.iter().map(|conn| (conn.id, conn.metric.latency)).collect();
+        assert_eq!(ids, vec![(1, 1), (2, 1), (3, 5)]);
+    }
+
     #[test]
     fn test_update_node_connection_removes_all_existing_transport_data_if_address_present_and_connections_empty() {
         let mut storage = NodeConnectionStorage::new();
@@ -250,13 +672,217 @@ mod test {
         assert_eq!(storage.nodes.len(), 1);
         assert_eq!(
             storage.nodes.get(&node_id),
-            Some(&NodeData {
+            Some(&Arc::new(NodeData {
                 id: node_id,
                 addr: addr.clone(),
                 last_ping_ts,
-                conns: vec![conn],
-            })
+                labels: BTreeMap::new(),
+                dropped_reports: 0,
+                conns: NodeConnections::from(vec![conn]),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_update_node_connection_records_samples_once_per_report() {
+        let mut storage = NodeConnectionStorage::new();
+        let conn = |dest: NodeId, last_updated_at: u64| NodeConnectionData {
+            id: dest as u64,
+            node_id: dest,
+            protocol: 1,
+            addr: String::from("127.0.0.1"),
+            metric: ConnectionMetric {
+                latency: last_updated_at as u16,
+                loss_percent: 0,
+                bandwidth: 100,
+            },
+            status: ConnectionStatus::CONNECTED,
+            last_updated_at,
+            direction: 0,
+        };
+
+        storage.upsert_node(2, String::from("127.0.0.2"), 0);
+        storage.upsert_node(1, String::from("127.0.0.1"), 0);
+        storage.update_node_connection(2, vec![conn(1, 10)]);
+        storage.update_node_connection(1, vec![conn(2, 10), conn(3, 10)]);
+        // retransmitted and late reports are not sampled again
+        storage.update_node_connection(1, vec![conn(2, 10), conn(3, 5)]);
+        storage.update_node_connection(1, vec![conn(2, 20)]);
+
+        let samples: Vec<(NodeId, NodeId, u64)> = storage.connection_samples(SampleFilter::default()).map(|sample| (sample.src, sample.dst, sample.ts)).collect();
+        assert_eq!(samples, vec![(1, 2, 10), (1, 3, 10), (1, 2, 20), (2, 1, 10)]);
+
+        let snapshot = storage.clone();
+        // a report without new samples leaves the ring shared with the snapshot
+        storage.update_node_connection(1, vec![conn(2, 20)]);
+        assert!(Arc::ptr_eq(&storage.conn_samples[&1], &snapshot.conn_samples[&1]));
+        for ts in 21..(21 + MAX_CONNECTION_SAMPLES as u64) {
+            storage.update_node_connection(1, vec![conn(2, ts)]);
+        }
+        assert_eq!(
+            storage
+                .connection_samples(SampleFilter {
+                    nodes: Some([3].into()),
+                    ..Default::default()
+                })
+                .count(),
+            0
         );
+        assert_eq!(snapshot.connection_samples(SampleFilter::default()).count(), 4);
+    }
+
+    #[test]
+    fn test_remove_node_connections_deletes_and_emits_link_removed() {
+        let mut storage = NodeConnectionStorage::new();
+        let conn = |id: u64| NodeConnectionData {
+            id,
+            node_id: 2,
+            protocol: 1,
+            addr: String::from("127.0.0.1"),
+            metric: ConnectionMetric {
+                latency: 1,
+                loss_percent: 0,
+                bandwidth: 100,
+            },
+            status: ConnectionStatus::DISCONNECTED,
+            last_updated_at: 0,
+            direction: 0,
+        };
+
+        storage.upsert_node(1, String::from("127.0.0.1"), 0);
+        storage.update_node_connection(1, vec![conn(1), conn(2)]);
+
+        assert_eq!(storage.remove_node_connections(1, &[1, 3]), vec![TopologyEvent::LinkRemoved { node_id: 1, conn_id: 1, dest: 2 }]);
+        assert_eq!(storage.remove_node_connections(1, &[1]), vec![]);
+        let ids: Vec<u64> = storage.get_node(1).expect("should have node").conns.iter().map(|conn| conn.id).collect();
+        assert_eq!(ids, vec![2]);
+    }
+
+    #[test]
+    fn test_merge_replicated_node_keeps_newest_copy() {
+        let mut storage = NodeConnectionStorage::new();
+        let conn = |id: u64, last_updated_at: u64| NodeConnectionData {
+            id,
+            node_id: 2,
+            protocol: 1,
+            addr: String::from("127.0.0.1"),
+            metric: ConnectionMetric {
+                latency: 1,
+                loss_percent: 0,
+                bandwidth: 100,
+            },
+            status: ConnectionStatus::CONNECTED,
+            last_updated_at,
+            direction: 0,
+        };
+        let mut replica = NodeData::new(1, String::from("127.0.0.1"), 1000);
+        replica.conns = vec![conn(1, 1000), conn(2, 1000)].into();
+
+        let events = storage.merge_replicated_node(replica.clone(), 1000);
+        assert_eq!(events.first(), Some(&TopologyEvent::NodeJoined(1)));
+        assert_eq!(storage.get_node(1), Some(replica.clone()));
+        assert_eq!(storage.get_node(1).map(|node| node.conns.to_vec()), Some(vec![conn(1, 1000), conn(2, 1000)]));
+
+        // an older copy is ignored
+        let stale = NodeData::new(1, String::from("127.0.0.1"), 500);
+        assert_eq!(storage.merge_replicated_node(stale, 1000), vec![]);
+        assert_eq!(storage.get_node(1), Some(replica.clone()));
+
+        // a newer copy replaces the node, including its removed connections
+        let mut newer = NodeData::new(1, String::from("127.0.0.1"), 2000);
+        newer.conns = vec![conn(1, 2000)].into();
+        assert_eq!(storage.merge_replicated_node(newer.clone(), 2000), vec![TopologyEvent::LinkRemoved { node_id: 1, conn_id: 2, dest: 2 }]);
+        assert_eq!(storage.get_node(1), Some(newer));
+    }
+
+    #[test]
+    fn test_upsert_node_and_check_offline_emit_join_and_leave_events() {
+        let mut storage = NodeConnectionStorage::new();
+        let addr = String::from("127.0.0.1");
+
+        assert_eq!(storage.upsert_node(1, addr.clone(), 0), vec![TopologyEvent::NodeJoined(1)]);
+        assert_eq!(storage.upsert_node(1, addr.clone(), 1000), vec![]);
+        assert_eq!(storage.check_offline_nodes(1000 + CONNECTION_TIMEOUT_MS), vec![]);
+        assert_eq!(storage.check_offline_nodes(1001 + CONNECTION_TIMEOUT_MS), vec![TopologyEvent::NodeLeft(1)]);
+        assert_eq!(storage.check_offline_nodes(1002 + CONNECTION_TIMEOUT_MS), vec![]);
+        assert_eq!(storage.upsert_node(1, addr, 2000 + CONNECTION_TIMEOUT_MS), vec![TopologyEvent::NodeJoined(1)]);
+    }
+
+    #[test]
+    fn test_update_node_labels_replaces_labels_and_filters_by_predicate() {
+        let mut storage = NodeConnectionStorage::new();
+        storage.upsert_node(1, String::from("127.0.0.1"), 0);
+        storage.upsert_node(2, String::from("127.0.0.2"), 0);
+        storage.update_node_labels(1, BTreeMap::from([(String::from("region"), String::from("eu"))]));
+        storage.update_node_labels(1, BTreeMap::from([(String::from("region"), String::from("us"))]));
+        storage.update_node_labels(3, BTreeMap::from([(String::from("region"), String::from("eu"))]));
+
+        let nodes = storage.list_node_where(|node| node.labels.get("region").map(|region| region.as_str()) == Some("us"));
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].id, 1);
+        assert_eq!(storage.count_node(), 2);
+    }
+
+    #[test]
+    fn test_add_host_stats_keeps_ordered_series_with_max_samples() {
+        let mut storage = NodeConnectionStorage::new();
+        let node_id = 1;
+        storage.upsert_node(node_id, String::from("127.0.0.1"), 0);
+        for ts in 0..(MAX_HOST_STATS_SAMPLES as u64 + 10) {
+            storage.add_host_stats(
+                node_id,
+                ts + 1,
+                HostStats {
+                    cpu_percent: ts as u32,
+                    ..Default::default()
+                },
+            );
+        }
+        storage.add_host_stats(node_id, 5, HostStats::default());
+
+        let detail = storage.get_node_detail(node_id).expect("should have node");
+        assert_eq!(detail.host_stats.len(), MAX_HOST_STATS_SAMPLES);
+        assert_eq!(detail.host_stats.first().map(|sample| sample.ts), Some(11));
+        assert_eq!(detail.host_stats.last().map(|sample| sample.ts), Some(MAX_HOST_STATS_SAMPLES as u64 + 10));
+    }
+
+    #[test]
+    fn test_update_node_app_metrics_keeps_latest_report() {
+        let mut storage = NodeConnectionStorage::new();
+        let node_id = 1;
+        let metric = |value| AppMetric {
+            name: String::from("sessions"),
+            value: AppMetricValue::Gauge(value),
+        };
+        storage.upsert_node(node_id, String::from("127.0.0.1"), 0);
+        storage.update_node_app_metrics(node_id, 2, vec![metric(2)]);
+        storage.update_node_app_metrics(node_id, 1, vec![metric(1)]);
+
+        assert_eq!(storage.get_node_app_metrics(node_id), Some(NodeAppMetrics { ts: 2, metrics: vec![metric(2)] }));
+        assert_eq!(storage.get_node_detail(node_id).map(|detail| detail.app_metrics), Some(vec![metric(2)]));
+        assert_eq!(storage.get_node_app_metrics(2), None);
+    }
+
+    #[test]
+    fn test_check_offline_nodes_drops_host_stats_of_left_nodes() {
+        let mut storage = NodeConnectionStorage::new();
+        storage.upsert_node(1, String::from("127.0.0.1"), 0);
+        storage.upsert_node(2, String::from("127.0.0.2"), CONNECTION_TIMEOUT_MS);
+        storage.add_host_stats(1, 0, HostStats::default());
+        storage.add_host_stats(2, CONNECTION_TIMEOUT_MS, HostStats::default());
+
+        assert_eq!(storage.check_offline_nodes(CONNECTION_TIMEOUT_MS + 1), vec![TopologyEvent::NodeLeft(1)]);
+        assert_eq!(storage.get_node_detail(1).map(|detail| detail.host_stats.len()), Some(0));
+        assert_eq!(storage.get_node_detail(2).map(|detail| detail.host_stats.len()), Some(1));
+    }
+
+    #[test]
+    fn test_add_host_stats_does_nothing_if_node_not_present() {
+        let mut storage = NodeConnectionStorage::new();
+        storage.add_host_stats(1, 1, HostStats::default());
+
+        assert_eq!(storage.host_stats.len(), 0);
+        assert_eq!(storage.get_node_detail(1), None);
     }
 
     #[test]
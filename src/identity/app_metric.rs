@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_HISTOGRAM_BUCKETS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1000, 5000];
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct AppHistogram {
+    pub bounds: Vec<u64>, // upper bound of each bucket, an implicit +Inf bucket is appended
+    pub counts: Vec<u64>, // per bucket counts, length is bounds.len() + 1
+    pub count: u64,
+    pub sum: u64,
+}
+
+impl AppHistogram {
+    pub fn new(mut bounds: Vec<u64>) -> Self {
+        bounds.sort_unstable();
+        bounds.dedup();
+        Self {
+            counts: vec![0; bounds.len() + 1],
+            bounds,
+            count: 0,
+            sum: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: u64) {
+        let index = self.bounds.iter().position(|bound| value <= *bound).unwrap_or(self.bounds.len());
+        self.counts[index] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum AppMetricValue {
+    Gauge(i64),
+    Counter(u64),
+    Histogram(AppHistogram),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct AppMetric {
+    pub name: String,
+    pub value: AppMetricValue,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_should_count_into_matching_bucket() {
+        let mut histogram = AppHistogram::new(vec![10, 1, 5]);
+        histogram.observe(0);
+        histogram.observe(5);
+        histogram.observe(7);
+        histogram.observe(100);
+
+        assert_eq!(histogram.bounds, vec![1, 5, 10]);
+        assert_eq!(histogram.counts, vec![1, 1, 1, 1]);
+        assert_eq!(histogram.count, 4);
+        assert_eq!(histogram.sum, 112);
+    }
+}
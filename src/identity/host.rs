@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct HostStats {
+    pub cpu_percent: u32,      // cpu usage since previous sample, in percent
+    pub load_avg_1m: u32,      // load average x100
+    pub load_avg_5m: u32,      // load average x100
+    pub load_avg_15m: u32,     // load average x100
+    pub mem_total_kb: u64,     // total memory in kB
+    pub mem_available_kb: u64, // available memory in kB
+    pub process_rss_kb: u64,   // resident set size of the agent process in kB
+    pub open_fds: u32,         // open file descriptors of the agent process
+    pub uptime_secs: u64,      // host uptime in seconds
+}
@@ -1,8 +1,12 @@
+mod app_metric;
 mod conn;
+mod host;
 
 use serde::{Deserialize, Serialize};
 
+pub use app_metric::*;
 pub use conn::*;
+pub use host::*;
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum ConnectionStatus {
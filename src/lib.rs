@@ -1,7 +1,10 @@
 mod collector;
 mod identity;
 mod services;
+#[cfg(feature = "testing")]
+pub mod testing;
 mod util;
 
 pub use collector::*;
+pub use identity::{AppHistogram, AppMetric, AppMetricValue, ConnectionMetric, ConnectionStatus, HostStats};
 pub use services::*;
@@ -1,4 +1,5 @@
 use std::any::Any;
+use std::collections::BTreeMap;
 
 use atm0s_sdn_identity::{NodeAddr, NodeId};
 use atm0s_sdn_network::behaviour::{BehaviorContext, ConnectionHandler, NetworkBehavior, NetworkBehaviorAction};
@@ -7,37 +8,112 @@ use atm0s_sdn_network::transport::{ConnectionRejectReason, ConnectionSender, Out
 use atm0s_sdn_router::RouteRule;
 use atm0s_sdn_utils::vec_dequeue::VecDeque;
 
-use crate::services::master::VISUALIZATION_MASTER_SERVICE;
+use crate::services::codec::{compact_agent_msg, encode_agent_msg};
+use crate::services::master::{VisualizationMasterMsg, VISUALIZATION_MASTER_SERVICE};
 
+use super::delivery::AgentAckConf;
 use super::handler::VisualizationAgentHandler;
 use super::logic::VisualizationAgentLogic;
-use super::msg::{VisualizationAgentBehaviourEvent, VisualizationAgentHandlerEvent};
+use super::msg::{
+    AgentQueuePolicy, VisualizationAgentBehaviourEvent, VisualizationAgentHandlerEvent, VisualizationAgentSdkEvent, DEFAULT_AGENT_QUEUE_LIMIT, DEFAULT_CONN_GC_TIMEOUT_MS, DEFAULT_MASTER_TIMEOUT_MS,
+    DEFAULT_MAX_REPORT_BYTES, MAX_CONN_STATS_SEND,
+};
+use super::sdk::VisualizationAgentSdk;
 use super::VISUALIZATION_AGENT_SERVICE;
 
 pub struct VisualizationAgentBehaviourConf {
     pub node_id: NodeId,
     pub node_addr: NodeAddr,
+    /// Free-form labels such as region, zone, role, build version or hostname
+    pub labels: BTreeMap<String, String>,
+    /// Include host metrics read from /proc in each ping, requires the `host-stats` feature on linux
+    pub report_host_stats: bool,
+    /// Max reports waiting to be sent, retransmits included, see `DEFAULT_AGENT_QUEUE_LIMIT`
+    pub queue_limit: usize,
+    pub queue_policy: AgentQueuePolicy,
+    /// Number reports and retransmit them until the master acks, disabled when None
+    pub ack: Option<AgentAckConf>,
+    /// Compress report payloads larger than `COMPRESS_MIN_BYTES`, flagged in the header meta. Masters without
+    /// compression support drop such reports, so only enable it once every master is upgraded.
+    pub compress: bool,
+    /// Send connection reports with a dictionary of the repeated addresses, same upgrade order as `compress`
+    pub dedup_addrs: bool,
+    /// Max connections per connections report
+    pub max_conns_per_report: usize,
+    /// Max encoded size of a connections report, keep it under the transport MTU
+    pub max_report_bytes: usize,
+    /// Disconnected connections are removed and reported as tombstones after this delay
+    pub conn_gc_timeout_ms: u64,
+    /// Without a master heartbeat for this long the agent fails over to another known master
+    pub master_timeout_ms: u64,
+}
+
+impl VisualizationAgentBehaviourConf {
+    /// Conf without labels and host stats, with the default queue, batching and no ack, compression or dedup
+    pub fn new(node_id: NodeId, node_addr: NodeAddr) -> Self {
+        Self {
+            node_id,
+            node_addr,
+            labels: BTreeMap::new(),
+            report_host_stats: false,
+            queue_limit: DEFAULT_AGENT_QUEUE_LIMIT,
+            queue_policy: AgentQueuePolicy::default(),
+            ack: None,
+            compress: false,
+            dedup_addrs: false,
+            max_conns_per_report: MAX_CONN_STATS_SEND,
+            max_report_bytes: DEFAULT_MAX_REPORT_BYTES,
+            conn_gc_timeout_ms: DEFAULT_CONN_GC_TIMEOUT_MS,
+            master_timeout_ms: DEFAULT_MASTER_TIMEOUT_MS,
+        }
+    }
 }
 
 pub struct VisualizationAgentBehaviour<HE, SE> {
     logic: VisualizationAgentLogic,
+    compress: bool,
+    dedup_addrs: bool,
+    sdk: VisualizationAgentSdk,
     queue_action: VecDeque<NetworkBehaviorAction<HE, SE>>,
 }
 
 impl<HE, SE> VisualizationAgentBehaviour<HE, SE> {
-    pub fn new(conf: VisualizationAgentBehaviourConf) -> Self {
-        Self {
-            logic: VisualizationAgentLogic::new(conf.node_id, conf.node_addr),
+    pub fn new(conf: VisualizationAgentBehaviourConf) -> (Self, VisualizationAgentSdk) {
+        let sdk = VisualizationAgentSdk::new();
+        let behaviour = Self {
+            compress: conf.compress,
+            dedup_addrs: conf.dedup_addrs,
+            logic: VisualizationAgentLogic::new(conf),
+            sdk: sdk.clone(),
             queue_action: VecDeque::new(),
+        };
+        (behaviour, sdk)
+    }
+
+    fn process_sdk_events(&mut self) {
+        while let Some(event) = self.sdk.pop_event() {
+            self.logic.on_sdk_event(event);
         }
     }
 
+    /// Reports dropped because the outgoing queue was full, since start
+    pub fn dropped_reports(&self) -> u64 {
+        self.logic.dropped_reports()
+    }
+
     pub fn process_all_msg(&mut self) {
+        let route = match self.logic.master_target() {
+            Some(master) => RouteRule::ToNode(master),
+            None => RouteRule::ToService(VISUALIZATION_MASTER_SERVICE as u32),
+        };
         while let Some(msg) = self.logic.pop_msg() {
-            let header = MsgHeader::new()
-                .set_to_service_id(VISUALIZATION_MASTER_SERVICE)
-                .set_route(RouteRule::ToService(VISUALIZATION_MASTER_SERVICE as u32));
-            let action = TransportMsg::from_payload_bincode(header, &msg);
+            let header = MsgHeader::new().set_to_service_id(VISUALIZATION_MASTER_SERVICE).set_route(route.clone());
+            let msg = if self.dedup_addrs {
+                compact_agent_msg(msg)
+            } else {
+                msg
+            };
+            let action = encode_agent_msg(header, &msg, self.compress);
             self.queue_action.push_back(NetworkBehaviorAction::ToNet(action))
         }
     }
@@ -47,36 +123,54 @@ impl<BE, HE, SE> NetworkBehavior<BE, HE, SE> for VisualizationAgentBehaviour<HE,
 where
     BE: From<VisualizationAgentBehaviourEvent> + TryInto<VisualizationAgentBehaviourEvent> + Send + Sync + 'static,
     HE: From<VisualizationAgentHandlerEvent> + TryInto<VisualizationAgentHandlerEvent> + Send + Sync + 'static,
-    SE: Send + Sync + 'static,
+    SE: TryInto<VisualizationAgentSdkEvent> + Send + Sync + 'static,
 {
     fn service_id(&self) -> u8 {
         return VISUALIZATION_AGENT_SERVICE;
     }
 
     fn on_started(&mut self, ctx: &BehaviorContext, now_ms: u64) {
+        self.sdk.set_awaker(ctx.awaker.clone());
         self.logic.report_stats(now_ms)
     }
 
-    fn on_awake(&mut self, ctx: &BehaviorContext, now_ms: u64) {}
+    fn on_awake(&mut self, ctx: &BehaviorContext, now_ms: u64) {
+        self.process_sdk_events();
+    }
 
     fn on_tick(&mut self, ctx: &BehaviorContext, now_ms: u64, interval_ms: u64) {
+        self.process_sdk_events();
         self.process_all_msg();
         self.logic.report_stats(now_ms);
+        self.sdk.set_delivery_stats(self.logic.delivery_stats());
+        self.sdk.set_master_health(self.logic.master_health());
     }
 
-    fn on_local_msg(&mut self, ctx: &BehaviorContext, now_ms: u64, msg: TransportMsg) {}
+    fn on_local_msg(&mut self, ctx: &BehaviorContext, now_ms: u64, msg: TransportMsg) {
+        match msg.get_payload_bincode::<VisualizationMasterMsg>() {
+            Ok(VisualizationMasterMsg::ReportAck(seq)) => self.logic.on_report_ack(seq),
+            Ok(VisualizationMasterMsg::Heartbeat(status)) => self.logic.on_master_heartbeat(status, now_ms),
+            Err(_e) => {}
+        }
+    }
 
     fn on_handler_event(&mut self, ctx: &BehaviorContext, now_ms: u64, node_id: NodeId, conn_id: atm0s_sdn_identity::ConnId, event: BE) {
         let msg: Result<VisualizationAgentBehaviourEvent, _> = event.try_into();
         match msg {
             Ok(msg) => match msg {
                 VisualizationAgentBehaviourEvent::ConnectionStats(conn_id, node_id, metric) => self.logic.on_connection_stats(conn_id, node_id, metric, now_ms),
+                VisualizationAgentBehaviourEvent::ReportAck(seq) => self.logic.on_report_ack(seq),
+                VisualizationAgentBehaviourEvent::MasterHeartbeat(status) => self.logic.on_master_heartbeat(status, now_ms),
             },
             Err(_e) => {}
         }
     }
 
-    fn on_sdk_msg(&mut self, ctx: &BehaviorContext, now_ms: u64, from_service: u8, event: SE) {}
+    fn on_sdk_msg(&mut self, ctx: &BehaviorContext, now_ms: u64, from_service: u8, event: SE) {
+        if let Ok(event) = event.try_into() {
+            self.logic.on_sdk_event(event);
+        }
+    }
 
     fn check_incoming_connection(&mut self, ctx: &BehaviorContext, now_ms: u64, node: NodeId, conn_id: atm0s_sdn_identity::ConnId) -> Result<(), ConnectionRejectReason> {
         Ok(())
@@ -0,0 +1,231 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    mem::discriminant,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::msg::VisualizationAgentMsg;
+
+/// Settings of the optional acknowledged delivery mode
+#[derive(Debug, Clone)]
+pub struct AgentAckConf {
+    /// Delay before the first retransmit, doubled on each attempt
+    pub retry_base_ms: u64,
+    pub retry_max_ms: u64,
+    /// Retransmits before a report is counted as lost
+    pub max_retries: u32,
+    /// Max reports waiting for an ack, the oldest is counted as lost when exceeded
+    pub max_pending: usize,
+}
+
+impl Default for AgentAckConf {
+    fn default() -> Self {
+        Self {
+            retry_base_ms: 1000,
+            retry_max_ms: 16000,
+            max_retries: 5,
+            max_pending: 256,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DeliveryStats {
+    pub sent: u64,
+    pub acked: u64,
+    pub retried: u64,
+    pub lost: u64,
+}
+
+struct PendingReport {
+    msg: VisualizationAgentMsg,
+    attempts: u32,
+    next_retry_at: u64,
+}
+
+/// Sequence numbering, ack tracking and retransmit with backoff of agent reports.
+pub struct ReliableDelivery {
+    conf: AgentAckConf,
+    next_seq: u64,
+    pending: BTreeMap<u64, PendingReport>,
+    retransmit: VecDeque<VisualizationAgentMsg>,
+    stats: DeliveryStats,
+}
+
+impl ReliableDelivery {
+    pub fn new(conf: AgentAckConf) -> Self {
+        Self {
+            conf,
+            next_seq: 1,
+            pending: BTreeMap::new(),
+            retransmit: VecDeque::new(),
+            stats: DeliveryStats::default(),
+        }
+    }
+
+    fn backoff(&self, attempts: u32) -> u64 {
+        self.conf.retry_base_ms.saturating_mul(1 << attempts.min(16)).min(self.conf.retry_max_ms)
+    }
+
+    /// Assign a sequence number and wait for its ack. Connection reports and tombstones are split in batches and each
+    /// batch is retried on its own, other kinds are full state so a newer report replaces the pending one of the same kind.
+    pub fn track(&mut self, msg: VisualizationAgentMsg, now_ms: u64) -> VisualizationAgentMsg {
+        if !matches!(msg, VisualizationAgentMsg::NodeConnections(..) | VisualizationAgentMsg::NodeConnectionsRemoved(..)) {
+            let kind = discriminant(&msg);
+            self.pending.retain(|_, pending| discriminant(&pending.msg) != kind);
+        }
+        if self.pending.len() >= self.conf.max_pending.max(1) {
+            self.pending.pop_first();
+            self.stats.lost += 1;
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.stats.sent += 1;
+        self.pending.insert(
+            seq,
+            PendingReport {
+                msg: msg.clone(),
+                attempts: 0,
+                next_retry_at: now_ms + self.backoff(0),
+            },
+        );
+        VisualizationAgentMsg::Reliable(seq, Box::new(msg))
+    }
+
+    pub fn on_ack(&mut self, seq: u64) {
+        if self.pending.remove(&seq).is_some() {
+            self.stats.acked += 1;
+        }
+    }
+
+    /// Queue due retransmits, at most `max_retransmit` waiting at once, and give up on reports which exceeded
+    /// `max_retries`. Due reports which do not fit stay due until a later tick.
+    pub fn on_tick(&mut self, now_ms: u64, max_retransmit: usize) {
+        let due: Vec<u64> = self.pending.iter().filter(|(_, pending)| pending.next_retry_at <= now_ms).map(|(seq, _)| *seq).collect();
+        for seq in due {
+            let attempts = match self.pending.get(&seq) {
+                Some(pending) => pending.attempts + 1,
+                None => continue,
+            };
+            if attempts > self.conf.max_retries {
+                self.pending.remove(&seq);
+                self.stats.lost += 1;
+                continue;
+            }
+            if self.retransmit.len() >= max_retransmit {
+                continue;
+            }
+            let next_retry_at = now_ms + self.backoff(attempts);
+            if let Some(pending) = self.pending.get_mut(&seq) {
+                pending.attempts = attempts;
+                pending.next_retry_at = next_retry_at;
+                self.retransmit.push_back(VisualizationAgentMsg::Reliable(seq, Box::new(pending.msg.clone())));
+                self.stats.retried += 1;
+            }
+        }
+    }
+
+    /// Retransmits waiting to be sent
+    pub fn retransmit_len(&self) -> usize {
+        self.retransmit.len()
+    }
+
+    pub fn pop_retransmit(&mut self) -> Option<VisualizationAgentMsg> {
+        self.retransmit.pop_front()
+    }
+
+    pub fn stats(&self) -> DeliveryStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ping(ts: u64) -> VisualizationAgentMsg {
+        VisualizationAgentMsg::NodePing(1, String::from("127.0.0.1"), ts)
+    }
+
+    fn conns() -> VisualizationAgentMsg {
+        VisualizationAgentMsg::NodeConnections(1, vec![])
+    }
+
+    fn conf() -> AgentAckConf {
+        AgentAckConf {
+            retry_base_ms: 100,
+            retry_max_ms: 300,
+            max_retries: 2,
+            max_pending: 8,
+        }
+    }
+
+    #[test]
+    fn should_number_reports_and_count_acks() {
+        let mut delivery = ReliableDelivery::new(conf());
+        assert_eq!(delivery.track(conns(), 0), VisualizationAgentMsg::Reliable(1, Box::new(conns())));
+        assert_eq!(delivery.track(conns(), 0), VisualizationAgentMsg::Reliable(2, Box::new(conns())));
+
+        delivery.on_ack(1);
+        delivery.on_ack(1);
+        delivery.on_tick(100, usize::MAX);
+        assert_eq!(delivery.pop_retransmit(), Some(VisualizationAgentMsg::Reliable(2, Box::new(conns()))));
+        assert_eq!(delivery.pop_retransmit(), None);
+        assert_eq!(
+            delivery.stats(),
+            DeliveryStats {
+                sent: 2,
+                acked: 1,
+                retried: 1,
+                lost: 0
+            }
+        );
+    }
+
+    #[test]
+    fn should_keep_due_reports_which_do_not_fit_for_later() {
+        let mut delivery = ReliableDelivery::new(conf());
+        delivery.track(conns(), 0);
+        delivery.track(conns(), 0);
+
+        delivery.on_tick(100, 1);
+        assert_eq!(delivery.retransmit_len(), 1);
+        assert_eq!(delivery.pop_retransmit(), Some(VisualizationAgentMsg::Reliable(1, Box::new(conns()))));
+        delivery.on_tick(101, 1);
+        assert_eq!(delivery.pop_retransmit(), Some(VisualizationAgentMsg::Reliable(2, Box::new(conns()))));
+        assert_eq!(delivery.stats().retried, 2);
+    }
+
+    #[test]
+    fn should_retry_with_backoff_then_count_lost() {
+        let mut delivery = ReliableDelivery::new(conf());
+        delivery.track(conns(), 0);
+
+        // retries at 100 then 100 + 200, gives up at 300 + 300
+        delivery.on_tick(99, usize::MAX);
+        assert_eq!(delivery.pop_retransmit(), None);
+        delivery.on_tick(100, usize::MAX);
+        assert!(delivery.pop_retransmit().is_some());
+        delivery.on_tick(299, usize::MAX);
+        assert_eq!(delivery.pop_retransmit(), None);
+        delivery.on_tick(300, usize::MAX);
+        assert!(delivery.pop_retransmit().is_some());
+        delivery.on_tick(600, usize::MAX);
+        assert_eq!(delivery.pop_retransmit(), None);
+        assert_eq!(delivery.stats().retried, 2);
+        assert_eq!(delivery.stats().lost, 1);
+    }
+
+    #[test]
+    fn newer_full_state_report_should_replace_pending_one() {
+        let mut delivery = ReliableDelivery::new(conf());
+        delivery.track(ping(0), 0);
+        delivery.track(ping(1), 0);
+
+        delivery.on_tick(100, usize::MAX);
+        assert_eq!(delivery.pop_retransmit(), Some(VisualizationAgentMsg::Reliable(2, Box::new(ping(1)))));
+        assert_eq!(delivery.pop_retransmit(), None);
+        assert_eq!(delivery.stats().lost, 0);
+    }
+}
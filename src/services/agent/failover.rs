@@ -0,0 +1,164 @@
+use atm0s_sdn_identity::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::MasterStatus;
+
+/// What the agent knows about the masters receiving its reports
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct MasterHealth {
+    /// Master which answered last
+    pub master: Option<NodeId>,
+    pub leader: Option<NodeId>,
+    /// Live masters as last announced
+    pub masters: Vec<NodeId>,
+    pub last_heard_at: Option<u64>,
+    /// False once no master answered within the master timeout
+    pub healthy: bool,
+    /// Master the reports are sent to after a failover, None while they go to the nearest master service
+    pub pinned: Option<NodeId>,
+    pub failovers: u64,
+}
+
+/// Watches master heartbeats and picks another known master when the current one goes silent
+pub struct MasterTracker {
+    timeout_ms: u64,
+    // start of the current silence window, reset by heartbeats and failovers
+    waiting_since: Option<u64>,
+    // masters which stayed silent since the last heartbeat
+    tried: Vec<NodeId>,
+    health: MasterHealth,
+}
+
+impl MasterTracker {
+    pub fn new(timeout_ms: u64) -> Self {
+        Self {
+            timeout_ms,
+            waiting_since: None,
+            tried: vec![],
+            health: MasterHealth::default(),
+        }
+    }
+
+    pub fn on_heartbeat(&mut self, status: MasterStatus, now_ms: u64) {
+        self.health.master = Some(status.node_id);
+        self.health.leader = Some(status.leader);
+        self.health.masters = status.masters;
+        self.health.last_heard_at = Some(now_ms);
+        self.health.healthy = true;
+        self.waiting_since = Some(now_ms);
+        self.tried.clear();
+    }
+
+    /// Mark the masters unhealthy after `timeout_ms` of silence and fail over to the leader, or the next known
+    /// master, once per silence window. Once every known master stayed silent the reports go back to the nearest
+    /// master service, which also reaches masters started since the last heartbeat.
+    pub fn on_tick(&mut self, now_ms: u64) {
+        let since = *self.waiting_since.get_or_insert(now_ms);
+        if now_ms.saturating_sub(since) < self.timeout_ms {
+            return;
+        }
+        self.health.healthy = false;
+        self.waiting_since = Some(now_ms);
+
+        let silent = self.health.pinned.or(self.health.master);
+        self.tried.extend(silent);
+        let candidates: Vec<NodeId> = self.health.masters.iter().filter(|master| !self.tried.contains(master)).cloned().collect();
+        let next = match self.health.leader.filter(|leader| candidates.contains(leader)) {
+            Some(leader) => Some(leader),
+            None => candidates.iter().find(|master| Some(**master) > silent).or(candidates.first()).cloned(),
+        };
+        if next.is_none() {
+            self.tried.clear();
+        }
+        if next != self.health.pinned {
+            self.health.pinned = next;
+            self.health.failovers += 1;
+        }
+    }
+
+    /// Node the reports should be routed to, None for the nearest master service
+    pub fn target(&self) -> Option<NodeId> {
+        self.health.pinned
+    }
+
+    pub fn health(&self) -> MasterHealth {
+        self.health.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn status(node_id: NodeId, masters: &[NodeId]) -> MasterStatus {
+        MasterStatus::elect(node_id, masters)
+    }
+
+    #[test]
+    fn should_be_healthy_while_heartbeats_arrive() {
+        let mut tracker = MasterTracker::new(1000);
+        tracker.on_tick(0);
+        tracker.on_heartbeat(status(20, &[10]), 500);
+        tracker.on_tick(1499);
+
+        let health = tracker.health();
+        assert!(health.healthy);
+        assert_eq!(health.master, Some(20));
+        assert_eq!(health.leader, Some(10));
+        assert_eq!(health.masters, vec![10, 20]);
+        assert_eq!(tracker.target(), None);
+    }
+
+    #[test]
+    fn should_fail_over_to_leader_then_next_master() {
+        let mut tracker = MasterTracker::new(1000);
+        tracker.on_heartbeat(status(20, &[10, 30]), 0);
+
+        tracker.on_tick(1000);
+        assert!(!tracker.health().healthy);
+        assert_eq!(tracker.target(), Some(10));
+
+        // the leader stays silent too, move on to the next master not tried yet after another window
+        tracker.on_tick(1500);
+        assert_eq!(tracker.target(), Some(10));
+        tracker.on_tick(2000);
+        assert_eq!(tracker.target(), Some(30));
+        assert_eq!(tracker.health().failovers, 2);
+
+        tracker.on_heartbeat(status(30, &[20]), 2100);
+        assert!(tracker.health().healthy);
+        assert_eq!(tracker.target(), Some(30));
+    }
+
+    #[test]
+    fn should_unpin_once_every_known_master_stayed_silent() {
+        let mut tracker = MasterTracker::new(1000);
+        tracker.on_heartbeat(status(20, &[10]), 0);
+
+        tracker.on_tick(1000);
+        assert_eq!(tracker.target(), Some(10));
+        // the pinned master died as well, back to the nearest master service
+        tracker.on_tick(2000);
+        assert_eq!(tracker.target(), None);
+        assert_eq!(tracker.health().failovers, 2);
+
+        // the master service stays silent, the known masters are tried again
+        tracker.on_tick(3000);
+        assert_eq!(tracker.target(), Some(10));
+
+        tracker.on_tick(4000);
+        assert_eq!(tracker.target(), None);
+        assert_eq!(tracker.health().failovers, 4);
+    }
+
+    #[test]
+    fn should_stay_on_master_service_without_known_masters() {
+        let mut tracker = MasterTracker::new(1000);
+        tracker.on_tick(0);
+        tracker.on_tick(1000);
+
+        assert!(!tracker.health().healthy);
+        assert_eq!(tracker.target(), None);
+        assert_eq!(tracker.health().failovers, 0);
+    }
+}
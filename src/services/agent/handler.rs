@@ -4,7 +4,7 @@ use atm0s_sdn_network::transport::ConnectionEvent;
 use atm0s_sdn_utils::vec_dequeue::VecDeque;
 
 use crate::identity::ConnectionMetric;
-use crate::{VisualizationAgentBehaviourEvent, VisualizationAgentHandlerEvent};
+use crate::{VisualizationAgentBehaviourEvent, VisualizationAgentHandlerEvent, VisualizationMasterMsg};
 
 pub struct VisualizationAgentHandler<BE, HE> {
     conn_id: ConnId,
@@ -35,7 +35,14 @@ where
 
     fn on_event(&mut self, ctx: &ConnectionContext, now_ms: u64, event: ConnectionEvent) {
         match event {
-            ConnectionEvent::Msg(msg) => {}
+            ConnectionEvent::Msg(msg) => {
+                let be = match msg.get_payload_bincode::<VisualizationMasterMsg>() {
+                    Ok(VisualizationMasterMsg::ReportAck(seq)) => VisualizationAgentBehaviourEvent::ReportAck(seq),
+                    Ok(VisualizationMasterMsg::Heartbeat(status)) => VisualizationAgentBehaviourEvent::MasterHeartbeat(status),
+                    Err(_e) => return,
+                };
+                self.actions.push_back(ConnectionHandlerAction::ToBehaviour(be.into()));
+            }
             ConnectionEvent::Stats(stats) => {
                 // println!("on stats event...");
                 let metric = ConnectionMetric {
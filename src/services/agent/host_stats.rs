@@ -0,0 +1,132 @@
+use crate::identity::HostStats;
+
+/// Reads host and process metrics from `/proc`, only available on linux with the `host-stats` feature.
+pub struct HostStatsCollector {
+    #[cfg(any(test, all(feature = "host-stats", target_os = "linux")))]
+    prev_cpu: Option<(u64, u64)>,
+}
+
+impl HostStatsCollector {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(any(test, all(feature = "host-stats", target_os = "linux")))]
+            prev_cpu: None,
+        }
+    }
+
+    #[cfg(all(feature = "host-stats", target_os = "linux"))]
+    pub fn collect(&mut self) -> Option<HostStats> {
+        use std::fs::{read_dir, read_to_string};
+
+        let mut stats = HostStats::default();
+        if let Some((busy, total)) = read_to_string("/proc/stat").ok().as_deref().and_then(parse_cpu_times) {
+            // the first reading is the average since boot, nothing is reported until there is a previous one
+            stats.cpu_percent = self.cpu_percent(busy, total)?;
+        }
+        if let Some((load_1m, load_5m, load_15m)) = read_to_string("/proc/loadavg").ok().as_deref().and_then(parse_loadavg) {
+            stats.load_avg_1m = load_1m;
+            stats.load_avg_5m = load_5m;
+            stats.load_avg_15m = load_15m;
+        }
+        if let Ok(content) = read_to_string("/proc/meminfo") {
+            stats.mem_total_kb = parse_kb_field(&content, "MemTotal:").unwrap_or(0);
+            stats.mem_available_kb = parse_kb_field(&content, "MemAvailable:").unwrap_or(0);
+        }
+        if let Ok(content) = read_to_string("/proc/self/status") {
+            stats.process_rss_kb = parse_kb_field(&content, "VmRSS:").unwrap_or(0);
+        }
+        if let Ok(entries) = read_dir("/proc/self/fd") {
+            stats.open_fds = entries.count() as u32;
+        }
+        if let Some(uptime) = read_to_string("/proc/uptime").ok().as_deref().and_then(parse_uptime) {
+            stats.uptime_secs = uptime;
+        }
+        Some(stats)
+    }
+
+    #[cfg(not(all(feature = "host-stats", target_os = "linux")))]
+    pub fn collect(&mut self) -> Option<HostStats> {
+        None
+    }
+
+    /// Busy percent since the previous reading, None on the first one
+    #[cfg(any(test, all(feature = "host-stats", target_os = "linux")))]
+    fn cpu_percent(&mut self, busy: u64, total: u64) -> Option<u32> {
+        let (prev_busy, prev_total) = self.prev_cpu.replace((busy, total))?;
+        let delta_total = total.saturating_sub(prev_total);
+        if delta_total == 0 {
+            return Some(0);
+        }
+        Some((busy.saturating_sub(prev_busy) * 100 / delta_total) as u32)
+    }
+}
+
+/// Returns (busy, total) jiffies from the aggregated `cpu` line of /proc/stat
+#[cfg(any(test, all(feature = "host-stats", target_os = "linux")))]
+fn parse_cpu_times(content: &str) -> Option<(u64, u64)> {
+    let line = content.lines().find(|line| line.starts_with("cpu "))?;
+    let values: Vec<u64> = line.split_whitespace().skip(1).filter_map(|v| v.parse().ok()).collect();
+    if values.len() < 4 {
+        return None;
+    }
+    let total: u64 = values.iter().sum();
+    let idle = values[3] + values.get(4).copied().unwrap_or(0);
+    Some((total - idle, total))
+}
+
+/// Returns the 1m, 5m and 15m load averages multiplied by 100
+#[cfg(any(test, all(feature = "host-stats", target_os = "linux")))]
+fn parse_loadavg(content: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = content.split_whitespace().map(|v| v.parse::<f64>().ok().map(|v| (v * 100.0).round() as u32));
+    Some((parts.next()??, parts.next()??, parts.next()??))
+}
+
+/// Returns the value of a `Name:   1234 kB` line, as found in /proc/meminfo and /proc/self/status
+#[cfg(any(test, all(feature = "host-stats", target_os = "linux")))]
+fn parse_kb_field(content: &str, name: &str) -> Option<u64> {
+    let line = content.lines().find(|line| line.starts_with(name))?;
+    line[name.len()..].split_whitespace().next()?.parse().ok()
+}
+
+#[cfg(any(test, all(feature = "host-stats", target_os = "linux")))]
+fn parse_uptime(content: &str) -> Option<u64> {
+    content.split_whitespace().next()?.parse::<f64>().ok().map(|v| v as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_cpu_times_from_proc_stat() {
+        let content = "cpu  100 0 50 800 50 0 0 0 0 0\ncpu0 50 0 25 400 25 0 0 0 0 0\n";
+        assert_eq!(parse_cpu_times(content), Some((150, 1000)));
+    }
+
+    #[test]
+    fn should_calc_cpu_percent_only_from_previous_sample() {
+        let mut collector = HostStatsCollector::new();
+        assert_eq!(collector.cpu_percent(150, 1000), None);
+        assert_eq!(collector.cpu_percent(200, 1100), Some(50));
+        assert_eq!(collector.cpu_percent(200, 1100), Some(0));
+    }
+
+    #[test]
+    fn should_parse_loadavg() {
+        assert_eq!(parse_loadavg("0.52 1.05 2.00 1/345 12345\n"), Some((52, 105, 200)));
+        assert_eq!(parse_loadavg("garbage"), None);
+    }
+
+    #[test]
+    fn should_parse_kb_fields() {
+        let content = "MemTotal:       16318412 kB\nMemFree:         1234567 kB\nMemAvailable:    8000000 kB\n";
+        assert_eq!(parse_kb_field(content, "MemTotal:"), Some(16318412));
+        assert_eq!(parse_kb_field(content, "MemAvailable:"), Some(8000000));
+        assert_eq!(parse_kb_field(content, "VmRSS:"), None);
+    }
+
+    #[test]
+    fn should_parse_uptime() {
+        assert_eq!(parse_uptime("12345.67 54321.00\n"), Some(12345));
+    }
+}
@@ -1,27 +1,58 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    mem::discriminant,
+};
+
 use atm0s_sdn_identity::{ConnId, NodeAddr, NodeId};
-use atm0s_sdn_utils::vec_dequeue::VecDeque;
 
 use crate::identity::{generate_connection_id, ConnectionMetric, ConnectionStatus};
+use crate::MasterStatus;
 
 use super::{
-    msg::{ConnectionMsg, VisualizationAgentMsg, MAX_CONN_STATS_SEND},
-    storage::{ConnectionModifyData, ConnectionNode, ConnectionStorage},
+    behaviour::VisualizationAgentBehaviourConf,
+    delivery::{DeliveryStats, ReliableDelivery},
+    failover::{MasterHealth, MasterTracker},
+    host_stats::HostStatsCollector,
+    msg::{AgentQueuePolicy, ConnectionMsg, VisualizationAgentMsg, VisualizationAgentSdkEvent, LABELS_RESEND_INTERVAL_MS},
+    storage::{AppMetricsStorage, ConnectionModifyData, ConnectionNode, ConnectionStorage},
 };
 
 pub struct VisualizationAgentLogic {
     node_id: NodeId,
     node_addr: NodeAddr,
     msg_queue: VecDeque<VisualizationAgentMsg>,
+    queue_limit: usize,
+    queue_policy: AgentQueuePolicy,
+    dropped_reports: u64,
+    // dropped count carried by the last report which left the queue
+    dropped_reports_sent: u64,
+    delivery: Option<ReliableDelivery>,
+    master: MasterTracker,
+    now_ms: u64,
+    max_conns_per_report: usize,
+    max_report_bytes: usize,
+    conn_gc_timeout_ms: u64,
+    // removed connection id => removed at, resent with every report until the gc timeout passes again
+    tombstones: BTreeMap<u64, u64>,
     storage: ConnectionStorage,
+    host_stats: Option<HostStatsCollector>,
+    labels: BTreeMap<String, String>,
+    labels_changed: bool,
+    labels_sent_at: u64,
+    app_metrics: AppMetricsStorage,
 }
 
-fn build_conns_stats_msg(id: NodeId, mut conns: Vec<ConnectionNode>) -> Vec<VisualizationAgentMsg> {
+/// Split connections into reports of at most `max_conns` entries and `max_bytes` bincode encoded bytes.
+/// A connection which alone exceeds `max_bytes` is still sent in its own report.
+fn build_conns_stats_msg(id: NodeId, mut conns: Vec<ConnectionNode>, max_conns: usize, max_bytes: usize) -> Vec<VisualizationAgentMsg> {
+    let empty_size = encoded_size(&VisualizationAgentMsg::NodeConnections(id, vec![]));
     let mut ret_val = Vec::<VisualizationAgentMsg>::new();
     let mut conn_vec_to_send = Vec::<ConnectionMsg>::new();
+    let mut size = empty_size;
     while let Some(conn) = conns.pop() {
         match conn.metric {
             Some(metric) => {
-                conn_vec_to_send.push(ConnectionMsg {
+                let conn_msg = ConnectionMsg {
                     conn_id: conn.uuid,
                     protocol: conn.protocol,
                     addr: conn.addr.to_string(),
@@ -30,42 +61,180 @@ fn build_conns_stats_msg(id: NodeId, mut conns: Vec<ConnectionNode>) -> Vec<Visu
                     status: conn.status,
                     metric: metric.clone(),
                     latest_updated_at: conn.latest_updated_at,
-                });
-                if conn_vec_to_send.len() >= MAX_CONN_STATS_SEND {
-                    ret_val.push(VisualizationAgentMsg::NodeConnections(id, conn_vec_to_send.clone()));
-                    conn_vec_to_send.clear();
+                };
+                let conn_size = encoded_size(&conn_msg);
+                if !conn_vec_to_send.is_empty() && (conn_vec_to_send.len() >= max_conns || size + conn_size > max_bytes) {
+                    ret_val.push(VisualizationAgentMsg::NodeConnections(id, std::mem::take(&mut conn_vec_to_send)));
+                    size = empty_size;
                 }
+                conn_vec_to_send.push(conn_msg);
+                size += conn_size;
             }
             None => {}
         };
     }
-    if conn_vec_to_send.len() > 0 {
-        ret_val.push(VisualizationAgentMsg::NodeConnections(id, conn_vec_to_send.clone()));
+    if !conn_vec_to_send.is_empty() {
+        ret_val.push(VisualizationAgentMsg::NodeConnections(id, conn_vec_to_send));
     }
     ret_val
 }
 
+/// Whether `queued` carries nothing that `msg` does not report again. Connection reports and tombstones are partial,
+/// like in `ReliableDelivery::track`, so a batch only replaces queued batches of the same connections.
+fn is_superseded_by(queued: &VisualizationAgentMsg, msg: &VisualizationAgentMsg) -> bool {
+    match (queued, msg) {
+        (VisualizationAgentMsg::NodeConnections(_, queued), VisualizationAgentMsg::NodeConnections(_, conns)) => queued.iter().all(|queued| conns.iter().any(|conn| conn.conn_id == queued.conn_id)),
+        (VisualizationAgentMsg::NodeConnectionsRemoved(_, queued), VisualizationAgentMsg::NodeConnectionsRemoved(_, ids)) => queued.iter().all(|id| ids.contains(id)),
+        _ => discriminant(queued) == discriminant(msg),
+    }
+}
+
+/// Split removed connection ids into reports of at most `max_bytes` encoded bytes
+fn build_tombstones_msg(id: NodeId, ids: Vec<u64>, max_bytes: usize) -> Vec<VisualizationAgentMsg> {
+    let empty_size = encoded_size(&VisualizationAgentMsg::NodeConnectionsRemoved(id, vec![]));
+    let per_msg = (max_bytes.saturating_sub(empty_size) / std::mem::size_of::<u64>()).max(1);
+    ids.chunks(per_msg).map(|chunk| VisualizationAgentMsg::NodeConnectionsRemoved(id, chunk.to_vec())).collect()
+}
+
+fn encoded_size<M: serde::Serialize>(msg: &M) -> usize {
+    bincode::serialized_size(msg).expect("Should size payload") as usize
+}
+
 impl VisualizationAgentLogic {
-    pub fn new(node_id: NodeId, node_addr: NodeAddr) -> Self {
+    pub fn new(conf: VisualizationAgentBehaviourConf) -> Self {
+        // in ack mode each report is wrapped with its sequence number, keep it inside the byte budget
+        let envelope_size = match conf.ack {
+            Some(_) => {
+                let empty = VisualizationAgentMsg::NodeConnections(conf.node_id, vec![]);
+                encoded_size(&VisualizationAgentMsg::Reliable(0, Box::new(empty.clone()))) - encoded_size(&empty)
+            }
+            None => 0,
+        };
         Self {
-            node_id: node_id,
-            node_addr: node_addr,
+            node_id: conf.node_id,
+            node_addr: conf.node_addr,
             msg_queue: VecDeque::new(),
+            queue_limit: conf.queue_limit.max(1),
+            queue_policy: conf.queue_policy,
+            dropped_reports: 0,
+            dropped_reports_sent: 0,
+            delivery: conf.ack.map(ReliableDelivery::new),
+            master: MasterTracker::new(conf.master_timeout_ms),
+            now_ms: 0,
+            max_conns_per_report: conf.max_conns_per_report.max(1),
+            max_report_bytes: conf.max_report_bytes.saturating_sub(envelope_size),
+            conn_gc_timeout_ms: conf.conn_gc_timeout_ms,
+            tombstones: BTreeMap::new(),
             storage: ConnectionStorage::new(),
+            host_stats: if conf.report_host_stats {
+                Some(HostStatsCollector::new())
+            } else {
+                None
+            },
+            labels: conf.labels,
+            labels_changed: true,
+            labels_sent_at: 0,
+            app_metrics: AppMetricsStorage::new(),
+        }
+    }
+
+    pub fn set_labels(&mut self, labels: BTreeMap<String, String>) {
+        if self.labels != labels {
+            self.labels = labels;
+            self.labels_changed = true;
         }
     }
 
     pub fn report_stats(&mut self, now_ms: u64) {
+        self.now_ms = now_ms;
+        self.master.on_tick(now_ms);
+        // retransmits count against the queue limit like queued reports
+        let max_retransmit = self.queue_limit.saturating_sub(self.msg_queue.len());
+        if let Some(delivery) = self.delivery.as_mut() {
+            delivery.on_tick(now_ms, max_retransmit);
+        }
+
+        // the drop counter goes first, so the master learns about losses as soon as the queue drains again
+        if self.dropped_reports > self.dropped_reports_sent && !self.queued_dropped_reports() {
+            self.enqueue(VisualizationAgentMsg::NodeDroppedReports(self.node_id, self.dropped_reports));
+        }
+
         let ping_msg = VisualizationAgentMsg::NodePing(self.node_id, self.node_addr.to_string(), now_ms);
-        self.msg_queue.push_back(ping_msg);
+        self.enqueue(ping_msg);
+        if let Some(stats) = self.host_stats.as_mut().and_then(|collector| collector.collect()) {
+            self.enqueue(VisualizationAgentMsg::NodeHostStats(self.node_id, now_ms, stats));
+        }
 
-        let mut stats_msgs = build_conns_stats_msg(self.node_id, self.storage.list_conns());
+        // labels are sent on start and on change, and resent from time to time in case the master restarted
+        if self.labels_changed || now_ms >= self.labels_sent_at + LABELS_RESEND_INTERVAL_MS {
+            self.enqueue(VisualizationAgentMsg::NodeLabels(self.node_id, self.labels.clone()));
+            self.labels_changed = false;
+            self.labels_sent_at = now_ms;
+        }
+
+        if !self.app_metrics.is_empty() {
+            self.enqueue(VisualizationAgentMsg::NodeAppMetrics(self.node_id, now_ms, self.app_metrics.list_metrics()));
+        }
+
+        for uuid in self.storage.remove_expired(now_ms, self.conn_gc_timeout_ms) {
+            self.tombstones.insert(uuid, now_ms);
+        }
+        let gc_timeout_ms = self.conn_gc_timeout_ms;
+        self.tombstones.retain(|_, removed_at| now_ms < *removed_at + gc_timeout_ms);
+        if !self.tombstones.is_empty() {
+            let ids: Vec<u64> = self.tombstones.keys().copied().collect();
+            for msg in build_tombstones_msg(self.node_id, ids, self.max_report_bytes) {
+                self.enqueue(msg);
+            }
+        }
+
+        let mut stats_msgs = build_conns_stats_msg(self.node_id, self.storage.list_conns(), self.max_conns_per_report, self.max_report_bytes);
         while let Some(msg) = stats_msgs.pop() {
-            self.msg_queue.push_back(msg);
+            self.enqueue(msg);
         }
     }
 
+    fn queued_dropped_reports(&self) -> bool {
+        self.msg_queue.iter().any(|msg| matches!(msg, VisualizationAgentMsg::NodeDroppedReports(..)))
+    }
+
+    fn enqueue(&mut self, msg: VisualizationAgentMsg) {
+        let retransmits = self.delivery.as_ref().map_or(0, |delivery| delivery.retransmit_len());
+        if self.msg_queue.len() + retransmits >= self.queue_limit {
+            // the queue is full of retransmits, which are never dropped for newer reports
+            if self.msg_queue.is_empty() {
+                self.dropped_reports += 1;
+                return;
+            }
+            match self.queue_policy {
+                AgentQueuePolicy::DropOldest => {
+                    self.msg_queue.pop_front();
+                    self.dropped_reports += 1;
+                }
+                AgentQueuePolicy::LatestPerKind => {
+                    let before = self.msg_queue.len();
+                    self.msg_queue.retain(|queued| !is_superseded_by(queued, &msg));
+                    if self.msg_queue.len() == before {
+                        self.msg_queue.pop_front();
+                    }
+                    self.dropped_reports += (before - self.msg_queue.len()) as u64;
+                }
+                AgentQueuePolicy::Pause => {
+                    self.dropped_reports += 1;
+                    return;
+                }
+            }
+        }
+        self.msg_queue.push_back(msg);
+    }
+
+    /// Reports dropped because the outgoing queue was full, since start
+    pub fn dropped_reports(&self) -> u64 {
+        self.dropped_reports
+    }
+
     pub fn on_node_connected(&mut self, conn_id: ConnId, node_id: NodeId, addr: NodeAddr, now: u64) {
+        self.tombstones.remove(&generate_connection_id(conn_id.protocol(), conn_id.direction(), node_id));
         self.storage.new_connection(conn_id, node_id, addr, now);
     }
 
@@ -93,8 +262,50 @@ impl VisualizationAgentLogic {
         );
     }
 
+    pub fn on_sdk_event(&mut self, event: VisualizationAgentSdkEvent) {
+        match event {
+            VisualizationAgentSdkEvent::SetLabels(labels) => self.set_labels(labels),
+            event => self.app_metrics.apply(event),
+        }
+    }
+
+    pub fn on_report_ack(&mut self, seq: u64) {
+        if let Some(delivery) = self.delivery.as_mut() {
+            delivery.on_ack(seq);
+        }
+    }
+
+    pub fn on_master_heartbeat(&mut self, status: MasterStatus, now_ms: u64) {
+        self.master.on_heartbeat(status, now_ms);
+    }
+
+    pub fn master_health(&self) -> MasterHealth {
+        self.master.health()
+    }
+
+    /// Master the reports are pinned to after a failover, None to use the nearest master service
+    pub fn master_target(&self) -> Option<NodeId> {
+        self.master.target()
+    }
+
+    /// Delivery counters of the ack mode, None when it is disabled
+    pub fn delivery_stats(&self) -> Option<DeliveryStats> {
+        self.delivery.as_ref().map(|delivery| delivery.stats())
+    }
+
+    /// Next report to send, retransmits go first. In ack mode reports are wrapped with their sequence number.
     pub fn pop_msg(&mut self) -> Option<VisualizationAgentMsg> {
-        self.msg_queue.pop_front()
+        if let Some(msg) = self.delivery.as_mut().and_then(|delivery| delivery.pop_retransmit()) {
+            return Some(msg);
+        }
+        let msg = self.msg_queue.pop_front()?;
+        if let VisualizationAgentMsg::NodeDroppedReports(_, count) = &msg {
+            self.dropped_reports_sent = *count;
+        }
+        match self.delivery.as_mut() {
+            Some(delivery) => Some(delivery.track(msg, self.now_ms)),
+            None => Some(msg),
+        }
     }
 }
 
@@ -104,6 +315,8 @@ mod test {
 
     use atm0s_sdn_identity::NodeAddrBuilder;
 
+    use crate::{services::agent::msg::MAX_CONN_STATS_SEND, DEFAULT_MAX_REPORT_BYTES};
+
     use super::*;
 
     #[test]
@@ -142,7 +355,7 @@ mod test {
             },
         ];
 
-        let result = build_conns_stats_msg(node_id, conns);
+        let result = build_conns_stats_msg(node_id, conns, MAX_CONN_STATS_SEND, DEFAULT_MAX_REPORT_BYTES);
 
         assert_eq!(result.len(), 1);
         let data = result.index(0).clone();
@@ -155,6 +368,319 @@ mod test {
         }
     }
 
+    #[test]
+    fn should_send_labels_on_start_and_on_change() {
+        let addr = NodeAddrBuilder::new(1).addr();
+        let labels = BTreeMap::from([(String::from("region"), String::from("eu"))]);
+        let mut logic = VisualizationAgentLogic::new(VisualizationAgentBehaviourConf {
+            labels: labels.clone(),
+            ..VisualizationAgentBehaviourConf::new(1, addr)
+        });
+
+        let count_labels_msg = |logic: &mut VisualizationAgentLogic| {
+            let mut count = 0;
+            while let Some(msg) = logic.pop_msg() {
+                if let VisualizationAgentMsg::NodeLabels(_, _) = msg {
+                    count += 1;
+                }
+            }
+            count
+        };
+
+        logic.report_stats(1000);
+        assert_eq!(count_labels_msg(&mut logic), 1);
+
+        logic.set_labels(labels.clone());
+        logic.report_stats(2000);
+        assert_eq!(count_labels_msg(&mut logic), 0);
+
+        logic.set_labels(BTreeMap::from([(String::from("region"), String::from("us"))]));
+        logic.report_stats(3000);
+        assert_eq!(count_labels_msg(&mut logic), 1);
+
+        logic.report_stats(3000 + LABELS_RESEND_INTERVAL_MS);
+        assert_eq!(count_labels_msg(&mut logic), 1);
+    }
+
+    fn drain(logic: &mut VisualizationAgentLogic) -> Vec<VisualizationAgentMsg> {
+        let mut msgs = vec![];
+        while let Some(msg) = logic.pop_msg() {
+            msgs.push(msg);
+        }
+        msgs
+    }
+
+    #[test]
+    fn should_drop_oldest_reports_when_queue_full_and_report_drop_count() {
+        let addr = NodeAddrBuilder::new(1).addr();
+        let mut logic = VisualizationAgentLogic::new(VisualizationAgentBehaviourConf {
+            queue_limit: 2,
+            queue_policy: AgentQueuePolicy::DropOldest,
+            ..VisualizationAgentBehaviourConf::new(1, addr)
+        });
+
+        // ping and labels fill the queue, the second ping pushes the first one out
+        logic.report_stats(1000);
+        logic.report_stats(2000);
+        assert_eq!(logic.dropped_reports(), 1);
+        let msgs = drain(&mut logic);
+        assert_eq!(msgs.len(), 2);
+        assert!(matches!(msgs[1], VisualizationAgentMsg::NodePing(_, _, 2000)));
+
+        logic.report_stats(3000);
+        assert_eq!(drain(&mut logic)[0], VisualizationAgentMsg::NodeDroppedReports(1, 1));
+
+        logic.report_stats(4000);
+        assert!(!drain(&mut logic).iter().any(|msg| matches!(msg, VisualizationAgentMsg::NodeDroppedReports(..))));
+    }
+
+    #[test]
+    fn should_keep_latest_report_per_kind_when_queue_full() {
+        let addr = NodeAddrBuilder::new(1).addr();
+        let mut logic = VisualizationAgentLogic::new(VisualizationAgentBehaviourConf {
+            queue_limit: 2,
+            queue_policy: AgentQueuePolicy::LatestPerKind,
+            ..VisualizationAgentBehaviourConf::new(1, addr)
+        });
+
+        logic.report_stats(1000);
+        logic.report_stats(2000);
+        assert_eq!(logic.dropped_reports(), 1);
+        logic.report_stats(3000);
+        // the drop counter report pushes out the labels, the newest ping replaces the older one
+        assert_eq!(logic.dropped_reports(), 3);
+        let msgs = drain(&mut logic);
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0], VisualizationAgentMsg::NodeDroppedReports(1, 1));
+        assert!(matches!(msgs[1], VisualizationAgentMsg::NodePing(_, _, 3000)));
+    }
+
+    #[test]
+    fn should_pause_queueing_when_queue_full() {
+        let addr = NodeAddrBuilder::new(1).addr();
+        let mut logic = VisualizationAgentLogic::new(VisualizationAgentBehaviourConf {
+            queue_limit: 2,
+            queue_policy: AgentQueuePolicy::Pause,
+            ..VisualizationAgentBehaviourConf::new(1, addr)
+        });
+
+        logic.report_stats(1000);
+        logic.report_stats(2000);
+        assert_eq!(logic.dropped_reports(), 1);
+        let msgs = drain(&mut logic);
+        assert!(matches!(msgs[0], VisualizationAgentMsg::NodePing(_, _, 1000)));
+
+        logic.report_stats(3000);
+        let msgs = drain(&mut logic);
+        assert_eq!(msgs[0], VisualizationAgentMsg::NodeDroppedReports(1, 1));
+        assert!(matches!(msgs[1], VisualizationAgentMsg::NodePing(_, _, 3000)));
+    }
+
+    #[test]
+    fn latest_per_kind_should_only_replace_batches_of_the_same_connections() {
+        let addr = NodeAddrBuilder::new(1).addr();
+        let mut logic = VisualizationAgentLogic::new(VisualizationAgentBehaviourConf {
+            queue_limit: 2,
+            queue_policy: AgentQueuePolicy::LatestPerKind,
+            ..VisualizationAgentBehaviourConf::new(1, addr)
+        });
+        let conns = |ids: &[u64]| {
+            let conns = ids
+                .iter()
+                .map(|id| ConnectionMsg {
+                    conn_id: *id,
+                    protocol: 1,
+                    addr: String::from("127.0.0.1"),
+                    node_id: *id as NodeId,
+                    direction: 0,
+                    status: ConnectionStatus::CONNECTED,
+                    metric: ConnectionMetric {
+                        latency: 0,
+                        bandwidth: 0,
+                        loss_percent: 0,
+                    },
+                    latest_updated_at: 0,
+                })
+                .collect();
+            VisualizationAgentMsg::NodeConnections(1, conns)
+        };
+
+        logic.enqueue(conns(&[1, 2]));
+        logic.enqueue(VisualizationAgentMsg::NodeConnectionsRemoved(1, vec![7]));
+        // other peers do not replace the queued batch, the oldest report is dropped instead
+        logic.enqueue(conns(&[3]));
+        // tombstones covering the queued ones replace them
+        logic.enqueue(VisualizationAgentMsg::NodeConnectionsRemoved(1, vec![7, 8]));
+        assert_eq!(logic.dropped_reports(), 2);
+        assert_eq!(drain(&mut logic), vec![conns(&[3]), VisualizationAgentMsg::NodeConnectionsRemoved(1, vec![7, 8])]);
+    }
+
+    fn conn_node(uuid: u64, addr: String) -> ConnectionNode {
+        ConnectionNode {
+            uuid,
+            protocol: 1,
+            addr,
+            node_id: 2,
+            direction: 1,
+            status: ConnectionStatus::CONNECTED,
+            metric: Some(ConnectionMetric {
+                latency: 1,
+                loss_percent: 0,
+                bandwidth: 100,
+            }),
+            latest_updated_at: 0,
+        }
+    }
+
+    fn sizes(msgs: &[VisualizationAgentMsg]) -> Vec<usize> {
+        msgs.iter().map(encoded_size).collect()
+    }
+
+    #[test]
+    fn should_fit_report_exactly_at_byte_budget() {
+        let conns: Vec<ConnectionNode> = (0..3).map(|i| conn_node(i, String::from("127.0.0.1"))).collect();
+        let exact = encoded_size(&build_conns_stats_msg(1, conns.clone(), usize::MAX, usize::MAX)[0]);
+
+        let result = build_conns_stats_msg(1, conns.clone(), usize::MAX, exact);
+        assert_eq!(sizes(&result), vec![exact]);
+
+        let result = build_conns_stats_msg(1, conns, usize::MAX, exact - 1);
+        assert_eq!(result.len(), 2);
+        assert!(sizes(&result).iter().all(|size| *size < exact));
+    }
+
+    #[test]
+    fn should_send_oversized_connection_alone() {
+        let conns = vec![conn_node(1, String::from("127.0.0.1")), conn_node(2, "x".repeat(200)), conn_node(3, String::from("127.0.0.1"))];
+
+        let result = build_conns_stats_msg(1, conns, usize::MAX, 100);
+        let counts: Vec<usize> = result
+            .iter()
+            .map(|msg| match msg {
+                VisualizationAgentMsg::NodeConnections(_, conns) => conns.len(),
+                _ => 0,
+            })
+            .collect();
+        assert_eq!(counts, vec![1, 1, 1]);
+        assert!(sizes(&result)[1] > 100);
+    }
+
+    #[test]
+    fn should_respect_both_count_and_byte_limits() {
+        let conns: Vec<ConnectionNode> = (0..100).map(|i| conn_node(i, format!("/ip4/10.0.0.{}/udp/10000", i))).collect();
+
+        let by_count = build_conns_stats_msg(1, conns.clone(), 7, usize::MAX);
+        assert_eq!(by_count.len(), 15);
+
+        let by_bytes = build_conns_stats_msg(1, conns, usize::MAX, 500);
+        assert!(by_bytes.len() > 1);
+        assert!(sizes(&by_bytes).iter().all(|size| *size <= 500));
+        let total: usize = by_bytes
+            .iter()
+            .map(|msg| match msg {
+                VisualizationAgentMsg::NodeConnections(_, conns) => conns.len(),
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(total, 100);
+    }
+
+    #[test]
+    fn retransmits_should_count_against_queue_limit() {
+        let addr = NodeAddrBuilder::new(1).addr();
+        let mut logic = VisualizationAgentLogic::new(VisualizationAgentBehaviourConf {
+            queue_limit: 2,
+            ack: Some(Default::default()),
+            ..VisualizationAgentBehaviourConf::new(1, addr)
+        });
+        logic.report_stats(0);
+        assert_eq!(drain(&mut logic).len(), 2);
+
+        // both reports are due again and fill the queue, the new ping is dropped
+        logic.report_stats(1000);
+        let msgs = drain(&mut logic);
+        assert_eq!(msgs.len(), 2);
+        assert!(msgs.iter().all(|msg| matches!(msg, VisualizationAgentMsg::Reliable(1 | 2, _))));
+        assert_eq!(logic.dropped_reports(), 1);
+    }
+
+    #[test]
+    fn ack_mode_should_keep_wrapped_report_inside_byte_budget() {
+        let addr = NodeAddrBuilder::new(1).addr();
+        let mut logic = VisualizationAgentLogic::new(VisualizationAgentBehaviourConf {
+            max_report_bytes: 300,
+            ack: Some(Default::default()),
+            ..VisualizationAgentBehaviourConf::new(1, addr.clone())
+        });
+        for i in 0..50 {
+            let conn_id = ConnId::from_out(1, i);
+            logic.on_node_connected(conn_id, 100 + i as NodeId, addr.clone(), 0);
+            logic.on_connection_stats(
+                conn_id,
+                100 + i as NodeId,
+                ConnectionMetric {
+                    latency: 1,
+                    bandwidth: 100,
+                    loss_percent: 0,
+                },
+                0,
+            );
+        }
+
+        logic.report_stats(1000);
+        let mut reports = 0;
+        while let Some(msg) = logic.pop_msg() {
+            if let VisualizationAgentMsg::Reliable(_, inner) = &msg {
+                if matches!(inner.as_ref(), VisualizationAgentMsg::NodeConnections(..)) {
+                    reports += 1;
+                    assert!(encoded_size(&msg) <= 300);
+                }
+            }
+        }
+        assert!(reports > 1);
+    }
+
+    #[test]
+    fn should_gc_disconnected_connections_and_resend_tombstones() {
+        let addr = NodeAddrBuilder::new(1).addr();
+        let mut logic = VisualizationAgentLogic::new(VisualizationAgentBehaviourConf {
+            conn_gc_timeout_ms: 1000,
+            ..VisualizationAgentBehaviourConf::new(1, addr.clone())
+        });
+        let conn_id = ConnId::from_out(1, 1);
+        let uuid = generate_connection_id(1, conn_id.direction(), 2);
+        let tombstones = |msgs: Vec<VisualizationAgentMsg>| -> Vec<u64> {
+            msgs.into_iter()
+                .filter_map(|msg| match msg {
+                    VisualizationAgentMsg::NodeConnectionsRemoved(_, ids) => Some(ids),
+                    _ => None,
+                })
+                .flatten()
+                .collect()
+        };
+
+        logic.on_node_connected(conn_id, 2, addr.clone(), 0);
+        logic.on_node_disconnected(conn_id, 2, 100);
+        logic.report_stats(1099);
+        assert_eq!(tombstones(drain(&mut logic)), Vec::<u64>::new());
+
+        logic.report_stats(1100);
+        assert_eq!(tombstones(drain(&mut logic)), vec![uuid]);
+        logic.report_stats(2099);
+        assert_eq!(tombstones(drain(&mut logic)), vec![uuid]);
+        logic.report_stats(2100);
+        assert_eq!(tombstones(drain(&mut logic)), Vec::<u64>::new());
+
+        // a reconnect before the tombstone expires cancels it
+        logic.on_node_connected(conn_id, 2, addr.clone(), 3000);
+        logic.on_node_disconnected(conn_id, 2, 3000);
+        logic.report_stats(4000);
+        assert_eq!(tombstones(drain(&mut logic)), vec![uuid]);
+        logic.on_node_connected(conn_id, 2, addr, 4100);
+        logic.report_stats(4200);
+        assert_eq!(tombstones(drain(&mut logic)), Vec::<u64>::new());
+    }
+
     #[test]
     fn should_split_to_multi_msg_if_number_conn_is_greater_than_max() {
         let node_id = 1;
@@ -177,7 +703,7 @@ mod test {
                 latest_updated_at: 0,
             })
         }
-        let result = build_conns_stats_msg(node_id, conns);
+        let result = build_conns_stats_msg(node_id, conns, MAX_CONN_STATS_SEND, DEFAULT_MAX_REPORT_BYTES);
 
         assert_eq!(result.len(), 2);
     }
@@ -1,9 +1,21 @@
 mod behaviour;
+mod delivery;
+mod failover;
 mod handler;
+mod host_stats;
 mod logic;
 mod msg;
+mod sdk;
 mod storage;
 
 pub static VISUALIZATION_AGENT_SERVICE: u8 = 9;
 pub use behaviour::{VisualizationAgentBehaviour, VisualizationAgentBehaviourConf};
-pub use msg::{VisualizationAgentBehaviourEvent, VisualizationAgentHandlerEvent, VisualizationAgentMsg};
+pub use delivery::{AgentAckConf, DeliveryStats};
+pub use failover::MasterHealth;
+#[cfg(test)]
+pub(crate) use logic::VisualizationAgentLogic;
+pub use msg::{
+    AgentQueuePolicy, CompactConnectionMsg, ConnectionMsg, VisualizationAgentBehaviourEvent, VisualizationAgentHandlerEvent, VisualizationAgentMsg, VisualizationAgentSdkEvent,
+    DEFAULT_AGENT_QUEUE_LIMIT, DEFAULT_CONN_GC_TIMEOUT_MS, DEFAULT_MASTER_TIMEOUT_MS, DEFAULT_MAX_REPORT_BYTES, MAX_CONN_STATS_SEND,
+};
+pub use sdk::VisualizationAgentSdk;
@@ -1,9 +1,34 @@
+use std::collections::BTreeMap;
+
 use atm0s_sdn_identity::{ConnId, NodeId};
 use serde::{Deserialize, Serialize};
 
-use crate::identity::{ConnectionMetric, ConnectionStatus};
+use crate::identity::{AppMetric, ConnectionMetric, ConnectionStatus, HostStats};
+use crate::MasterStatus;
 
+/// Default max connections per `NodeConnections` report
 pub const MAX_CONN_STATS_SEND: usize = 10;
+/// Default report payload budget, leaves room for the SDN and UDP/IP headers under a 1500 bytes MTU
+pub const DEFAULT_MAX_REPORT_BYTES: usize = 1200;
+pub const LABELS_RESEND_INTERVAL_MS: u64 = 1000 * 60;
+pub const DEFAULT_AGENT_QUEUE_LIMIT: usize = 1024;
+/// Default time a connection stays disconnected before the agent forgets it and reports a tombstone
+pub const DEFAULT_CONN_GC_TIMEOUT_MS: u64 = 1000 * 60;
+/// Default silence after which the agent considers its master lost and fails over
+pub const DEFAULT_MASTER_TIMEOUT_MS: u64 = 1000 * 10;
+
+/// What the agent does with new reports once its outgoing queue reaches the limit
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum AgentQueuePolicy {
+    /// Drop the oldest queued report
+    #[default]
+    DropOldest,
+    /// Drop queued reports which the new one supersedes: older ones of the same kind, or for connection reports and
+    /// tombstones the batches of the same connections. The oldest report is dropped when none is superseded.
+    LatestPerKind,
+    /// Stop queueing new reports until the queue is drained
+    Pause,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct ConnectionMsg {
@@ -17,19 +42,86 @@ pub struct ConnectionMsg {
     pub latest_updated_at: u64,
 }
 
+/// `ConnectionMsg` with the address replaced by its index in the report dictionary
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct CompactConnectionMsg {
+    pub conn_id: u64,
+    pub protocol: u8,
+    pub addr: u32,
+    pub node_id: NodeId,
+    pub direction: u8,
+    pub status: ConnectionStatus,
+    pub metric: ConnectionMetric,
+    pub latest_updated_at: u64,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum VisualizationAgentBehaviourEvent {
     ConnectionStats(ConnId, NodeId, ConnectionMetric),
+    // sequence number acked by the master
+    ReportAck(u64),
+    MasterHeartbeat(MasterStatus),
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum VisualizationAgentHandlerEvent {}
 
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum VisualizationAgentSdkEvent {
+    RegisterGauge(String),
+    RegisterCounter(String),
+    // name, bucket upper bounds
+    RegisterHistogram(String, Vec<u64>),
+    SetGauge(String, i64),
+    IncCounter(String, u64),
+    Observe(String, u64),
+    // labels replacing the current ones of the node
+    SetLabels(BTreeMap<String, String>),
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum VisualizationAgentMsg {
     // node_id, address, timestamp
     NodePing(NodeId, String, u64),
 
-    // node_id, list connections with length not greater than 20
+    // node_id, list connections split by the agent `max_conns_per_report` and `max_report_bytes`
     NodeConnections(NodeId, Vec<ConnectionMsg>),
+
+    // node_id, labels such as region, zone, role, version or hostname
+    NodeLabels(NodeId, BTreeMap<String, String>),
+
+    // node_id, timestamp, application metrics published through VisualizationAgentSdk
+    NodeAppMetrics(NodeId, u64, Vec<AppMetric>),
+
+    // node_id, total reports dropped by the agent queue since start
+    NodeDroppedReports(NodeId, u64),
+
+    // sequence number, report which the master must ack when the agent runs in ack mode
+    Reliable(u64, Box<VisualizationAgentMsg>),
+
+    // node_id, ids of connections garbage collected by the agent
+    NodeConnectionsRemoved(NodeId, Vec<u64>),
+
+    // node_id, address dictionary, connections referencing it, see `NodeConnections`
+    NodeConnectionsCompact(NodeId, Vec<String>, Vec<CompactConnectionMsg>),
+
+    // node_id, timestamp, host stats sent after the ping when enabled, a separate variant keeps pings readable by older masters
+    NodeHostStats(NodeId, u64, HostStats),
+}
+
+impl VisualizationAgentMsg {
+    /// The node which sent the report
+    pub fn node_id(&self) -> NodeId {
+        match self {
+            VisualizationAgentMsg::NodePing(node_id, ..)
+            | VisualizationAgentMsg::NodeConnections(node_id, _)
+            | VisualizationAgentMsg::NodeLabels(node_id, _)
+            | VisualizationAgentMsg::NodeAppMetrics(node_id, ..)
+            | VisualizationAgentMsg::NodeDroppedReports(node_id, _)
+            | VisualizationAgentMsg::NodeConnectionsRemoved(node_id, _)
+            | VisualizationAgentMsg::NodeConnectionsCompact(node_id, ..)
+            | VisualizationAgentMsg::NodeHostStats(node_id, ..) => *node_id,
+            VisualizationAgentMsg::Reliable(_, msg) => msg.node_id(),
+        }
+    }
 }
@@ -0,0 +1,108 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+
+use atm0s_sdn_utils::awaker::Awaker;
+use parking_lot::{Mutex, RwLock};
+
+use super::{delivery::DeliveryStats, failover::MasterHealth, msg::VisualizationAgentSdkEvent};
+
+/// Lets in-process code publish application metrics which are shipped with the agent reports.
+pub struct VisualizationAgentSdk {
+    queue: Arc<Mutex<VecDeque<VisualizationAgentSdkEvent>>>,
+    awaker: Arc<RwLock<Option<Arc<dyn Awaker>>>>,
+    delivery_stats: Arc<RwLock<Option<DeliveryStats>>>,
+    master_health: Arc<RwLock<MasterHealth>>,
+}
+
+impl Clone for VisualizationAgentSdk {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+            awaker: self.awaker.clone(),
+            delivery_stats: self.delivery_stats.clone(),
+            master_health: self.master_health.clone(),
+        }
+    }
+}
+
+impl Default for VisualizationAgentSdk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VisualizationAgentSdk {
+    pub fn new() -> Self {
+        Self {
+            queue: Arc::new(Mutex::new(VecDeque::new())),
+            awaker: Arc::new(RwLock::new(None)),
+            delivery_stats: Arc::new(RwLock::new(None)),
+            master_health: Arc::new(RwLock::new(MasterHealth::default())),
+        }
+    }
+
+    pub(crate) fn set_awaker(&self, awaker: Arc<dyn Awaker>) {
+        self.awaker.write().replace(awaker);
+    }
+
+    pub(crate) fn pop_event(&self) -> Option<VisualizationAgentSdkEvent> {
+        self.queue.lock().pop_front()
+    }
+
+    pub(crate) fn set_delivery_stats(&self, stats: Option<DeliveryStats>) {
+        *self.delivery_stats.write() = stats;
+    }
+
+    /// Sent, acked, retried and lost report counters, None unless the agent runs in ack mode
+    pub fn delivery_stats(&self) -> Option<DeliveryStats> {
+        *self.delivery_stats.read()
+    }
+
+    pub(crate) fn set_master_health(&self, health: MasterHealth) {
+        *self.master_health.write() = health;
+    }
+
+    /// Which master answered last, the elected leader and whether reports still get an answer
+    pub fn master_health(&self) -> MasterHealth {
+        self.master_health.read().clone()
+    }
+
+    fn push_event(&self, event: VisualizationAgentSdkEvent) {
+        self.queue.lock().push_back(event);
+        if let Some(awaker) = self.awaker.read().as_ref() {
+            awaker.notify();
+        }
+    }
+
+    pub fn register_gauge(&self, name: &str) {
+        self.push_event(VisualizationAgentSdkEvent::RegisterGauge(name.to_string()));
+    }
+
+    pub fn register_counter(&self, name: &str) {
+        self.push_event(VisualizationAgentSdkEvent::RegisterCounter(name.to_string()));
+    }
+
+    /// Register a histogram with the given bucket upper bounds, histograms which are not registered use `DEFAULT_HISTOGRAM_BUCKETS`
+    pub fn register_histogram(&self, name: &str, bounds: Vec<u64>) {
+        self.push_event(VisualizationAgentSdkEvent::RegisterHistogram(name.to_string(), bounds));
+    }
+
+    pub fn set_gauge(&self, name: &str, value: i64) {
+        self.push_event(VisualizationAgentSdkEvent::SetGauge(name.to_string(), value));
+    }
+
+    pub fn inc_counter(&self, name: &str, delta: u64) {
+        self.push_event(VisualizationAgentSdkEvent::IncCounter(name.to_string(), delta));
+    }
+
+    pub fn observe(&self, name: &str, value: u64) {
+        self.push_event(VisualizationAgentSdkEvent::Observe(name.to_string(), value));
+    }
+
+    /// Replace the node labels, the master gets them with the next report if they changed
+    pub fn set_labels(&self, labels: BTreeMap<String, String>) {
+        self.push_event(VisualizationAgentSdkEvent::SetLabels(labels));
+    }
+}
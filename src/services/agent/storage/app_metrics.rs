@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+
+use log::warn;
+
+use crate::identity::{AppHistogram, AppMetric, AppMetricValue, DEFAULT_HISTOGRAM_BUCKETS};
+use crate::VisualizationAgentSdkEvent;
+
+pub struct AppMetricsStorage {
+    metrics: BTreeMap<String, AppMetricValue>,
+}
+
+impl AppMetricsStorage {
+    pub fn new() -> Self {
+        Self { metrics: BTreeMap::new() }
+    }
+
+    pub fn apply(&mut self, event: VisualizationAgentSdkEvent) {
+        match event {
+            VisualizationAgentSdkEvent::RegisterGauge(name) => {
+                self.metrics.entry(name).or_insert(AppMetricValue::Gauge(0));
+            }
+            VisualizationAgentSdkEvent::RegisterCounter(name) => {
+                self.metrics.entry(name).or_insert(AppMetricValue::Counter(0));
+            }
+            VisualizationAgentSdkEvent::RegisterHistogram(name, bounds) => {
+                self.metrics.entry(name).or_insert_with(|| AppMetricValue::Histogram(AppHistogram::new(bounds)));
+            }
+            VisualizationAgentSdkEvent::SetGauge(name, value) => match self.metrics.entry(name).or_insert(AppMetricValue::Gauge(0)) {
+                AppMetricValue::Gauge(current) => *current = value,
+                _ => warn!("[VisualizationAgentService][AppMetricsStorage] metric is not a gauge"),
+            },
+            VisualizationAgentSdkEvent::IncCounter(name, delta) => match self.metrics.entry(name).or_insert(AppMetricValue::Counter(0)) {
+                AppMetricValue::Counter(current) => *current = current.saturating_add(delta),
+                _ => warn!("[VisualizationAgentService][AppMetricsStorage] metric is not a counter"),
+            },
+            VisualizationAgentSdkEvent::Observe(name, value) => match self
+                .metrics
+                .entry(name)
+                .or_insert_with(|| AppMetricValue::Histogram(AppHistogram::new(DEFAULT_HISTOGRAM_BUCKETS.to_vec())))
+            {
+                AppMetricValue::Histogram(histogram) => histogram.observe(value),
+                _ => warn!("[VisualizationAgentService][AppMetricsStorage] metric is not a histogram"),
+            },
+            VisualizationAgentSdkEvent::SetLabels(_) => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.metrics.is_empty()
+    }
+
+    pub fn list_metrics(&self) -> Vec<AppMetric> {
+        self.metrics
+            .iter()
+            .map(|(name, value)| AppMetric {
+                name: name.clone(),
+                value: value.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_apply_sdk_events_by_metric_kind() {
+        let mut storage = AppMetricsStorage::new();
+        storage.apply(VisualizationAgentSdkEvent::RegisterCounter(String::from("sessions")));
+        storage.apply(VisualizationAgentSdkEvent::IncCounter(String::from("sessions"), 2));
+        storage.apply(VisualizationAgentSdkEvent::IncCounter(String::from("sessions"), 3));
+        storage.apply(VisualizationAgentSdkEvent::SetGauge(String::from("queue_depth"), 7));
+        storage.apply(VisualizationAgentSdkEvent::SetGauge(String::from("queue_depth"), -1));
+        storage.apply(VisualizationAgentSdkEvent::RegisterHistogram(String::from("latency"), vec![10, 100]));
+        storage.apply(VisualizationAgentSdkEvent::Observe(String::from("latency"), 50));
+
+        let metrics = storage.list_metrics();
+        assert_eq!(
+            metrics,
+            vec![
+                AppMetric {
+                    name: String::from("latency"),
+                    value: AppMetricValue::Histogram(AppHistogram {
+                        bounds: vec![10, 100],
+                        counts: vec![0, 1, 0],
+                        count: 1,
+                        sum: 50,
+                    }),
+                },
+                AppMetric {
+                    name: String::from("queue_depth"),
+                    value: AppMetricValue::Gauge(-1),
+                },
+                AppMetric {
+                    name: String::from("sessions"),
+                    value: AppMetricValue::Counter(5),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_ignore_event_with_mismatched_kind() {
+        let mut storage = AppMetricsStorage::new();
+        storage.apply(VisualizationAgentSdkEvent::RegisterGauge(String::from("queue_depth")));
+        storage.apply(VisualizationAgentSdkEvent::IncCounter(String::from("queue_depth"), 1));
+
+        assert_eq!(
+            storage.list_metrics(),
+            vec![AppMetric {
+                name: String::from("queue_depth"),
+                value: AppMetricValue::Gauge(0),
+            }]
+        );
+    }
+}
@@ -81,11 +81,27 @@ impl ConnectionStorage {
         }
     }
 
+    /// Drop connections which stayed disconnected for `ttl_ms`, returns their ids
+    pub fn remove_expired(&mut self, now: u64, ttl_ms: u64) -> Vec<u64> {
+        let expired: Vec<u64> = self
+            .conns
+            .iter()
+            .filter(|(_, conn)| conn.status == ConnectionStatus::DISCONNECTED && now >= conn.latest_updated_at + ttl_ms)
+            .map(|(uuid, _)| *uuid)
+            .collect();
+        for uuid in expired.iter() {
+            self.conns.remove(uuid);
+        }
+        expired
+    }
+
+    /// Connections in id order, so reports do not depend on the map iteration order
     pub fn list_conns(&self) -> Vec<ConnectionNode> {
         let mut ret_val = Vec::<ConnectionNode>::new();
         for (_, conn) in self.conns.iter() {
             ret_val.push(conn.clone());
         }
+        ret_val.sort_by_key(|conn| conn.uuid);
         ret_val
     }
 }
@@ -1,3 +1,5 @@
+mod app_metrics;
 mod connection;
 
+pub use app_metrics::AppMetricsStorage;
 pub use connection::{ConnectionModifyData, ConnectionNode, ConnectionStorage};
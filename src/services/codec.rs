@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+
+use atm0s_sdn_network::msg::{MsgHeader, TransportMsg};
+
+use crate::{CompactConnectionMsg, ConnectionMsg, MasterReplicationMsg, VisualizationAgentMsg};
+
+/// Header `meta` value of a payload compressed with lz4, size prepended
+pub const META_COMPRESSED_LZ4: u8 = 1;
+/// Header `meta` values of master to master replication payloads, plain and lz4 compressed
+pub const META_REPLICATION: u8 = 2;
+pub const META_REPLICATION_LZ4: u8 = 3;
+/// Smaller payloads are sent as is, compression would not pay off
+pub const COMPRESS_MIN_BYTES: usize = 256;
+/// Compressed payloads announcing a larger decompressed size are rejected before allocating
+pub const MAX_DECOMPRESSED_BYTES: usize = 16 * 1024 * 1024;
+
+/// Serialize an agent report, compressing it when asked and worth it. Compression is flagged in the header `meta`
+/// so receivers decode both forms.
+pub fn encode_agent_msg(header: MsgHeader, msg: &VisualizationAgentMsg, compress: bool) -> TransportMsg {
+    let payload = bincode::serialize(msg).expect("Should serialize payload");
+    if compress {
+        if let Some(compressed) = compress_payload(&payload) {
+            return TransportMsg::build_raw(header.set_meta(META_COMPRESSED_LZ4), &compressed);
+        }
+    }
+    TransportMsg::build_raw(header, &payload)
+}
+
+pub fn decode_agent_msg(msg: &TransportMsg) -> Option<VisualizationAgentMsg> {
+    match msg.header.meta {
+        0 => msg.get_payload_bincode().ok(),
+        META_COMPRESSED_LZ4 => bincode::deserialize(&decompress_payload(msg.payload())?).ok(),
+        _ => None,
+    }
+}
+
+/// Replication messages are always compressed when worth it, they carry whole node states
+pub fn encode_replication_msg(header: MsgHeader, msg: &MasterReplicationMsg) -> TransportMsg {
+    let payload = bincode::serialize(msg).expect("Should serialize payload");
+    match compress_payload(&payload) {
+        Some(compressed) => TransportMsg::build_raw(header.set_meta(META_REPLICATION_LZ4), &compressed),
+        None => TransportMsg::build_raw(header.set_meta(META_REPLICATION), &payload),
+    }
+}
+
+pub fn decode_replication_msg(msg: &TransportMsg) -> Option<MasterReplicationMsg> {
+    match msg.header.meta {
+        META_REPLICATION => msg.get_payload_bincode().ok(),
+        META_REPLICATION_LZ4 => bincode::deserialize(&decompress_payload(msg.payload())?).ok(),
+        _ => None,
+    }
+}
+
+fn compress_payload(payload: &[u8]) -> Option<Vec<u8>> {
+    if payload.len() < COMPRESS_MIN_BYTES {
+        return None;
+    }
+    let compressed = lz4_flex::compress_prepend_size(payload);
+    if compressed.len() < payload.len() {
+        Some(compressed)
+    } else {
+        None
+    }
+}
+
+/// The size prepended by lz4 is checked against `MAX_DECOMPRESSED_BYTES`, it comes from the sender
+fn decompress_payload(payload: &[u8]) -> Option<Vec<u8>> {
+    let size: [u8; 4] = payload.get(..4)?.try_into().ok()?;
+    if u32::from_le_bytes(size) as usize > MAX_DECOMPRESSED_BYTES {
+        return None;
+    }
+    lz4_flex::decompress_size_prepended(payload).ok()
+}
+
+/// Replace the repeated address strings of a connections report by indexes into a per report dictionary
+pub fn compact_agent_msg(msg: VisualizationAgentMsg) -> VisualizationAgentMsg {
+    match msg {
+        VisualizationAgentMsg::NodeConnections(node_id, conns) => {
+            let mut addrs = Vec::<String>::new();
+            let mut indexes = HashMap::<String, u32>::new();
+            let conns = conns
+                .into_iter()
+                .map(|conn| {
+                    let addr = *indexes.entry(conn.addr).or_insert_with_key(|addr| {
+                        addrs.push(addr.clone());
+                        (addrs.len() - 1) as u32
+                    });
+                    CompactConnectionMsg {
+                        conn_id: conn.conn_id,
+                        protocol: conn.protocol,
+                        addr,
+                        node_id: conn.node_id,
+                        direction: conn.direction,
+                        status: conn.status,
+                        metric: conn.metric,
+                        latest_updated_at: conn.latest_updated_at,
+                    }
+                })
+                .collect();
+            VisualizationAgentMsg::NodeConnectionsCompact(node_id, addrs, conns)
+        }
+        VisualizationAgentMsg::Reliable(seq, msg) => VisualizationAgentMsg::Reliable(seq, Box::new(compact_agent_msg(*msg))),
+        msg => msg,
+    }
+}
+
+/// Reverse of `compact_agent_msg`, None when a connection points outside the dictionary
+pub fn expand_agent_msg(msg: VisualizationAgentMsg) -> Option<VisualizationAgentMsg> {
+    match msg {
+        VisualizationAgentMsg::NodeConnectionsCompact(node_id, addrs, conns) => {
+            let conns = conns
+                .into_iter()
+                .map(|conn| {
+                    Some(ConnectionMsg {
+                        conn_id: conn.conn_id,
+                        protocol: conn.protocol,
+                        addr: addrs.get(conn.addr as usize)?.clone(),
+                        node_id: conn.node_id,
+                        direction: conn.direction,
+                        status: conn.status,
+                        metric: conn.metric,
+                        latest_updated_at: conn.latest_updated_at,
+                    })
+                })
+                .collect::<Option<_>>()?;
+            Some(VisualizationAgentMsg::NodeConnections(node_id, conns))
+        }
+        VisualizationAgentMsg::Reliable(seq, msg) => Some(VisualizationAgentMsg::Reliable(seq, Box::new(expand_agent_msg(*msg)?))),
+        msg => Some(msg),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        collector::{NodeConnectionData, NodeData},
+        ConnectionMetric, ConnectionStatus,
+    };
+
+    use super::*;
+
+    fn conns(count: u64) -> VisualizationAgentMsg {
+        VisualizationAgentMsg::NodeConnections(
+            1,
+            (0..count)
+                .map(|index| ConnectionMsg {
+                    conn_id: index,
+                    protocol: 1,
+                    addr: format!("/ip4/192.168.1.{}/udp/10000", index % 2),
+                    node_id: 2,
+                    direction: 0,
+                    status: ConnectionStatus::CONNECTED,
+                    metric: ConnectionMetric {
+                        latency: 10,
+                        bandwidth: 1000,
+                        loss_percent: 0,
+                    },
+                    latest_updated_at: 0,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn should_compress_large_payload_and_flag_header() {
+        let msg = conns(20);
+        let plain = encode_agent_msg(MsgHeader::new(), &msg, false);
+        let compressed = encode_agent_msg(MsgHeader::new(), &msg, true);
+
+        assert_eq!(plain.header.meta, 0);
+        assert_eq!(compressed.header.meta, META_COMPRESSED_LZ4);
+        assert!(compressed.payload().len() < plain.payload().len());
+        assert_eq!(decode_agent_msg(&plain), Some(msg.clone()));
+        assert_eq!(decode_agent_msg(&compressed), Some(msg));
+    }
+
+    #[test]
+    fn should_not_compress_small_payload() {
+        let msg = VisualizationAgentMsg::NodePing(1, String::from("127.0.0.1"), 0);
+        let encoded = encode_agent_msg(MsgHeader::new(), &msg, true);
+
+        assert_eq!(encoded.header.meta, 0);
+        assert_eq!(decode_agent_msg(&encoded), Some(msg));
+    }
+
+    #[test]
+    fn node_ping_should_keep_the_baseline_wire_format() {
+        let msg = VisualizationAgentMsg::NodePing(1, String::from("a"), 2);
+        let mut expected = vec![0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'a'];
+        expected.extend_from_slice(&2u64.to_le_bytes());
+
+        assert_eq!(bincode::serialize(&msg).expect("should serialize"), expected);
+    }
+
+    #[test]
+    fn replication_msg_should_not_decode_as_agent_msg() {
+        let msg = MasterReplicationMsg::Hello(1);
+        let encoded = encode_replication_msg(MsgHeader::new(), &msg);
+
+        assert_eq!(encoded.header.meta, META_REPLICATION);
+        assert_eq!(decode_agent_msg(&encoded), None);
+        assert_eq!(decode_replication_msg(&encoded), Some(msg));
+        assert_eq!(decode_replication_msg(&encode_agent_msg(MsgHeader::new(), &conns(20), true)), None);
+    }
+
+    #[test]
+    fn replicated_nodes_should_round_trip() {
+        let mut node = NodeData::new(1, String::from("127.0.0.1"), 100);
+        node.conns = (0..20)
+            .map(|id| NodeConnectionData {
+                id,
+                node_id: 2,
+                protocol: 1,
+                addr: String::from("/ip4/192.168.1.2/udp/10000"),
+                metric: ConnectionMetric {
+                    latency: 10,
+                    bandwidth: 1000,
+                    loss_percent: 0,
+                },
+                status: ConnectionStatus::CONNECTED,
+                last_updated_at: 100,
+                direction: 0,
+            })
+            .collect();
+        let msg = MasterReplicationMsg::Nodes { from: 1, nodes: vec![node] };
+        let encoded = encode_replication_msg(MsgHeader::new(), &msg);
+
+        assert_eq!(encoded.header.meta, META_REPLICATION_LZ4);
+        assert_eq!(decode_replication_msg(&encoded), Some(msg));
+    }
+
+    #[test]
+    fn should_dedup_addresses_and_expand_back() {
+        let msg = VisualizationAgentMsg::Reliable(7, Box::new(conns(10)));
+        let compact = compact_agent_msg(msg.clone());
+
+        match &compact {
+            VisualizationAgentMsg::Reliable(7, inner) => match inner.as_ref() {
+                VisualizationAgentMsg::NodeConnectionsCompact(1, addrs, conns) => {
+                    assert_eq!(addrs.len(), 2);
+                    assert_eq!(conns.len(), 10);
+                }
+                other => panic!("unexpected {:?}", other),
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(bincode::serialized_size(&compact).expect("should size") < bincode::serialized_size(&msg).expect("should size"));
+        assert_eq!(expand_agent_msg(compact), Some(msg));
+    }
+
+    #[test]
+    fn should_reject_compact_report_with_index_outside_dictionary() {
+        let msg = match compact_agent_msg(conns(4)) {
+            VisualizationAgentMsg::NodeConnectionsCompact(node_id, mut addrs, conns) => {
+                addrs.pop();
+                VisualizationAgentMsg::NodeConnectionsCompact(node_id, addrs, conns)
+            }
+            other => panic!("unexpected {:?}", other),
+        };
+
+        assert_eq!(expand_agent_msg(VisualizationAgentMsg::Reliable(1, Box::new(msg))), None);
+    }
+
+    #[test]
+    fn should_reject_compressed_payload_announcing_oversized_content() {
+        let mut payload = ((MAX_DECOMPRESSED_BYTES + 1) as u32).to_le_bytes().to_vec();
+        payload.extend_from_slice(&[0; 16]);
+        let msg = TransportMsg::build_raw(MsgHeader::new().set_meta(META_COMPRESSED_LZ4), &payload);
+
+        assert_eq!(decode_agent_msg(&msg), None);
+        assert_eq!(decompress_payload(&[1, 0]), None);
+    }
+}
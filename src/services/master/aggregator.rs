@@ -0,0 +1,370 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+use atm0s_sdn_identity::NodeId;
+
+use crate::{
+    collector::{InterRegionLink, LabelSelector, NodeData, RegionDetail, RegionHealth, RegionSummary, SdnMonitorController},
+    identity::{ConnectionStatus, CONNECTION_TIMEOUT_MS},
+    VisualizationAgentMsg,
+};
+
+use super::replication::MasterReplicationMsg;
+
+/// Which nodes belong to the region of an aggregator
+#[derive(Debug, Clone)]
+pub enum RegionScope {
+    /// Nodes whose labels match, nodes without labels belong to no region. Reports of a node are kept until its
+    /// labels are known
+    Labels(LabelSelector),
+    /// Nodes with id in the inclusive range
+    NodeRange(NodeId, NodeId),
+}
+
+/// Settings of the aggregator mode, where a master keeps the reports of its region and sends summaries upstream
+#[derive(Debug, Clone)]
+pub struct AggregatorConf {
+    pub region: String,
+    pub scope: RegionScope,
+    /// Master receiving the region summaries and the reports of nodes outside of the region
+    pub upstream: NodeId,
+    pub summary_interval_ms: u64,
+    /// Byte budget of a single summary or detail message before compression
+    pub max_msg_bytes: usize,
+    /// Compress the agent reports forwarded upstream, only once the upstream master decodes compressed reports
+    pub compress_forwarded: bool,
+}
+
+impl AggregatorConf {
+    pub fn new(region: &str, scope: RegionScope, upstream: NodeId) -> Self {
+        Self {
+            region: region.to_string(),
+            scope,
+            upstream,
+            summary_interval_ms: 5000,
+            max_msg_bytes: 1200,
+            compress_forwarded: false,
+        }
+    }
+}
+
+/// Pre-aggregates the region state: health counters and the links leaving the region are sent upstream,
+/// the node detail only when the upstream master asks for it.
+pub struct RegionAggregator {
+    node_id: NodeId,
+    conf: AggregatorConf,
+    controller: SdnMonitorController,
+    // nodes whose labels are known to be out of a label scope
+    foreign: HashSet<NodeId>,
+    next_summary_at: u64,
+    outgoing: VecDeque<(NodeId, MasterReplicationMsg)>,
+}
+
+impl RegionAggregator {
+    pub fn new(node_id: NodeId, conf: AggregatorConf, controller: SdnMonitorController) -> Self {
+        Self {
+            node_id,
+            conf,
+            controller,
+            foreign: HashSet::new(),
+            next_summary_at: 0,
+            outgoing: VecDeque::new(),
+        }
+    }
+
+    pub fn upstream(&self) -> NodeId {
+        self.conf.upstream
+    }
+
+    pub fn compress_forwarded(&self) -> bool {
+        self.conf.compress_forwarded
+    }
+
+    fn in_scope(&self, node_id: NodeId, labels: &BTreeMap<String, String>) -> bool {
+        match &self.conf.scope {
+            RegionScope::Labels(selector) => !labels.is_empty() && selector.matches(labels),
+            RegionScope::NodeRange(min, max) => (*min..=*max).contains(&node_id),
+        }
+    }
+
+    /// Whether the report belongs to a node outside of the region and should go to the upstream master as is
+    pub fn should_forward(&mut self, msg: &VisualizationAgentMsg) -> bool {
+        let node_id = msg.node_id();
+        if let VisualizationAgentMsg::NodeLabels(_, labels) = msg {
+            if self.in_scope(node_id, labels) {
+                self.foreign.remove(&node_id);
+            } else {
+                self.foreign.insert(node_id);
+            }
+        }
+        match self.conf.scope {
+            RegionScope::Labels(_) => self.foreign.contains(&node_id),
+            RegionScope::NodeRange(..) => !self.in_scope(node_id, &BTreeMap::new()),
+        }
+    }
+
+    fn region_nodes(&self) -> Vec<NodeData> {
+        let mut nodes: Vec<NodeData> = self
+            .controller
+            .get_nodes_shared()
+            .iter()
+            .filter(|node| !self.foreign.contains(&node.id) && self.in_scope(node.id, &node.labels))
+            .map(|node| node.as_ref().clone())
+            .collect();
+        nodes.sort_by_key(|node| node.id);
+        nodes
+    }
+
+    pub fn on_tick(&mut self, now_ms: u64) {
+        if now_ms < self.next_summary_at {
+            return;
+        }
+        self.next_summary_at = now_ms + self.conf.summary_interval_ms;
+
+        let nodes = self.region_nodes();
+        let members: HashSet<NodeId> = nodes.iter().map(|node| node.id).collect();
+        let mut health = RegionHealth {
+            nodes: nodes.len() as u32,
+            ..Default::default()
+        };
+        let mut latency_sum = 0;
+        let mut links = vec![];
+        for node in nodes.iter() {
+            if now_ms.saturating_sub(node.last_ping_ts) <= CONNECTION_TIMEOUT_MS {
+                health.online += 1;
+            }
+            for conn in node.conns.iter() {
+                health.links += 1;
+                match conn.status {
+                    ConnectionStatus::CONNECTED => {
+                        latency_sum += conn.metric.latency as u64;
+                        health.max_loss_percent = health.max_loss_percent.max(conn.metric.loss_percent);
+                    }
+                    ConnectionStatus::DISCONNECTED => health.links_down += 1,
+                }
+                if !members.contains(&conn.node_id) {
+                    links.push(InterRegionLink {
+                        node_id: node.id,
+                        conn_id: conn.id,
+                        dest: conn.node_id,
+                        status: conn.status.clone(),
+                        metric: conn.metric.clone(),
+                    });
+                }
+            }
+        }
+        let connected = health.links - health.links_down;
+        if connected > 0 {
+            health.avg_latency_ms = (latency_sum / connected as u64) as u32;
+        }
+
+        let upstream = self.conf.upstream;
+        let summary = RegionSummary {
+            region: self.conf.region.clone(),
+            aggregator: self.node_id,
+            ts: now_ms,
+            health,
+            links: vec![],
+        };
+        for links in split_by_bytes(links, self.conf.max_msg_bytes) {
+            let part = RegionSummary { links, ..summary.clone() };
+            self.outgoing.push_back((upstream, MasterReplicationMsg::RegionSummary(part)));
+        }
+    }
+
+    /// Send the region nodes to the requesting master, split in parts fitting the byte budget
+    pub fn on_detail_request(&mut self, from: NodeId, region: &str, now_ms: u64) {
+        if region != self.conf.region {
+            return;
+        }
+        for nodes in split_by_bytes(self.region_nodes(), self.conf.max_msg_bytes) {
+            let detail = RegionDetail {
+                region: region.to_string(),
+                ts: now_ms,
+                nodes,
+            };
+            self.outgoing.push_back((from, MasterReplicationMsg::RegionDetail { from: self.node_id, detail }));
+        }
+    }
+
+    pub fn pop_msg(&mut self) -> Option<(NodeId, MasterReplicationMsg)> {
+        self.outgoing.pop_front()
+    }
+}
+
+/// Split items in batches of at most `max_bytes` bincode encoded bytes, an item larger than that goes alone.
+/// Always returns at least one, possibly empty, batch.
+fn split_by_bytes<T: serde::Serialize>(items: Vec<T>, max_bytes: usize) -> Vec<Vec<T>> {
+    let mut batches: Vec<Vec<T>> = vec![vec![]];
+    let mut size = 0;
+    for item in items {
+        let item_size = bincode::serialized_size(&item).unwrap_or(0) as usize;
+        if size + item_size > max_bytes && batches.last().is_some_and(|batch| !batch.is_empty()) {
+            batches.push(vec![]);
+            size = 0;
+        }
+        batches.last_mut().expect("Should have a batch").push(item);
+        size += item_size;
+    }
+    batches
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        collector::{NodeConnectionData, NodeConnections},
+        ConnectionMetric,
+    };
+
+    use super::*;
+
+    fn node(id: NodeId, ts: u64, conns: &[(NodeId, ConnectionStatus, u32)]) -> NodeData {
+        let mut node = NodeData::new(id, format!("/ip4/127.0.0.{}", id), ts);
+        node.conns = conns
+            .iter()
+            .map(|(dest, status, loss_percent)| NodeConnectionData {
+                id: *dest as u64,
+                node_id: *dest,
+                protocol: 1,
+                addr: format!("/ip4/127.0.0.{}", dest),
+                metric: ConnectionMetric {
+                    latency: 20,
+                    loss_percent: *loss_percent,
+                    bandwidth: 100,
+                },
+                status: status.clone(),
+                last_updated_at: ts,
+                direction: 0,
+            })
+            .collect::<NodeConnections>();
+        node
+    }
+
+    fn controller(nodes: Vec<NodeData>) -> SdnMonitorController {
+        let mut controller = SdnMonitorController::new();
+        controller.update_batch(0, |storage, events| {
+            for node in nodes {
+                events.append(&mut storage.merge_replicated_node(node, 0));
+            }
+        });
+        controller
+    }
+
+    fn drain(aggregator: &mut RegionAggregator) -> Vec<(NodeId, MasterReplicationMsg)> {
+        std::iter::from_fn(|| aggregator.pop_msg()).collect()
+    }
+
+    #[test]
+    fn should_summarize_region_health_and_links_leaving_it() {
+        use ConnectionStatus::{CONNECTED, DISCONNECTED};
+        let controller = controller(vec![
+            node(1, 200000, &[(2, CONNECTED, 0), (9, CONNECTED, 5)]),
+            node(2, 200000, &[(1, CONNECTED, 0), (3, DISCONNECTED, 0)]),
+            node(3, 0, &[]),
+            node(9, 200000, &[(1, CONNECTED, 0)]),
+        ]);
+        let mut aggregator = RegionAggregator::new(1, AggregatorConf::new("eu", RegionScope::NodeRange(1, 3), 100), controller);
+
+        aggregator.on_tick(200000);
+        let msgs = drain(&mut aggregator);
+        assert_eq!(msgs.len(), 1);
+        let (to, msg) = msgs.into_iter().next().expect("should have summary");
+        assert_eq!(to, 100);
+        let summary = match msg {
+            MasterReplicationMsg::RegionSummary(summary) => summary,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(
+            summary.health,
+            RegionHealth {
+                nodes: 3,
+                online: 2,
+                links: 4,
+                links_down: 1,
+                avg_latency_ms: 20,
+                max_loss_percent: 5,
+            }
+        );
+        assert_eq!(summary.links.iter().map(|link| (link.node_id, link.dest)).collect::<Vec<_>>(), vec![(1, 9)]);
+
+        // nothing more until the next interval
+        aggregator.on_tick(204999);
+        assert_eq!(drain(&mut aggregator), vec![]);
+    }
+
+    #[test]
+    fn large_summary_should_be_split_in_parts_merged_upstream() {
+        let conns: Vec<(NodeId, ConnectionStatus, u32)> = (100..150).map(|dest| (dest, ConnectionStatus::CONNECTED, 0)).collect();
+        let controller = controller(vec![node(1, 0, &conns)]);
+        let conf = AggregatorConf {
+            max_msg_bytes: 300,
+            ..AggregatorConf::new("eu", RegionScope::NodeRange(1, 10), 100)
+        };
+        let mut aggregator = RegionAggregator::new(1, conf, controller);
+        let mut upstream = SdnMonitorController::new();
+
+        aggregator.on_tick(0);
+        let msgs = drain(&mut aggregator);
+        assert!(msgs.len() > 1);
+        for (_, msg) in msgs {
+            if let MasterReplicationMsg::RegionSummary(part) = msg {
+                upstream.merge_region_summary(part);
+            }
+        }
+        let summary = upstream.get_region("eu").expect("should have region");
+        assert_eq!(summary.links.len(), 50);
+        assert_eq!(summary.health.links, 50);
+    }
+
+    #[test]
+    fn should_forward_reports_of_nodes_outside_region() {
+        let controller = SdnMonitorController::new();
+        let mut by_range = RegionAggregator::new(1, AggregatorConf::new("eu", RegionScope::NodeRange(1, 10), 100), controller.clone());
+        assert!(!by_range.should_forward(&VisualizationAgentMsg::NodePing(5, String::new(), 0)));
+        assert!(by_range.should_forward(&VisualizationAgentMsg::NodePing(11, String::new(), 0)));
+
+        let selector = LabelSelector::parse("region=eu").expect("should parse");
+        let mut by_labels = RegionAggregator::new(1, AggregatorConf::new("eu", RegionScope::Labels(selector), 100), controller);
+        let labels = |region: &str| BTreeMap::from([(String::from("region"), region.to_string())]);
+        assert!(!by_labels.should_forward(&VisualizationAgentMsg::NodePing(5, String::new(), 0)));
+        assert!(by_labels.should_forward(&VisualizationAgentMsg::NodeLabels(5, labels("us"))));
+        assert!(by_labels.should_forward(&VisualizationAgentMsg::NodePing(5, String::new(), 0)));
+        assert!(!by_labels.should_forward(&VisualizationAgentMsg::NodeLabels(5, labels("eu"))));
+        assert!(!by_labels.should_forward(&VisualizationAgentMsg::NodePing(5, String::new(), 0)));
+    }
+
+    #[test]
+    fn nodes_without_labels_should_be_outside_every_label_region() {
+        let mut labeled = node(1, 0, &[]);
+        labeled.labels = BTreeMap::from([(String::from("region"), String::from("eu"))]);
+        let controller = controller(vec![labeled, node(2, 0, &[])]);
+        let selector = LabelSelector::parse("region!=us").expect("should parse");
+        let mut aggregator = RegionAggregator::new(1, AggregatorConf::new("eu", RegionScope::Labels(selector), 100), controller);
+
+        assert!(aggregator.should_forward(&VisualizationAgentMsg::NodeLabels(2, BTreeMap::new())));
+        aggregator.on_detail_request(100, "eu", 10);
+        match drain(&mut aggregator).as_slice() {
+            [(100, MasterReplicationMsg::RegionDetail { detail, .. })] => {
+                assert_eq!(detail.nodes.iter().map(|node| node.id).collect::<Vec<_>>(), vec![1]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_answer_detail_request_of_own_region() {
+        let controller = controller(vec![node(1, 0, &[]), node(2, 0, &[]), node(20, 0, &[])]);
+        let mut aggregator = RegionAggregator::new(1, AggregatorConf::new("eu", RegionScope::NodeRange(1, 10), 100), controller);
+
+        aggregator.on_detail_request(100, "us", 10);
+        assert_eq!(drain(&mut aggregator), vec![]);
+
+        aggregator.on_detail_request(100, "eu", 10);
+        match drain(&mut aggregator).as_slice() {
+            [(100, MasterReplicationMsg::RegionDetail { from: 1, detail })] => {
+                assert_eq!(detail.ts, 10);
+                assert_eq!(detail.nodes.iter().map(|node| node.id).collect::<Vec<_>>(), vec![1, 2]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -1,26 +1,203 @@
+use std::{collections::HashMap, sync::Arc};
+
 use atm0s_sdn_identity::NodeId;
 use atm0s_sdn_network::behaviour::{BehaviorContext, ConnectionHandler, NetworkBehavior, NetworkBehaviorAction};
-use atm0s_sdn_network::msg::TransportMsg;
+use atm0s_sdn_network::msg::{MsgHeader, TransportMsg};
 use atm0s_sdn_network::transport::{ConnectionRejectReason, ConnectionSender, OutgoingConnectionError};
+use atm0s_sdn_router::RouteRule;
 use atm0s_sdn_utils::vec_dequeue::VecDeque;
 
-use crate::collector::SdnMonitorController;
-use crate::{VisualizationAgentMsg, VisualizationMasterSdk, VISUALIZATION_MASTER_SERVICE};
+use crate::collector::{SdnMonitorController, REGION_DETAIL_TTL_MS};
+use crate::services::codec::{decode_agent_msg, decode_replication_msg, encode_agent_msg, encode_replication_msg, expand_agent_msg};
+use crate::{VisualizationAgentMsg, VisualizationMasterMsg, VisualizationMasterSdk, VISUALIZATION_AGENT_SERVICE, VISUALIZATION_MASTER_SERVICE};
 
+use super::aggregator::{AggregatorConf, RegionAggregator};
 use super::handler::VisualizationMasterHandler;
+use super::ingest::{IngestBackpressure, IngestQueue, IngestWorker, DEFAULT_INGEST_CAPACITY};
 use super::logic::VisualizationMasterLogic;
-use super::msg::{VisualizationMasterBehaviourEvent, VisualizationMasterHandlerEvent};
+use super::msg::{MasterRole, MasterStatus, VisualizationMasterBehaviourEvent, VisualizationMasterHandlerEvent};
+use super::replication::{MasterReplication, MasterReplicationConf, MasterReplicationMsg};
+
+#[derive(Debug, Clone)]
+pub struct VisualizationMasterBehaviourConf {
+    /// Max agent messages waiting for the ingest worker
+    pub ingest_capacity: usize,
+    pub backpressure: IngestBackpressure,
+    /// Exchange node states with other masters so each of them serves the full topology. Host stats, app metrics and
+    /// connection samples are not replicated, each master serves only those of the reports it received.
+    pub replication: Option<MasterReplicationConf>,
+    /// Keep only the reports of a region and send its summary to an upstream master
+    pub aggregator: Option<AggregatorConf>,
+}
 
+impl Default for VisualizationMasterBehaviourConf {
+    fn default() -> Self {
+        Self {
+            ingest_capacity: DEFAULT_INGEST_CAPACITY,
+            backpressure: IngestBackpressure::default(),
+            replication: None,
+            aggregator: None,
+        }
+    }
+}
+
+/// Agent messages are queued to a dedicated ingest worker, so the network plane thread never waits on the store.
+/// Any master accepts region summaries from aggregators and fetches region details for the HTTP drill-down.
 pub struct VisualizationMasterBehaviour<HE, SE> {
-    logic: VisualizationMasterLogic,
+    node_id: NodeId,
+    controller: SdnMonitorController,
+    sdk: VisualizationMasterSdk,
+    replication_conf: Option<MasterReplicationConf>,
+    replication: Option<MasterReplication>,
+    aggregator_conf: Option<AggregatorConf>,
+    aggregator: Option<RegionAggregator>,
+    // when the last region detail part arrived, on the master clock since the aggregator one may differ
+    region_details_at: HashMap<String, u64>,
+    ingest: IngestWorker,
     queue_action: VecDeque<NetworkBehaviorAction<HE, SE>>,
 }
 
 impl<HE, SE> VisualizationMasterBehaviour<HE, SE> {
     pub fn new(controller: SdnMonitorController) -> (Self, VisualizationMasterSdk) {
-        let logic = VisualizationMasterLogic::new(controller.clone());
-        let sdk = VisualizationMasterSdk::new(controller);
-        (Self { logic, queue_action: VecDeque::new() }, sdk)
+        Self::new_with_conf(controller, VisualizationMasterBehaviourConf::default())
+    }
+
+    pub fn new_with_conf(controller: SdnMonitorController, conf: VisualizationMasterBehaviourConf) -> (Self, VisualizationMasterSdk) {
+        let queue = Arc::new(IngestQueue::new(conf.ingest_capacity, conf.backpressure));
+        let sdk = VisualizationMasterSdk::new_with_ingest(controller.clone(), queue.counters());
+        let ingest = IngestWorker::spawn(queue, VisualizationMasterLogic::new(controller.clone()));
+        (
+            Self {
+                node_id: 0,
+                controller,
+                sdk: sdk.clone(),
+                replication_conf: conf.replication,
+                replication: None,
+                aggregator_conf: conf.aggregator,
+                aggregator: None,
+                region_details_at: HashMap::new(),
+                ingest,
+                queue_action: VecDeque::new(),
+            },
+            sdk,
+        )
+    }
+
+    /// Ingest queue of this master, the simulation harness waits on it to read a settled store
+    #[cfg(feature = "testing")]
+    pub(crate) fn ingest_queue(&self) -> Arc<IngestQueue> {
+        self.ingest.queue().clone()
+    }
+
+    fn status(&self) -> MasterStatus {
+        match &self.replication {
+            Some(replication) => replication.status(),
+            None => MasterStatus::elect(self.node_id, &[]),
+        }
+    }
+
+    /// Publish the election result, only the leader fires alerts
+    fn update_status(&mut self) {
+        let status = self.status();
+        self.controller.set_alerts_muted(status.role() != MasterRole::Leader);
+        self.sdk.set_status(status);
+    }
+
+    fn send_to_agent(&mut self, node_id: NodeId, msg: &VisualizationMasterMsg) {
+        let header = MsgHeader::new().set_to_service_id(VISUALIZATION_AGENT_SERVICE).set_route(RouteRule::ToNode(node_id));
+        self.queue_action.push_back(NetworkBehaviorAction::ToNet(TransportMsg::from_payload_bincode(header, msg)));
+    }
+
+    /// Answer pings with a heartbeat and queue reports for the ingest worker. Reports sent in ack mode are acked once
+    /// the queue accepted them, a full queue leaves them to the agent retransmit. Reports forwarded by an aggregator
+    /// are acked by the upstream master.
+    fn on_agent_msg(&mut self, now_ms: u64, msg: VisualizationAgentMsg) {
+        let (msg, seq) = match expand_agent_msg(msg) {
+            Some(VisualizationAgentMsg::Reliable(seq, msg)) => (*msg, Some(seq)),
+            Some(msg) => (msg, None),
+            None => {
+                log::warn!("[VisualizationMaster] drop compact report with an address outside of its dictionary");
+                return;
+            }
+        };
+        let node_id = msg.node_id();
+        if let VisualizationAgentMsg::NodePing(..) = &msg {
+            self.send_to_agent(node_id, &VisualizationMasterMsg::Heartbeat(self.status()));
+        }
+        if let Some(aggregator) = &mut self.aggregator {
+            if aggregator.should_forward(&msg) {
+                // reliable reports keep their sequence, the upstream master acks the agent once it queued them
+                let msg = match seq {
+                    Some(seq) => VisualizationAgentMsg::Reliable(seq, Box::new(msg)),
+                    None => msg,
+                };
+                let header = MsgHeader::new().set_to_service_id(VISUALIZATION_MASTER_SERVICE).set_route(RouteRule::ToNode(aggregator.upstream()));
+                self.queue_action
+                    .push_back(NetworkBehaviorAction::ToNet(encode_agent_msg(header, &msg, aggregator.compress_forwarded())));
+                return;
+            }
+        }
+        match seq {
+            Some(seq) => {
+                if self.ingest.queue().push_acked(msg, now_ms) {
+                    self.send_to_agent(node_id, &VisualizationMasterMsg::ReportAck(seq));
+                }
+            }
+            None => {
+                self.ingest.queue().push(msg, now_ms);
+            }
+        }
+    }
+
+    fn on_replication_msg(&mut self, now_ms: u64, msg: MasterReplicationMsg) {
+        match msg {
+            MasterReplicationMsg::RegionSummary(summary) => self.controller.merge_region_summary(summary),
+            MasterReplicationMsg::RegionDetail { detail, .. } => {
+                self.region_details_at.insert(detail.region.clone(), now_ms);
+                self.controller.merge_region_detail(detail);
+            }
+            MasterReplicationMsg::RegionDetailRequest { from, region } => {
+                if let Some(aggregator) = &mut self.aggregator {
+                    aggregator.on_detail_request(from, &region, now_ms);
+                }
+            }
+            msg => {
+                if let Some(replication) = &mut self.replication {
+                    replication.on_msg(msg, now_ms);
+                }
+            }
+        }
+        self.pop_master_msgs();
+    }
+
+    /// Ask the aggregators for the region details requested through the HTTP drill-down, fresh copies are kept
+    fn request_region_details(&mut self, now_ms: u64) {
+        for region in self.controller.take_region_requests() {
+            if self.region_details_at.get(&region).is_some_and(|at| now_ms.saturating_sub(*at) <= REGION_DETAIL_TTL_MS) {
+                continue;
+            }
+            if let Some(summary) = self.controller.get_region(&region) {
+                self.send_to_master(summary.aggregator, &MasterReplicationMsg::RegionDetailRequest { from: self.node_id, region });
+            }
+        }
+    }
+
+    fn send_to_master(&mut self, node_id: NodeId, msg: &MasterReplicationMsg) {
+        let header = MsgHeader::new().set_to_service_id(VISUALIZATION_MASTER_SERVICE).set_route(RouteRule::ToNode(node_id));
+        self.queue_action.push_back(NetworkBehaviorAction::ToNet(encode_replication_msg(header, msg)));
+    }
+
+    fn pop_master_msgs(&mut self) {
+        let mut msgs = vec![];
+        if let Some(replication) = &mut self.replication {
+            msgs.extend(std::iter::from_fn(|| replication.pop_msg()));
+        }
+        if let Some(aggregator) = &mut self.aggregator {
+            msgs.extend(std::iter::from_fn(|| aggregator.pop_msg()));
+        }
+        for (to, msg) in msgs {
+            self.send_to_master(to, &msg);
+        }
     }
 }
 
@@ -34,15 +211,37 @@ where
         return VISUALIZATION_MASTER_SERVICE;
     }
 
-    fn on_started(&mut self, ctx: &BehaviorContext, now_ms: u64) {}
+    fn on_started(&mut self, ctx: &BehaviorContext, now_ms: u64) {
+        self.node_id = ctx.node_id;
+        if let Some(conf) = self.replication_conf.take() {
+            self.replication = Some(MasterReplication::new(ctx.node_id, conf, self.controller.clone()));
+        }
+        if let Some(conf) = self.aggregator_conf.take() {
+            self.aggregator = Some(RegionAggregator::new(ctx.node_id, conf, self.controller.clone()));
+        }
+        self.update_status();
+    }
 
     fn on_awake(&mut self, ctx: &BehaviorContext, now_ms: u64) {}
 
-    fn on_tick(&mut self, ctx: &BehaviorContext, now_ms: u64, interval_ms: u64) {}
+    fn on_tick(&mut self, ctx: &BehaviorContext, now_ms: u64, interval_ms: u64) {
+        self.ingest.queue().push_tick(now_ms);
+        if let Some(replication) = &mut self.replication {
+            replication.on_tick(now_ms);
+        }
+        if let Some(aggregator) = &mut self.aggregator {
+            aggregator.on_tick(now_ms);
+        }
+        self.request_region_details(now_ms);
+        self.pop_master_msgs();
+        self.update_status();
+    }
 
     fn on_local_msg(&mut self, ctx: &BehaviorContext, now_ms: u64, msg: TransportMsg) {
-        if let Ok(payload) = msg.get_payload_bincode::<VisualizationAgentMsg>() {
-            self.logic.process_agent_msg(payload);
+        if let Some(payload) = decode_agent_msg(&msg) {
+            self.on_agent_msg(now_ms, payload);
+        } else if let Some(payload) = decode_replication_msg(&msg) {
+            self.on_replication_msg(now_ms, payload);
         }
     }
 
@@ -50,7 +249,8 @@ where
         let msg: Result<VisualizationMasterBehaviourEvent, _> = event.try_into();
         match msg {
             Ok(msg) => match msg {
-                VisualizationMasterBehaviourEvent::OnMsg(payload) => self.logic.process_agent_msg(payload),
+                VisualizationMasterBehaviourEvent::OnMsg(payload) => self.on_agent_msg(now_ms, payload),
+                VisualizationMasterBehaviourEvent::OnReplication(payload) => self.on_replication_msg(now_ms, payload),
             },
             Err(_e) => {}
         }
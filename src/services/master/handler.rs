@@ -3,7 +3,7 @@ use atm0s_sdn_network::behaviour::{ConnectionContext, ConnectionHandler, Connect
 use atm0s_sdn_network::transport::ConnectionEvent;
 use atm0s_sdn_utils::vec_dequeue::VecDeque;
 
-use crate::VisualizationAgentMsg;
+use crate::services::codec::{decode_agent_msg, decode_replication_msg};
 
 use super::msg::{VisualizationMasterBehaviourEvent, VisualizationMasterHandlerEvent};
 
@@ -35,10 +35,14 @@ where
     fn on_event(&mut self, ctx: &ConnectionContext, now_ms: u64, event: ConnectionEvent) {
         match event {
             ConnectionEvent::Msg(msg) => {
-                if let Ok(payload) = msg.get_payload_bincode::<VisualizationAgentMsg>() {
-                    let behaviour_event = VisualizationMasterBehaviourEvent::OnMsg(payload);
-                    self.actions.push_back(ConnectionHandlerAction::ToBehaviour(behaviour_event.into()));
-                }
+                let behaviour_event = match decode_agent_msg(&msg) {
+                    Some(payload) => VisualizationMasterBehaviourEvent::OnMsg(payload),
+                    None => match decode_replication_msg(&msg) {
+                        Some(payload) => VisualizationMasterBehaviourEvent::OnReplication(payload),
+                        None => return,
+                    },
+                };
+                self.actions.push_back(ConnectionHandlerAction::ToBehaviour(behaviour_event.into()));
             }
             _ => {}
         }
@@ -0,0 +1,603 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    mem::{discriminant, Discriminant},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use parking_lot::{Condvar, Mutex};
+use serde::{Deserialize, Serialize};
+
+use atm0s_sdn_identity::NodeId;
+
+use crate::VisualizationAgentMsg;
+
+use super::logic::VisualizationMasterLogic;
+
+pub const DEFAULT_INGEST_CAPACITY: usize = 4096;
+
+/// What to do with a new agent message when the ingest queue is full
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IngestBackpressure {
+    /// Drop the oldest pending message
+    #[default]
+    DropOldest,
+    /// Merge into a pending message of the same node and kind, dropping the oldest only if none exists
+    CoalescePerNode,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct IngestStats {
+    /// Messages waiting for the worker
+    pub depth: usize,
+    pub dropped: u64,
+    pub coalesced: u64,
+    pub processed: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct IngestCounters {
+    depth: AtomicUsize,
+    dropped: AtomicU64,
+    coalesced: AtomicU64,
+    processed: AtomicU64,
+}
+
+impl IngestCounters {
+    pub fn snapshot(&self) -> IngestStats {
+        IngestStats {
+            depth: self.depth.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            processed: self.processed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Fold `next` into `pending` when both are the same kind of report from the same node, keeping the latest values.
+/// Returns `next` back when they can not be merged.
+pub fn merge_agent_msg(pending: &mut VisualizationAgentMsg, next: VisualizationAgentMsg) -> Result<(), VisualizationAgentMsg> {
+    if pending.node_id() != next.node_id() {
+        return Err(next);
+    }
+    match (pending, next) {
+        (VisualizationAgentMsg::NodePing(_, addr, ts), VisualizationAgentMsg::NodePing(_, next_addr, next_ts)) => {
+            if next_ts >= *ts {
+                *addr = next_addr;
+                *ts = next_ts;
+            }
+            Ok(())
+        }
+        (VisualizationAgentMsg::NodeConnections(_, conns), VisualizationAgentMsg::NodeConnections(_, next_conns)) => {
+            for next_conn in next_conns {
+                match conns.iter_mut().find(|conn| conn.conn_id == next_conn.conn_id) {
+                    Some(conn) => {
+                        if next_conn.latest_updated_at >= conn.latest_updated_at {
+                            *conn = next_conn;
+                        }
+                    }
+                    None => conns.push(next_conn),
+                }
+            }
+            Ok(())
+        }
+        (VisualizationAgentMsg::NodeLabels(_, labels), VisualizationAgentMsg::NodeLabels(_, next_labels)) => {
+            *labels = next_labels;
+            Ok(())
+        }
+        (VisualizationAgentMsg::NodeAppMetrics(_, ts, metrics), VisualizationAgentMsg::NodeAppMetrics(_, next_ts, next_metrics)) => {
+            if next_ts >= *ts {
+                *ts = next_ts;
+                *metrics = next_metrics;
+            }
+            Ok(())
+        }
+        (VisualizationAgentMsg::NodeConnectionsRemoved(_, ids), VisualizationAgentMsg::NodeConnectionsRemoved(_, next_ids)) => {
+            for id in next_ids {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+            Ok(())
+        }
+        (VisualizationAgentMsg::NodeDroppedReports(_, count), VisualizationAgentMsg::NodeDroppedReports(_, next_count)) => {
+            *count = (*count).max(next_count);
+            Ok(())
+        }
+        (VisualizationAgentMsg::NodeHostStats(_, ts, stats), VisualizationAgentMsg::NodeHostStats(_, next_ts, next_stats)) => {
+            if next_ts >= *ts {
+                *ts = next_ts;
+                *stats = next_stats;
+            }
+            Ok(())
+        }
+        (_, next) => Err(next),
+    }
+}
+
+/// Some(true) when the report removes connections, Some(false) when it reports them
+fn connection_change(msg: &VisualizationAgentMsg) -> Option<bool> {
+    match msg {
+        VisualizationAgentMsg::NodeConnections(..) | VisualizationAgentMsg::NodeConnectionsCompact(..) => Some(false),
+        VisualizationAgentMsg::NodeConnectionsRemoved(..) => Some(true),
+        VisualizationAgentMsg::Reliable(_, msg) => connection_change(msg),
+        _ => None,
+    }
+}
+
+/// `next` must not be merged ahead of `pending` when one removes connections of the node and the other reports them,
+/// a reconnect reuses the connection id so the order decides whether the link is alive.
+fn keeps_order(pending: &VisualizationAgentMsg, next: &VisualizationAgentMsg) -> bool {
+    match (connection_change(pending), connection_change(next)) {
+        (Some(pending_removes), Some(next_removes)) => pending_removes != next_removes && pending.node_id() == next.node_id(),
+        _ => false,
+    }
+}
+
+/// Merge reports of the same node and kind into the first one, returns the coalesced list and the number of merged messages.
+/// Reports which can not be merged, like `Reliable` ones, are kept as their own entry and later ones merge into it.
+/// Connections and removed connections of a node are never merged across each other, see `keeps_order`.
+pub fn coalesce_agent_msgs(msgs: Vec<VisualizationAgentMsg>) -> (Vec<VisualizationAgentMsg>, usize) {
+    let mut ret_val: Vec<VisualizationAgentMsg> = Vec::with_capacity(msgs.len());
+    let mut slots = HashMap::<(NodeId, Discriminant<VisualizationAgentMsg>), usize>::new();
+    let conns_kind = discriminant(&VisualizationAgentMsg::NodeConnections(0, vec![]));
+    let removed_kind = discriminant(&VisualizationAgentMsg::NodeConnectionsRemoved(0, vec![]));
+    let mut merged = 0;
+    for msg in msgs {
+        let node_id = msg.node_id();
+        match connection_change(&msg) {
+            Some(true) => slots.remove(&(node_id, conns_kind)),
+            Some(false) => slots.remove(&(node_id, removed_kind)),
+            None => None,
+        };
+        let key = (node_id, discriminant(&msg));
+        let msg = match slots.get(&key) {
+            Some(index) => match merge_agent_msg(&mut ret_val[*index], msg) {
+                Ok(()) => {
+                    merged += 1;
+                    continue;
+                }
+                Err(msg) => msg,
+            },
+            None => msg,
+        };
+        slots.insert(key, ret_val.len());
+        ret_val.push(msg);
+    }
+    (ret_val, merged)
+}
+
+struct IngestState {
+    // pending messages and whether the agent already got an ack for them, acked ones are never dropped
+    msgs: VecDeque<(VisualizationAgentMsg, bool)>,
+    // latest receive time of the pending messages
+    received_at: u64,
+    // latest tick not yet applied, ticks are never dropped
+    tick: Option<u64>,
+    // a batch was popped and is not applied yet
+    #[cfg(any(test, feature = "testing"))]
+    busy: bool,
+    closed: bool,
+}
+
+/// Messages popped by the ingest worker, with the time the network plane received the latest one
+#[derive(Debug, PartialEq)]
+pub(crate) struct IngestBatch {
+    pub msgs: Vec<VisualizationAgentMsg>,
+    pub received_at: u64,
+    pub tick: Option<u64>,
+}
+
+/// Bounded queue between the network plane thread and the ingest worker.
+pub(crate) struct IngestQueue {
+    state: Mutex<IngestState>,
+    condvar: Condvar,
+    #[cfg(any(test, feature = "testing"))]
+    idle: Condvar,
+    capacity: usize,
+    backpressure: IngestBackpressure,
+    counters: Arc<IngestCounters>,
+}
+
+impl IngestQueue {
+    pub fn new(capacity: usize, backpressure: IngestBackpressure) -> Self {
+        Self {
+            state: Mutex::new(IngestState {
+                msgs: VecDeque::new(),
+                received_at: 0,
+                tick: None,
+                #[cfg(any(test, feature = "testing"))]
+                busy: false,
+                closed: false,
+            }),
+            condvar: Condvar::new(),
+            #[cfg(any(test, feature = "testing"))]
+            idle: Condvar::new(),
+            capacity: capacity.max(1),
+            backpressure,
+            counters: Arc::new(IngestCounters::default()),
+        }
+    }
+
+    pub fn counters(&self) -> Arc<IngestCounters> {
+        self.counters.clone()
+    }
+
+    /// Never blocks on the worker, applies the backpressure policy when full. Returns false when the message was
+    /// dropped because every pending message is acked.
+    pub fn push(&self, msg: VisualizationAgentMsg, now_ms: u64) -> bool {
+        self.push_inner(msg, false, now_ms)
+    }
+
+    /// Push a report the agent will be acked for, it is never dropped once accepted. Returns false when the queue is
+    /// full of acked reports, the caller must not ack it then so the agent retransmits.
+    pub fn push_acked(&self, msg: VisualizationAgentMsg, now_ms: u64) -> bool {
+        self.push_inner(msg, true, now_ms)
+    }
+
+    fn push_inner(&self, msg: VisualizationAgentMsg, acked: bool, now_ms: u64) -> bool {
+        let mut state = self.state.lock();
+        state.received_at = state.received_at.max(now_ms);
+        let mut msg = msg;
+        if state.msgs.len() >= self.capacity {
+            if self.backpressure == IngestBackpressure::CoalescePerNode {
+                for (pending, pending_acked) in state.msgs.iter_mut().rev() {
+                    if keeps_order(pending, &msg) {
+                        break;
+                    }
+                    match merge_agent_msg(pending, msg) {
+                        Ok(()) => {
+                            *pending_acked |= acked;
+                            self.counters.coalesced.fetch_add(1, Ordering::Relaxed);
+                            return true;
+                        }
+                        Err(next) => msg = next,
+                    }
+                }
+            }
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            match state.msgs.iter().position(|(_, acked)| !acked) {
+                Some(oldest) => {
+                    state.msgs.remove(oldest);
+                }
+                None => return false,
+            }
+        }
+        state.msgs.push_back((msg, acked));
+        self.counters.depth.store(state.msgs.len(), Ordering::Relaxed);
+        drop(state);
+        self.condvar.notify_one();
+        true
+    }
+
+    pub fn push_tick(&self, now_ms: u64) {
+        self.state.lock().tick = Some(now_ms);
+        self.condvar.notify_one();
+    }
+
+    pub fn close(&self) {
+        self.state.lock().closed = true;
+        self.condvar.notify_all();
+    }
+
+    /// Wait for pending messages and tick, returns None once closed and drained
+    pub fn pop_batch(&self) -> Option<IngestBatch> {
+        let mut state = self.state.lock();
+        while state.msgs.is_empty() && state.tick.is_none() {
+            if state.closed {
+                return None;
+            }
+            self.condvar.wait(&mut state);
+        }
+        let msgs: Vec<_> = state.msgs.drain(..).map(|(msg, _)| msg).collect();
+        self.counters.depth.store(0, Ordering::Relaxed);
+        #[cfg(any(test, feature = "testing"))]
+        {
+            state.busy = true;
+        }
+        Some(IngestBatch {
+            msgs,
+            received_at: state.received_at,
+            tick: state.tick.take(),
+        })
+    }
+
+    /// Called by the worker once the popped batch is applied, only `wait_idle` needs it
+    #[cfg(any(test, feature = "testing"))]
+    pub fn batch_done(&self) {
+        self.state.lock().busy = false;
+        self.idle.notify_all();
+    }
+
+    /// Block until everything pushed so far is applied, lets the simulation harness read a settled store
+    #[cfg(any(test, feature = "testing"))]
+    pub fn wait_idle(&self) {
+        let mut state = self.state.lock();
+        while state.busy || !state.msgs.is_empty() || state.tick.is_some() {
+            self.idle.wait(&mut state);
+        }
+    }
+}
+
+/// Dedicated thread applying queued agent messages to the store, stopped and joined on drop.
+pub(crate) struct IngestWorker {
+    queue: Arc<IngestQueue>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl IngestWorker {
+    pub fn spawn(queue: Arc<IngestQueue>, mut logic: VisualizationMasterLogic) -> Self {
+        let worker_queue = queue.clone();
+        let handle = thread::Builder::new()
+            .name("visualization-ingest".to_string())
+            .spawn(move || {
+                let counters = worker_queue.counters();
+                while let Some(batch) = worker_queue.pop_batch() {
+                    let count = batch.msgs.len() as u64;
+                    let (msgs, merged) = coalesce_agent_msgs(batch.msgs);
+                    counters.coalesced.fetch_add(merged as u64, Ordering::Relaxed);
+                    if !msgs.is_empty() {
+                        logic.process_agent_msgs(msgs, batch.received_at);
+                    }
+                    counters.processed.fetch_add(count, Ordering::Relaxed);
+                    if let Some(now_ms) = batch.tick {
+                        logic.on_tick(now_ms);
+                    }
+                    #[cfg(any(test, feature = "testing"))]
+                    worker_queue.batch_done();
+                }
+            })
+            .expect("should spawn ingest worker");
+        Self { queue, handle: Some(handle) }
+    }
+
+    pub fn queue(&self) -> &Arc<IngestQueue> {
+        &self.queue
+    }
+}
+
+impl Drop for IngestWorker {
+    fn drop(&mut self) {
+        self.queue.close();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                log::error!("[VisualizationMaster] ingest worker panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        collector::{SdnMonitorController, TopologyEvent},
+        CompactConnectionMsg, ConnectionMetric, ConnectionMsg, ConnectionStatus,
+    };
+
+    use super::*;
+
+    fn ping(node_id: u32, ts: u64) -> VisualizationAgentMsg {
+        VisualizationAgentMsg::NodePing(node_id, String::from("127.0.0.1"), ts)
+    }
+
+    fn conn(conn_id: u64, latency: u16, ts: u64) -> ConnectionMsg {
+        ConnectionMsg {
+            conn_id,
+            protocol: 1,
+            addr: String::from("127.0.0.1"),
+            node_id: 100,
+            direction: 0,
+            status: ConnectionStatus::CONNECTED,
+            metric: ConnectionMetric {
+                latency,
+                bandwidth: 100,
+                loss_percent: 0,
+            },
+            latest_updated_at: ts,
+        }
+    }
+
+    #[test]
+    fn should_drop_oldest_when_full() {
+        let queue = IngestQueue::new(2, IngestBackpressure::DropOldest);
+        queue.push(ping(1, 0), 0);
+        queue.push(ping(2, 0), 0);
+        queue.push(ping(3, 0), 0);
+
+        let stats = queue.counters().snapshot();
+        assert_eq!(stats.depth, 2);
+        assert_eq!(stats.dropped, 1);
+        assert_eq!(queue.pop_batch().map(|batch| batch.msgs), Some(vec![ping(2, 0), ping(3, 0)]));
+    }
+
+    #[test]
+    fn should_never_drop_acked_messages() {
+        let queue = IngestQueue::new(2, IngestBackpressure::DropOldest);
+        assert!(queue.push_acked(ping(1, 0), 0));
+        assert!(queue.push(ping(2, 0), 0));
+        assert!(queue.push_acked(ping(3, 0), 0));
+        // full of acked messages, the new one is refused whether acked or not
+        assert!(!queue.push_acked(ping(4, 0), 0));
+        assert!(!queue.push(ping(5, 0), 0));
+
+        assert_eq!(queue.counters().snapshot().dropped, 3);
+        assert_eq!(queue.pop_batch().map(|batch| batch.msgs), Some(vec![ping(1, 0), ping(3, 0)]));
+    }
+
+    #[test]
+    fn should_coalesce_same_node_when_full() {
+        let queue = IngestQueue::new(2, IngestBackpressure::CoalescePerNode);
+        queue.push(VisualizationAgentMsg::NodeConnections(1, vec![conn(1, 10, 0)]), 0);
+        queue.push(ping(2, 0), 0);
+        queue.push(VisualizationAgentMsg::NodeConnections(1, vec![conn(1, 20, 1), conn(2, 5, 1)]), 0);
+        queue.push(ping(3, 0), 0);
+
+        let stats = queue.counters().snapshot();
+        assert_eq!(stats.coalesced, 1);
+        assert_eq!(stats.dropped, 1);
+        assert_eq!(queue.pop_batch().map(|batch| batch.msgs), Some(vec![ping(2, 0), ping(3, 0)]));
+    }
+
+    #[test]
+    fn merge_should_keep_latest_metric_per_connection() {
+        let mut pending = VisualizationAgentMsg::NodeConnections(1, vec![conn(1, 10, 2)]);
+        assert_eq!(merge_agent_msg(&mut pending, VisualizationAgentMsg::NodeConnections(1, vec![conn(1, 20, 1), conn(2, 5, 1)])), Ok(()));
+        assert_eq!(pending, VisualizationAgentMsg::NodeConnections(1, vec![conn(1, 10, 2), conn(2, 5, 1)]));
+
+        assert_eq!(merge_agent_msg(&mut pending, ping(1, 0)), Err(ping(1, 0)));
+        assert_eq!(
+            merge_agent_msg(&mut pending, VisualizationAgentMsg::NodeConnections(2, vec![])),
+            Err(VisualizationAgentMsg::NodeConnections(2, vec![]))
+        );
+    }
+
+    #[test]
+    fn coalesce_should_merge_per_node_and_kind_keeping_first_position() {
+        let msgs = vec![
+            ping(1, 0),
+            VisualizationAgentMsg::NodeConnections(1, vec![conn(1, 10, 0)]),
+            ping(2, 0),
+            VisualizationAgentMsg::NodeConnections(1, vec![conn(1, 20, 1), conn(2, 5, 1)]),
+            ping(1, 1),
+        ];
+
+        let (msgs, merged) = coalesce_agent_msgs(msgs);
+        assert_eq!(merged, 2);
+        assert_eq!(msgs, vec![ping(1, 1), VisualizationAgentMsg::NodeConnections(1, vec![conn(1, 20, 1), conn(2, 5, 1)]), ping(2, 0)]);
+    }
+
+    #[test]
+    fn coalesce_should_keep_unmergeable_messages_of_same_kind() {
+        let reliable = |seq, ts| VisualizationAgentMsg::Reliable(seq, Box::new(ping(1, ts)));
+        let compact = |latency| {
+            let conn = conn(1, latency, latency as u64);
+            let compact = CompactConnectionMsg {
+                conn_id: conn.conn_id,
+                protocol: conn.protocol,
+                addr: 0,
+                node_id: conn.node_id,
+                direction: conn.direction,
+                status: conn.status,
+                metric: conn.metric,
+                latest_updated_at: conn.latest_updated_at,
+            };
+            VisualizationAgentMsg::NodeConnectionsCompact(1, vec![conn.addr], vec![compact])
+        };
+        let msgs = vec![reliable(1, 0), reliable(2, 1), compact(10), compact(20)];
+
+        let (coalesced, merged) = coalesce_agent_msgs(msgs.clone());
+        assert_eq!(merged, 0);
+        assert_eq!(coalesced, msgs);
+    }
+
+    #[test]
+    fn coalesce_should_not_merge_connections_across_a_removal() {
+        let msgs = vec![
+            VisualizationAgentMsg::NodeConnections(1, vec![conn(7, 10, 1)]),
+            VisualizationAgentMsg::NodeConnectionsRemoved(1, vec![7]),
+            VisualizationAgentMsg::NodeConnections(1, vec![conn(7, 20, 5)]),
+            VisualizationAgentMsg::NodeConnectionsRemoved(1, vec![8]),
+        ];
+
+        let (coalesced, merged) = coalesce_agent_msgs(msgs.clone());
+        assert_eq!(merged, 0);
+        assert_eq!(coalesced, msgs);
+    }
+
+    #[test]
+    fn reconnect_after_removal_should_keep_the_link() {
+        let controller = SdnMonitorController::new();
+        let mut subscription = controller.subscribe();
+        let mut logic = VisualizationMasterLogic::new(controller.clone());
+
+        let (msgs, _) = coalesce_agent_msgs(vec![
+            ping(1, 0),
+            VisualizationAgentMsg::NodeConnections(1, vec![conn(7, 10, 1)]),
+            VisualizationAgentMsg::NodeConnectionsRemoved(1, vec![7]),
+            VisualizationAgentMsg::NodeConnections(1, vec![conn(7, 20, 5)]),
+        ]);
+        logic.process_agent_msgs(msgs, 5);
+
+        let mut last_link_event = None;
+        while let Some(event) = subscription.try_recv() {
+            if matches!(event, TopologyEvent::LinkUp { .. } | TopologyEvent::LinkDown { .. } | TopologyEvent::LinkRemoved { .. }) {
+                last_link_event = Some(event);
+            }
+        }
+        assert!(matches!(last_link_event, Some(TopologyEvent::LinkUp { conn_id: 7, .. })), "{:?}", last_link_event);
+    }
+
+    #[test]
+    fn should_not_coalesce_connections_across_a_queued_removal_when_full() {
+        let queue = IngestQueue::new(2, IngestBackpressure::CoalescePerNode);
+        queue.push(VisualizationAgentMsg::NodeConnections(1, vec![conn(7, 10, 1)]), 0);
+        queue.push(VisualizationAgentMsg::NodeConnectionsRemoved(1, vec![7]), 0);
+        queue.push(VisualizationAgentMsg::NodeConnections(1, vec![conn(7, 20, 5)]), 0);
+
+        let stats = queue.counters().snapshot();
+        assert_eq!(stats.coalesced, 0);
+        assert_eq!(stats.dropped, 1);
+        assert_eq!(
+            queue.pop_batch().map(|batch| batch.msgs),
+            Some(vec![
+                VisualizationAgentMsg::NodeConnectionsRemoved(1, vec![7]),
+                VisualizationAgentMsg::NodeConnections(1, vec![conn(7, 20, 5)])
+            ])
+        );
+    }
+
+    #[test]
+    fn coalesced_batch_should_publish_latest_metric_once() {
+        let controller = SdnMonitorController::new();
+        let mut subscription = controller.subscribe();
+        let mut logic = VisualizationMasterLogic::new(controller.clone());
+
+        let (msgs, _) = coalesce_agent_msgs(vec![
+            ping(1, 0),
+            VisualizationAgentMsg::NodeConnections(1, vec![conn(1, 10, 0)]),
+            VisualizationAgentMsg::NodeConnections(1, vec![conn(1, 20, 1)]),
+        ]);
+        logic.process_agent_msgs(msgs, 0);
+
+        let mut metrics = vec![];
+        while let Some(event) = subscription.try_recv() {
+            if let TopologyEvent::MetricUpdated { metric, .. } = event {
+                metrics.push(metric.latency);
+            }
+        }
+        assert_eq!(metrics, vec![20]);
+    }
+
+    #[test]
+    fn worker_should_apply_messages_and_ticks() {
+        let controller = SdnMonitorController::new();
+        let queue = Arc::new(IngestQueue::new(16, IngestBackpressure::DropOldest));
+        let counters = queue.counters();
+        let worker = IngestWorker::spawn(queue, VisualizationMasterLogic::new(controller.clone()));
+
+        worker.queue().push(ping(1, 0), 0);
+        worker.queue().push_tick(1);
+        drop(worker);
+
+        assert!(controller.get_node(1).is_some());
+        assert_eq!(counters.snapshot().processed, 1);
+    }
+
+    #[test]
+    fn wait_idle_should_return_once_pushed_messages_are_applied() {
+        let controller = SdnMonitorController::new();
+        let queue = Arc::new(IngestQueue::new(16, IngestBackpressure::DropOldest));
+        let worker = IngestWorker::spawn(queue, VisualizationMasterLogic::new(controller.clone()));
+
+        for node_id in 1..=10 {
+            worker.queue().push(ping(node_id, 0), 0);
+            worker.queue().wait_idle();
+            assert!(controller.get_node(node_id).is_some());
+        }
+        worker.queue().wait_idle();
+    }
+}
@@ -1,5 +1,8 @@
+use atm0s_sdn_identity::NodeId;
+
 use crate::{
-    collector::{NodeConnectionData, NodeData, SdnMonitorController},
+    collector::{NodeConnectionData, NodeConnectionStorage, NodeData, SdnMonitorController, TopologyEvent},
+    services::codec::expand_agent_msg,
     VisualizationAgentMsg,
 };
 
@@ -18,10 +21,31 @@ impl VisualizationMasterLogic {
         Self { controller: controller.clone() }
     }
 
-    pub fn process_agent_msg(&mut self, msg: VisualizationAgentMsg) {
+    pub fn process_agent_msg(&mut self, msg: VisualizationAgentMsg, now_ms: u64) {
+        self.process_agent_msgs(vec![msg], now_ms);
+    }
+
+    /// Apply a batch of agent messages with a single store update, the batch should already be coalesced.
+    /// `now_ms` is when the batch was received, it stamps the emitted metrics.
+    pub fn process_agent_msgs(&mut self, msgs: Vec<VisualizationAgentMsg>, now_ms: u64) {
+        let mut node_ids: Vec<NodeId> = msgs.iter().map(|msg| msg.node_id()).collect();
+        node_ids.sort_unstable();
+        node_ids.dedup();
+        self.controller.update_batch(now_ms, |storage, events| {
+            for msg in msgs {
+                Self::apply_agent_msg(storage, events, msg);
+            }
+        });
+        self.controller.emit_ingest_metrics(&node_ids, now_ms);
+    }
+
+    fn apply_agent_msg(storage: &mut NodeConnectionStorage, events: &mut Vec<TopologyEvent>, msg: VisualizationAgentMsg) {
         match msg {
             VisualizationAgentMsg::NodePing(node_id, addr, now_ms) => {
-                self.controller.upsert_node(node_id, addr, now_ms);
+                events.append(&mut storage.upsert_node(node_id, addr, now_ms));
+            }
+            VisualizationAgentMsg::NodeHostStats(node_id, ts, stats) => {
+                storage.add_host_stats(node_id, ts, stats);
             }
             VisualizationAgentMsg::NodeConnections(addr, conns) => {
                 let data: Vec<NodeConnectionData> = conns
@@ -37,12 +61,126 @@ impl VisualizationMasterLogic {
                         last_updated_at: conn.latest_updated_at,
                     })
                     .collect();
-                self.controller.update_node_conns(addr, data);
+                events.append(&mut storage.update_node_connection(addr, data));
+            }
+            VisualizationAgentMsg::NodeLabels(node_id, labels) => {
+                storage.update_node_labels(node_id, labels);
             }
+            VisualizationAgentMsg::NodeAppMetrics(node_id, ts, metrics) => {
+                storage.update_node_app_metrics(node_id, ts, metrics);
+            }
+            VisualizationAgentMsg::NodeConnectionsRemoved(node_id, ids) => {
+                events.append(&mut storage.remove_node_connections(node_id, &ids));
+            }
+            VisualizationAgentMsg::NodeDroppedReports(node_id, count) => {
+                storage.update_node_dropped_reports(node_id, count);
+            }
+            VisualizationAgentMsg::Reliable(_, msg) => {
+                Self::apply_agent_msg(storage, events, *msg);
+            }
+            msg @ VisualizationAgentMsg::NodeConnectionsCompact(..) => match expand_agent_msg(msg) {
+                Some(msg) => Self::apply_agent_msg(storage, events, msg),
+                None => log::warn!("[VisualizationMaster] drop compact report with an address outside of its dictionary"),
+            },
         }
     }
 
+    pub fn on_tick(&mut self, now_ms: u64) {
+        self.controller.check_offline_nodes(now_ms);
+        self.controller.flush_metrics_sinks(now_ms);
+    }
+
     pub fn get_nodes(&self) -> Vec<NodeData> {
         self.controller.get_nodes()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use atm0s_sdn_identity::{ConnId, NodeAddrBuilder};
+
+    use crate::{services::agent::VisualizationAgentLogic, ConnectionMetric, ConnectionStatus, InfluxUdpSink, SinkTrigger, StatsdSink, StatsdTagStyle, VisualizationAgentBehaviourConf};
+
+    use super::*;
+
+    fn deliver(agent: &mut VisualizationAgentLogic, master: &mut VisualizationMasterLogic, now_ms: u64) {
+        let mut msgs = vec![];
+        while let Some(msg) = agent.pop_msg() {
+            msgs.push(msg);
+        }
+        master.process_agent_msgs(msgs, now_ms);
+    }
+
+    #[test]
+    fn connection_lifecycle_should_end_with_removal_on_master() {
+        let controller = SdnMonitorController::new();
+        let mut master = VisualizationMasterLogic::new(controller.clone());
+        let addr = NodeAddrBuilder::new(1).addr();
+        let mut agent = VisualizationAgentLogic::new(VisualizationAgentBehaviourConf {
+            conn_gc_timeout_ms: 1000,
+            ..VisualizationAgentBehaviourConf::new(1, addr.clone())
+        });
+        let conn_id = ConnId::from_out(1, 1);
+        let status = |controller: &SdnMonitorController| controller.get_node(1).and_then(|node| node.conns.iter().next().map(|conn| conn.status.clone()));
+
+        agent.on_node_connected(conn_id, 2, addr, 0);
+        agent.on_connection_stats(
+            conn_id,
+            2,
+            ConnectionMetric {
+                latency: 10,
+                bandwidth: 100,
+                loss_percent: 0,
+            },
+            0,
+        );
+        agent.report_stats(0);
+        deliver(&mut agent, &mut master, 0);
+        assert_eq!(status(&controller), Some(ConnectionStatus::CONNECTED));
+
+        agent.on_node_disconnected(conn_id, 2, 500);
+        agent.report_stats(500);
+        deliver(&mut agent, &mut master, 500);
+        assert_eq!(status(&controller), Some(ConnectionStatus::DISCONNECTED));
+
+        let mut subscription = controller.subscribe();
+        agent.report_stats(1500);
+        deliver(&mut agent, &mut master, 1500);
+        assert_eq!(status(&controller), None);
+        assert_eq!(controller.get_node(1).map(|node| node.conns.len()), Some(0));
+        assert!(std::iter::from_fn(|| subscription.try_recv()).any(|event| matches!(event, TopologyEvent::LinkRemoved { node_id: 1, dest: 2, .. })));
+    }
+
+    #[test]
+    fn ingest_and_interval_sinks_should_emit_to_udp_listeners() {
+        let controller = SdnMonitorController::new();
+        let mut master = VisualizationMasterLogic::new(controller.clone());
+        let listen = || {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0").expect("should bind");
+            socket.set_read_timeout(Some(std::time::Duration::from_secs(5))).expect("should set timeout");
+            socket
+        };
+        let (ingest, interval) = (listen(), listen());
+        controller.add_metrics_sink(InfluxUdpSink::new(ingest.local_addr().expect("should have addr")).expect("should create sink"), SinkTrigger::OnIngest);
+        controller.add_metrics_sink(
+            StatsdSink::new(interval.local_addr().expect("should have addr"), StatsdTagStyle::Datadog).expect("should create sink"),
+            SinkTrigger::Interval(1000),
+        );
+        let recv = |socket: &std::net::UdpSocket| {
+            let mut buf = [0; 2048];
+            let len = socket.recv(&mut buf).expect("should receive");
+            String::from_utf8_lossy(&buf[..len]).to_string()
+        };
+
+        let mut agent = VisualizationAgentLogic::new(VisualizationAgentBehaviourConf::new(7, NodeAddrBuilder::new(7).addr()));
+        agent.report_stats(10_000);
+        deliver(&mut agent, &mut master, 10_000);
+        assert!(recv(&ingest).starts_with("sdn_node,node_id=7 online=1i,connections=0i,dropped_reports=0i "));
+
+        master.on_tick(10_000);
+        assert_eq!(
+            recv(&interval),
+            "sdn_node.online:1|g|#node_id:7\nsdn_node.connections:0|g|#node_id:7\nsdn_node.dropped_reports:0|g|#node_id:7\n"
+        );
+    }
+}
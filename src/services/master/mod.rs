@@ -1,11 +1,23 @@
+mod aggregator;
 mod behaviour;
 mod handler;
+mod ingest;
 mod logic;
 mod msg;
+mod replication;
 mod sdk;
 
 pub static VISUALIZATION_MASTER_SERVICE: u8 = 8;
 
-pub use behaviour::VisualizationMasterBehaviour;
-pub use msg::{VisualizationMasterBehaviourEvent, VisualizationMasterHandlerEvent};
+pub use aggregator::{AggregatorConf, RegionScope};
+pub use behaviour::{VisualizationMasterBehaviour, VisualizationMasterBehaviourConf};
+pub use ingest::{IngestBackpressure, IngestStats, DEFAULT_INGEST_CAPACITY};
+pub use msg::{MasterRole, MasterStatus, VisualizationMasterBehaviourEvent, VisualizationMasterHandlerEvent, VisualizationMasterMsg};
+pub use replication::{MasterReplicationConf, MasterReplicationMsg};
 pub use sdk::VisualizationMasterSdk;
+
+#[cfg(feature = "testing")]
+pub(crate) use ingest::IngestQueue;
+/// Ingest logic without the worker thread, public with the `testing` feature for load tests
+#[cfg(any(test, feature = "testing"))]
+pub use logic::VisualizationMasterLogic;
@@ -1,12 +1,57 @@
 use atm0s_sdn_identity::NodeId;
 use serde::{Deserialize, Serialize};
 
-use crate::VisualizationAgentMsg;
+use crate::{MasterReplicationMsg, VisualizationAgentMsg};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum VisualizationMasterBehaviourEvent {
     OnMsg(VisualizationAgentMsg),
+    OnReplication(MasterReplicationMsg),
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum VisualizationMasterHandlerEvent {}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MasterRole {
+    /// Handles commands and fires alerts
+    Leader,
+    /// Keeps a replicated copy of the topology, ready to take over
+    Standby,
+}
+
+/// A master view of the live masters, the one with the lowest node id leads
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct MasterStatus {
+    pub node_id: NodeId,
+    pub leader: NodeId,
+    /// Live masters including this one, in id order
+    pub masters: Vec<NodeId>,
+}
+
+impl MasterStatus {
+    pub fn elect(node_id: NodeId, peers: &[NodeId]) -> Self {
+        let mut masters = peers.to_vec();
+        masters.push(node_id);
+        masters.sort();
+        masters.dedup();
+        Self { node_id, leader: masters[0], masters }
+    }
+
+    pub fn role(&self) -> MasterRole {
+        if self.leader == self.node_id {
+            MasterRole::Leader
+        } else {
+            MasterRole::Standby
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum VisualizationMasterMsg {
+    // sequence number of a received `VisualizationAgentMsg::Reliable` report
+    ReportAck(u64),
+    // answer to each agent ping, lets agents detect a silent master and fail over
+    Heartbeat(MasterStatus),
+}
@@ -0,0 +1,436 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use atm0s_sdn_identity::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::collector::{NodeData, RegionDetail, RegionSummary, SdnMonitorController};
+
+use super::msg::MasterStatus;
+
+/// Settings of master to master replication, which covers node addresses, labels and connections but not the time series
+#[derive(Debug, Clone)]
+pub struct MasterReplicationConf {
+    /// Masters known upfront, others are found by probing nodes of the topology
+    pub peers: Vec<NodeId>,
+    pub sync_interval_ms: u64,
+    /// Topology nodes probed for a master service on each sync
+    pub probe_batch: usize,
+    /// A peer not heard from for this long is forgotten
+    pub peer_timeout_ms: u64,
+    /// Byte budget of a single replication message before compression
+    pub max_msg_bytes: usize,
+}
+
+impl Default for MasterReplicationConf {
+    fn default() -> Self {
+        Self {
+            peers: vec![],
+            sync_interval_ms: 5000,
+            probe_batch: 16,
+            peer_timeout_ms: 30000,
+            max_msg_bytes: 1200,
+        }
+    }
+}
+
+/// Messages exchanged between masters, for replication and for regional aggregation
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum MasterReplicationMsg {
+    /// Announce a master, sent to seeds and probed nodes
+    Hello(NodeId),
+    /// Versions of the sender's nodes with id in `range`, inclusive, along with the masters it knows
+    Digest {
+        from: NodeId,
+        masters: Vec<NodeId>,
+        range: (NodeId, NodeId),
+        versions: Vec<(NodeId, u64)>,
+    },
+    /// Ask for the state of nodes the sender has an older copy of
+    Pull {
+        from: NodeId,
+        nodes: Vec<NodeId>,
+    },
+    Nodes {
+        from: NodeId,
+        nodes: Vec<NodeData>,
+    },
+    /// Sent by an aggregator to its upstream master, see `AggregatorConf`
+    RegionSummary(RegionSummary),
+    RegionDetailRequest {
+        from: NodeId,
+        region: String,
+    },
+    RegionDetail {
+        from: NodeId,
+        detail: RegionDetail,
+    },
+}
+
+impl MasterReplicationMsg {
+    pub fn from(&self) -> NodeId {
+        match self {
+            MasterReplicationMsg::Hello(from) => *from,
+            MasterReplicationMsg::Digest { from, .. } => *from,
+            MasterReplicationMsg::Pull { from, .. } => *from,
+            MasterReplicationMsg::Nodes { from, .. } => *from,
+            MasterReplicationMsg::RegionSummary(summary) => summary.aggregator,
+            MasterReplicationMsg::RegionDetailRequest { from, .. } => *from,
+            MasterReplicationMsg::RegionDetail { from, .. } => *from,
+        }
+    }
+}
+
+/// Anti entropy between masters: each sync sends the versions of all nodes to every peer, which answers with
+/// the nodes it holds newer copies of and pulls the ones it is behind on. Copies are merged last writer wins.
+/// Only `NodeData` is replicated: host stats, app metrics and the connection samples behind `/api/export/connections`
+/// and the Grafana `/query` stay on the master which received the report, and are lost with it.
+pub struct MasterReplication {
+    node_id: NodeId,
+    conf: MasterReplicationConf,
+    controller: SdnMonitorController,
+    /// peer master id to last time it was heard from
+    peers: BTreeMap<NodeId, u64>,
+    probe_cursor: NodeId,
+    next_sync_at: u64,
+    outgoing: VecDeque<(NodeId, MasterReplicationMsg)>,
+}
+
+impl MasterReplication {
+    pub fn new(node_id: NodeId, conf: MasterReplicationConf, controller: SdnMonitorController) -> Self {
+        Self {
+            node_id,
+            conf,
+            controller,
+            peers: BTreeMap::new(),
+            probe_cursor: 0,
+            next_sync_at: 0,
+            outgoing: VecDeque::new(),
+        }
+    }
+
+    pub fn peers(&self) -> Vec<NodeId> {
+        self.peers.keys().cloned().collect()
+    }
+
+    /// Leader election among this master and its live peers
+    pub fn status(&self) -> MasterStatus {
+        MasterStatus::elect(self.node_id, &self.peers())
+    }
+
+    fn touch_peer(&mut self, peer: NodeId, now_ms: u64) {
+        if peer != self.node_id {
+            self.peers.insert(peer, now_ms);
+        }
+    }
+
+    fn versions(&self) -> BTreeMap<NodeId, u64> {
+        self.controller.get_nodes_shared().iter().map(|node| (node.id, node.version())).collect()
+    }
+
+    pub fn on_tick(&mut self, now_ms: u64) {
+        if now_ms < self.next_sync_at {
+            return;
+        }
+        self.next_sync_at = now_ms + self.conf.sync_interval_ms;
+        let timeout = self.conf.peer_timeout_ms;
+        self.peers.retain(|_, last_seen| now_ms.saturating_sub(*last_seen) <= timeout);
+
+        let versions = self.versions();
+        self.probe(&versions);
+        self.send_digests(&versions);
+    }
+
+    /// Say hello to the seeds and to the next `probe_batch` topology nodes not known as masters, round robin
+    fn probe(&mut self, versions: &BTreeMap<NodeId, u64>) {
+        let seeds: Vec<NodeId> = self.conf.peers.iter().filter(|peer| **peer != self.node_id && !self.peers.contains_key(peer)).cloned().collect();
+        for seed in seeds {
+            self.outgoing.push_back((seed, MasterReplicationMsg::Hello(self.node_id)));
+        }
+
+        let is_candidate = |id: &&NodeId| **id != self.node_id && !self.peers.contains_key(id) && !self.conf.peers.contains(id);
+        let mut targets: Vec<NodeId> = versions
+            .keys()
+            .filter(|id| **id > self.probe_cursor)
+            .filter(is_candidate)
+            .take(self.conf.probe_batch)
+            .cloned()
+            .collect();
+        if targets.len() < self.conf.probe_batch {
+            let wrapped = versions.keys().filter(|id| **id <= self.probe_cursor).filter(is_candidate).take(self.conf.probe_batch - targets.len());
+            targets.extend(wrapped.cloned().collect::<Vec<_>>());
+        }
+        if let Some(last) = targets.last() {
+            self.probe_cursor = *last;
+        }
+        for target in targets {
+            self.outgoing.push_back((target, MasterReplicationMsg::Hello(self.node_id)));
+        }
+    }
+
+    fn send_digests(&mut self, versions: &BTreeMap<NodeId, u64>) {
+        if self.peers.is_empty() {
+            return;
+        }
+        // a (NodeId, u64) entry takes 12 bytes, keep some room for the header and the master list
+        let per_msg = (self.conf.max_msg_bytes.saturating_sub(64 + 4 * self.peers.len()) / 12).max(1);
+        let entries: Vec<(NodeId, u64)> = versions.iter().map(|(id, version)| (*id, *version)).collect();
+        let mut masters = self.peers();
+        masters.push(self.node_id);
+
+        let mut digests = vec![];
+        let mut start = 0;
+        let chunks: Vec<&[(NodeId, u64)]> = if entries.is_empty() {
+            vec![&[]]
+        } else {
+            entries.chunks(per_msg).collect()
+        };
+        let last = chunks.len() - 1;
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            let end = match chunk.last() {
+                Some((id, _)) if index < last => *id,
+                _ => NodeId::MAX,
+            };
+            digests.push(MasterReplicationMsg::Digest {
+                from: self.node_id,
+                masters: masters.clone(),
+                range: (start, end),
+                versions: chunk.to_vec(),
+            });
+            start = end.saturating_add(1);
+        }
+
+        for peer in self.peers() {
+            for digest in digests.iter() {
+                self.outgoing.push_back((peer, digest.clone()));
+            }
+        }
+    }
+
+    pub fn on_msg(&mut self, msg: MasterReplicationMsg, now_ms: u64) {
+        let from = msg.from();
+        match msg {
+            // aggregators are not replication peers, their messages are handled by the behaviour
+            MasterReplicationMsg::RegionSummary(_) | MasterReplicationMsg::RegionDetailRequest { .. } | MasterReplicationMsg::RegionDetail { .. } => {}
+            MasterReplicationMsg::Hello(_) => self.touch_peer(from, now_ms),
+            MasterReplicationMsg::Digest { masters, range, versions, .. } => {
+                self.touch_peer(from, now_ms);
+                // gossiped masters become peers only once they speak, so a dead one is not kept alive by others
+                for master in masters {
+                    if master != self.node_id && !self.peers.contains_key(&master) {
+                        self.outgoing.push_back((master, MasterReplicationMsg::Hello(self.node_id)));
+                    }
+                }
+                let remote: HashMap<NodeId, u64> = versions.into_iter().collect();
+                let local = self.versions();
+                let mut push = vec![];
+                let mut pull = vec![];
+                for (id, version) in local.range(range.0..=range.1) {
+                    match remote.get(id) {
+                        Some(remote) if remote > version => pull.push(*id),
+                        Some(remote) if remote == version => {}
+                        _ => push.push(*id),
+                    }
+                }
+                pull.extend(remote.keys().filter(|id| !local.contains_key(id)).cloned());
+                if !pull.is_empty() {
+                    pull.sort();
+                    self.outgoing.push_back((from, MasterReplicationMsg::Pull { from: self.node_id, nodes: pull }));
+                }
+                self.send_nodes(from, &push);
+            }
+            MasterReplicationMsg::Pull { nodes, .. } => {
+                self.touch_peer(from, now_ms);
+                self.send_nodes(from, &nodes);
+            }
+            MasterReplicationMsg::Nodes { nodes, .. } => {
+                self.touch_peer(from, now_ms);
+                self.controller.update_batch(now_ms, |storage, events| {
+                    for node in nodes {
+                        events.append(&mut storage.merge_replicated_node(node, now_ms));
+                    }
+                });
+            }
+        }
+    }
+
+    /// Send the given nodes in batches fitting the byte budget, a node larger than the budget goes alone
+    fn send_nodes(&mut self, to: NodeId, ids: &[NodeId]) {
+        let mut batch = vec![];
+        let mut batch_bytes = 0;
+        for id in ids {
+            let node = match self.controller.get_node_shared(*id) {
+                Some(node) => node,
+                None => continue,
+            };
+            let size = bincode::serialized_size(node.as_ref()).unwrap_or(0) as usize;
+            if !batch.is_empty() && batch_bytes + size > self.conf.max_msg_bytes {
+                let nodes = std::mem::take(&mut batch);
+                self.outgoing.push_back((to, MasterReplicationMsg::Nodes { from: self.node_id, nodes }));
+                batch_bytes = 0;
+            }
+            batch.push(node.as_ref().clone());
+            batch_bytes += size;
+        }
+        if !batch.is_empty() {
+            self.outgoing.push_back((to, MasterReplicationMsg::Nodes { from: self.node_id, nodes: batch }));
+        }
+    }
+
+    pub fn pop_msg(&mut self) -> Option<(NodeId, MasterReplicationMsg)> {
+        self.outgoing.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        collector::{NodeConnectionData, NodeConnections},
+        ConnectionMetric, ConnectionStatus,
+    };
+
+    use super::*;
+
+    fn node(id: NodeId, ts: u64, dests: &[NodeId]) -> NodeData {
+        let mut node = NodeData::new(id, format!("/ip4/127.0.0.{}", id), ts);
+        node.conns = dests
+            .iter()
+            .map(|dest| NodeConnectionData {
+                id: *dest as u64,
+                node_id: *dest,
+                protocol: 1,
+                addr: format!("/ip4/127.0.0.{}", dest),
+                metric: ConnectionMetric {
+                    latency: 10,
+                    loss_percent: 0,
+                    bandwidth: 100,
+                },
+                status: ConnectionStatus::CONNECTED,
+                last_updated_at: ts,
+                direction: 0,
+            })
+            .collect::<NodeConnections>();
+        node
+    }
+
+    fn add(controller: &mut SdnMonitorController, node: NodeData) {
+        controller.update_batch(0, |storage, events| events.append(&mut storage.merge_replicated_node(node, 0)));
+    }
+
+    /// Deliver queued messages between the masters until none are left, messages to other nodes are dropped
+    fn run(masters: &mut [&mut MasterReplication], now_ms: u64) -> usize {
+        let mut delivered = 0;
+        loop {
+            let mut msgs = vec![];
+            for master in masters.iter_mut() {
+                while let Some(msg) = master.pop_msg() {
+                    msgs.push(msg);
+                }
+            }
+            if msgs.is_empty() {
+                return delivered;
+            }
+            for (to, msg) in msgs {
+                if let Some(master) = masters.iter_mut().find(|master| master.node_id == to) {
+                    master.on_msg(msg, now_ms);
+                    delivered += 1;
+                }
+            }
+        }
+    }
+
+    fn sorted(controller: &SdnMonitorController) -> Vec<NodeData> {
+        let mut nodes = controller.get_nodes();
+        nodes.sort_by_key(|node| node.id);
+        nodes
+    }
+
+    #[test]
+    fn masters_should_converge_to_union_of_partial_views() {
+        let (mut c1, mut c2) = (SdnMonitorController::new(), SdnMonitorController::new());
+        add(&mut c1, node(1, 100, &[2]));
+        add(&mut c1, node(3, 100, &[1]));
+        add(&mut c2, node(2, 100, &[1]));
+        add(&mut c2, node(4, 100, &[2]));
+        let conf = MasterReplicationConf {
+            peers: vec![2],
+            max_msg_bytes: 200,
+            ..Default::default()
+        };
+        let mut m1 = MasterReplication::new(1, conf.clone(), c1.clone());
+        let mut m2 = MasterReplication::new(2, MasterReplicationConf { peers: vec![], ..conf }, c2.clone());
+
+        m1.on_tick(0);
+        m2.on_tick(0);
+        run(&mut [&mut m1, &mut m2], 0);
+        assert_eq!(m2.peers(), vec![1]);
+
+        m1.on_tick(5000);
+        m2.on_tick(5000);
+        run(&mut [&mut m1, &mut m2], 5000);
+        assert_eq!(m1.peers(), vec![2]);
+        assert_eq!(sorted(&c1).len(), 4);
+        assert_eq!(sorted(&c1), sorted(&c2));
+
+        // once converged only digests are exchanged
+        m1.on_tick(10000);
+        m2.on_tick(10000);
+        assert_eq!(run(&mut [&mut m1, &mut m2], 10000), 2);
+    }
+
+    #[test]
+    fn newest_node_state_should_win() {
+        let (mut c1, mut c2) = (SdnMonitorController::new(), SdnMonitorController::new());
+        add(&mut c1, node(5, 100, &[6, 7]));
+        add(&mut c2, node(5, 200, &[6]));
+        let conf = MasterReplicationConf {
+            peers: vec![1, 2],
+            ..Default::default()
+        };
+        let mut m1 = MasterReplication::new(1, conf.clone(), c1.clone());
+        let mut m2 = MasterReplication::new(2, conf, c2.clone());
+
+        for now_ms in [0, 5000] {
+            m1.on_tick(now_ms);
+            m2.on_tick(now_ms);
+            run(&mut [&mut m1, &mut m2], now_ms);
+        }
+        assert_eq!(c1.get_node(5), Some(node(5, 200, &[6])));
+        assert_eq!(c2.get_node(5), Some(node(5, 200, &[6])));
+    }
+
+    #[test]
+    fn masters_should_discover_each_other_by_probing_topology() {
+        let (mut c1, mut c2, mut c3) = (SdnMonitorController::new(), SdnMonitorController::new(), SdnMonitorController::new());
+        // master 1 only sees the agent of node 3, which knows about master 2
+        add(&mut c1, node(3, 100, &[]));
+        add(&mut c2, node(4, 100, &[]));
+        add(&mut c3, node(9, 100, &[]));
+        add(&mut c1, node(2, 100, &[]));
+        let conf = MasterReplicationConf { probe_batch: 1, ..Default::default() };
+        let mut m1 = MasterReplication::new(1, conf.clone(), c1.clone());
+        let mut m2 = MasterReplication::new(2, conf.clone(), c2.clone());
+        let mut m3 = MasterReplication::new(3, MasterReplicationConf { peers: vec![2], ..conf }, c3.clone());
+
+        for round in 0..4 {
+            let now_ms = round * 5000;
+            m1.on_tick(now_ms);
+            m2.on_tick(now_ms);
+            m3.on_tick(now_ms);
+            run(&mut [&mut m1, &mut m2, &mut m3], now_ms);
+        }
+        assert_eq!(m1.peers(), vec![2, 3]);
+        assert_eq!(m2.peers(), vec![1, 3]);
+        assert_eq!(sorted(&c1), sorted(&c2));
+        assert_eq!(sorted(&c1), sorted(&c3));
+    }
+
+    #[test]
+    fn silent_peer_should_be_forgotten() {
+        let mut replication = MasterReplication::new(1, MasterReplicationConf::default(), SdnMonitorController::new());
+        replication.on_msg(MasterReplicationMsg::Hello(2), 0);
+        assert_eq!(replication.peers(), vec![2]);
+
+        replication.on_tick(30001);
+        assert_eq!(replication.peers(), Vec::<NodeId>::new());
+    }
+}
@@ -1,21 +1,101 @@
-use crate::collector::{NodeData, SdnMonitorController};
+use std::sync::Arc;
+
+use atm0s_sdn_identity::NodeId;
+use parking_lot::RwLock;
+
+use crate::collector::{Link, LinkMetric, NodeConnectionData, NodeData, SdnMonitorController, TopologyEvent, TopologySubscription};
+
+use super::ingest::{IngestCounters, IngestStats};
+use super::msg::{MasterRole, MasterStatus};
 
 pub struct VisualizationMasterSdk {
     controller: SdnMonitorController,
+    ingest: Arc<IngestCounters>,
+    status: Arc<RwLock<Option<MasterStatus>>>,
 }
 
 impl Clone for VisualizationMasterSdk {
     fn clone(&self) -> Self {
-        Self { controller: self.controller.clone() }
+        Self {
+            controller: self.controller.clone(),
+            ingest: self.ingest.clone(),
+            status: self.status.clone(),
+        }
     }
 }
 
 impl VisualizationMasterSdk {
     pub fn new(controller: SdnMonitorController) -> Self {
-        Self { controller }
+        Self::new_with_ingest(controller, Arc::new(IngestCounters::default()))
+    }
+
+    pub(crate) fn new_with_ingest(controller: SdnMonitorController, ingest: Arc<IngestCounters>) -> Self {
+        Self {
+            controller,
+            ingest,
+            status: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub(crate) fn set_status(&self, status: MasterStatus) {
+        *self.status.write() = Some(status);
+    }
+
+    /// Live masters and the elected leader as seen by this master, None until the behaviour is started
+    pub fn status(&self) -> Option<MasterStatus> {
+        self.status.read().clone()
+    }
+
+    /// Leader unless the behaviour is not started yet or another live master has a lower node id
+    pub fn role(&self) -> Option<MasterRole> {
+        self.status.read().as_ref().map(|status| status.role())
+    }
+
+    /// Ingest queue depth and drop counters of the master behaviour
+    pub fn ingest_stats(&self) -> IngestStats {
+        self.ingest.snapshot()
     }
 
     pub fn get_nodes(&self) -> Vec<NodeData> {
         self.controller.get_nodes()
     }
+
+    pub fn get_node(&self, node_id: NodeId) -> Option<NodeData> {
+        self.controller.get_node(node_id)
+    }
+
+    /// Nodes which `node_id` reports a live connection to
+    pub fn neighbors(&self, node_id: NodeId) -> Vec<NodeId> {
+        self.controller.neighbors(node_id)
+    }
+
+    /// Connections reported by `a` towards `b`
+    pub fn edge(&self, a: NodeId, b: NodeId) -> Vec<NodeConnectionData> {
+        self.controller.edge(a, b)
+    }
+
+    /// Nodes accepted by `predicate`, only matching nodes are cloned
+    pub fn nodes_where<F: Fn(&NodeData) -> bool>(&self, predicate: F) -> Vec<NodeData> {
+        self.controller.get_nodes_where(predicate)
+    }
+
+    /// The `n` worst live links ordered by `by_metric`
+    pub fn worst_links(&self, n: usize, by_metric: LinkMetric) -> Vec<Link> {
+        self.controller.worst_links(n, by_metric)
+    }
+
+    /// Lowest total latency path between two nodes, including both ends
+    pub fn shortest_path(&self, a: NodeId, b: NodeId) -> Option<Vec<NodeId>> {
+        self.controller.shortest_path(a, b)
+    }
+
+    /// Subscribe to all topology events, see `TopologySubscription::recv`
+    pub fn subscribe(&self) -> TopologySubscription {
+        self.controller.subscribe()
+    }
+
+    /// Subscribe to topology events accepted by `filter`
+    pub fn subscribe_filtered<F: Fn(&TopologyEvent) -> bool + Send + Sync + 'static>(&self, filter: F) -> TopologySubscription {
+        self.controller.subscribe_filtered(filter)
+    }
 }
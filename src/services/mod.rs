@@ -1,5 +1,7 @@
 mod agent;
+mod codec;
 mod master;
 
 pub use agent::*;
+pub use codec::{COMPRESS_MIN_BYTES, META_COMPRESSED_LZ4};
 pub use master::*;
@@ -0,0 +1,93 @@
+//! Deterministic in-process harness for the agent and master behaviours, enabled by the `testing` feature.
+//! Behaviours and handlers are driven directly with fake connections and a virtual clock, messages are routed
+//! hop by hop over the simulated links, so a whole mesh runs in a single test without sockets or sleeps.
+//! `SyntheticTopology` generates the report stream of thousands of agents for load testing the master.
+
+mod network;
+mod sender;
+mod topology;
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use atm0s_sdn_network::convert_enum;
+use atm0s_sdn_network::transport::ConnectionStats;
+
+use crate::{VisualizationAgentBehaviourEvent, VisualizationAgentHandlerEvent, VisualizationAgentSdkEvent, VisualizationMasterBehaviourEvent, VisualizationMasterHandlerEvent};
+
+pub use network::{SimNetwork, DEFAULT_SIM_TICK_MS};
+pub use sender::FakeConnectionSender;
+pub use topology::{SyntheticTopology, SyntheticTopologyConf, TopologyModel};
+
+#[derive(Debug, PartialEq, Eq, convert_enum::From, convert_enum::TryInto)]
+pub enum SimBehaviourEvent {
+    Agent(VisualizationAgentBehaviourEvent),
+    Master(VisualizationMasterBehaviourEvent),
+}
+
+/// Neither service sends handler events, the variants only satisfy the bounds
+#[derive(Debug, PartialEq, Eq, convert_enum::From)]
+pub enum SimHandlerEvent {
+    Agent(VisualizationAgentHandlerEvent),
+    Master(VisualizationMasterHandlerEvent),
+}
+
+impl TryFrom<SimHandlerEvent> for VisualizationAgentHandlerEvent {
+    type Error = ();
+
+    fn try_from(event: SimHandlerEvent) -> Result<Self, Self::Error> {
+        match event {
+            SimHandlerEvent::Agent(event) => Ok(event),
+            SimHandlerEvent::Master(_) => Err(()),
+        }
+    }
+}
+
+impl TryFrom<SimHandlerEvent> for VisualizationMasterHandlerEvent {
+    type Error = ();
+
+    fn try_from(event: SimHandlerEvent) -> Result<Self, Self::Error> {
+        match event {
+            SimHandlerEvent::Master(event) => Ok(event),
+            SimHandlerEvent::Agent(_) => Err(()),
+        }
+    }
+}
+
+pub type SimSdkEvent = VisualizationAgentSdkEvent;
+
+/// Time of every simulated node, it only moves when advanced. Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now_ms: Arc<AtomicU64>,
+}
+
+impl VirtualClock {
+    pub fn new(start_ms: u64) -> Self {
+        Self {
+            now_ms: Arc::new(AtomicU64::new(start_ms)),
+        }
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.now_ms.load(Ordering::Relaxed)
+    }
+
+    /// Returns the new time
+    pub fn advance(&self, delta_ms: u64) -> u64 {
+        self.now_ms.fetch_add(delta_ms, Ordering::Relaxed) + delta_ms
+    }
+}
+
+/// Transport stats as reported by a healthy link, for `SimNetwork::inject_stats`
+pub fn link_stats(rtt_ms: u16, loss_percent: u32, sending_kbps: u32) -> ConnectionStats {
+    ConnectionStats {
+        rtt_ms,
+        sending_kbps,
+        send_est_kbps: sending_kbps,
+        loss_percent,
+        over_use: false,
+    }
+}
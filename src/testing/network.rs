@@ -0,0 +1,555 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Arc,
+};
+
+use atm0s_sdn_identity::{ConnDirection, ConnId, NodeAddr, NodeId};
+use atm0s_sdn_network::{
+    behaviour::{BehaviorContext, ConnectionContext, ConnectionHandler, ConnectionHandlerAction, NetworkBehavior, NetworkBehaviorAction},
+    msg::{MsgHeader, TransportMsg},
+    transport::{ConnectionEvent, ConnectionSender, ConnectionStats},
+};
+use atm0s_sdn_router::RouteRule;
+use atm0s_sdn_utils::awaker::{Awaker, MockAwaker};
+
+use crate::{
+    collector::SdnMonitorController, services::IngestQueue, VisualizationAgentBehaviour, VisualizationAgentBehaviourConf, VisualizationAgentSdk, VisualizationMasterBehaviour,
+    VisualizationMasterBehaviourConf, VisualizationMasterSdk, VISUALIZATION_AGENT_SERVICE, VISUALIZATION_MASTER_SERVICE,
+};
+
+use super::{FakeConnectionSender, SimBehaviourEvent, SimHandlerEvent, SimSdkEvent, VirtualClock};
+
+pub const DEFAULT_SIM_TICK_MS: u64 = 1000;
+/// Protocol of every simulated connection
+const SIM_PROTOCOL: u8 = 0;
+/// Delivery rounds after which `settle` gives up, only reached by services answering each other forever
+const MAX_SETTLE_ROUNDS: usize = 1000;
+
+type SimBehaviour = Box<dyn NetworkBehavior<SimBehaviourEvent, SimHandlerEvent, SimSdkEvent>>;
+type SimHandler = Box<dyn ConnectionHandler<SimBehaviourEvent, SimHandlerEvent>>;
+
+struct SimConn {
+    conn_id: ConnId,
+    remote: NodeId,
+    sender: Arc<FakeConnectionSender>,
+    // by service id
+    handlers: BTreeMap<u8, SimHandler>,
+}
+
+struct SimMaster {
+    controller: SdnMonitorController,
+    sdk: VisualizationMasterSdk,
+    ingest: Arc<IngestQueue>,
+}
+
+struct SimNode {
+    id: NodeId,
+    addr: NodeAddr,
+    alive: bool,
+    awaker: Arc<MockAwaker>,
+    // by service id
+    behaviours: BTreeMap<u8, SimBehaviour>,
+    // by connection uuid, both ends of a link share it
+    conns: BTreeMap<u64, SimConn>,
+    agent_sdk: VisualizationAgentSdk,
+    master: Option<SimMaster>,
+}
+
+impl SimNode {
+    /// Apply what the master ingest got so far, one message at a time keeps its batches and coalescing
+    /// independent of the worker thread timing
+    fn wait_ingest(&self) {
+        if let Some(master) = self.master.as_ref() {
+            master.ingest.wait_idle();
+        }
+    }
+
+    fn ctx(&self, service_id: u8) -> BehaviorContext {
+        BehaviorContext {
+            service_id,
+            node_id: self.id,
+            awaker: self.awaker.clone(),
+        }
+    }
+
+    fn conn_ctx(&self, service_id: u8, remote: NodeId, conn_id: ConnId) -> ConnectionContext {
+        ConnectionContext {
+            service_id,
+            local_node_id: self.id,
+            remote_node_id: remote,
+            conn_id,
+            awaker: self.awaker.clone(),
+        }
+    }
+}
+
+enum SimAction {
+    Behaviour(NetworkBehaviorAction<SimHandlerEvent, SimSdkEvent>),
+    // service id, connection uuid
+    Handler(u8, u64, ConnectionHandlerAction<SimBehaviourEvent, SimHandlerEvent>),
+}
+
+/// Nodes running the visualization agent, and the master for some of them, linked by fake connections.
+/// Everything happens in node, connection and message order on the caller thread, except the master ingest
+/// which is waited for after each delivered message, and services only see the virtual clock, so a scenario replays identically: stores,
+/// traffic and event history. Metrics sinks are flushed with the virtual clock, only the HTTP API reads the wall clock.
+/// Each mutation settles the network before returning.
+pub struct SimNetwork {
+    clock: VirtualClock,
+    tick_ms: u64,
+    nodes: BTreeMap<NodeId, SimNode>,
+    next_conn_uuid: u64,
+    dropped_msgs: u64,
+}
+
+impl Default for SimNetwork {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIM_TICK_MS)
+    }
+}
+
+impl SimNetwork {
+    pub fn new(tick_ms: u64) -> Self {
+        Self {
+            clock: VirtualClock::default(),
+            tick_ms,
+            nodes: BTreeMap::new(),
+            next_conn_uuid: 0,
+            dropped_msgs: 0,
+        }
+    }
+
+    pub fn clock(&self) -> VirtualClock {
+        self.clock.clone()
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.clock.now_ms()
+    }
+
+    /// Start a node running only the agent
+    pub fn add_agent(&mut self, conf: VisualizationAgentBehaviourConf) {
+        self.add_node(conf, None);
+    }
+
+    /// Start a node running the agent and a master with its own store
+    pub fn add_master(&mut self, agent_conf: VisualizationAgentBehaviourConf, conf: VisualizationMasterBehaviourConf) {
+        self.add_node(agent_conf, Some(conf));
+    }
+
+    fn add_node(&mut self, agent_conf: VisualizationAgentBehaviourConf, master_conf: Option<VisualizationMasterBehaviourConf>) {
+        let node_id = agent_conf.node_id;
+        assert!(!self.nodes.contains_key(&node_id), "node {} already added", node_id);
+        let addr = agent_conf.node_addr.clone();
+        let (agent, agent_sdk) = VisualizationAgentBehaviour::<SimHandlerEvent, SimSdkEvent>::new(agent_conf);
+        let mut behaviours: BTreeMap<u8, SimBehaviour> = BTreeMap::new();
+        behaviours.insert(VISUALIZATION_AGENT_SERVICE, Box::new(agent));
+        let master = master_conf.map(|conf| {
+            let controller = SdnMonitorController::new();
+            let (master, sdk) = VisualizationMasterBehaviour::<SimHandlerEvent, SimSdkEvent>::new_with_conf(controller.clone(), conf);
+            let ingest = master.ingest_queue();
+            behaviours.insert(VISUALIZATION_MASTER_SERVICE, Box::new(master));
+            SimMaster { controller, sdk, ingest }
+        });
+
+        let mut node = SimNode {
+            id: node_id,
+            addr,
+            alive: true,
+            awaker: Arc::new(MockAwaker::default()),
+            behaviours: BTreeMap::new(),
+            conns: BTreeMap::new(),
+            agent_sdk,
+            master,
+        };
+        let now_ms = self.now_ms();
+        for (service_id, behaviour) in behaviours.iter_mut() {
+            behaviour.on_started(&node.ctx(*service_id), now_ms);
+        }
+        node.behaviours = behaviours;
+        self.nodes.insert(node_id, node);
+        self.settle();
+    }
+
+    fn node(&self, node_id: NodeId) -> &SimNode {
+        self.nodes.get(&node_id).unwrap_or_else(|| panic!("node {} is not simulated", node_id))
+    }
+
+    fn master(&self, node_id: NodeId) -> &SimMaster {
+        self.node(node_id).master.as_ref().unwrap_or_else(|| panic!("node {} is not a master", node_id))
+    }
+
+    /// Store of a master node, panics for other nodes
+    pub fn controller(&self, node_id: NodeId) -> &SdnMonitorController {
+        &self.master(node_id).controller
+    }
+
+    /// Sdk of a master node, panics for other nodes
+    pub fn master_sdk(&self, node_id: NodeId) -> &VisualizationMasterSdk {
+        &self.master(node_id).sdk
+    }
+
+    pub fn agent_sdk(&self, node_id: NodeId) -> &VisualizationAgentSdk {
+        &self.node(node_id).agent_sdk
+    }
+
+    pub fn is_alive(&self, node_id: NodeId) -> bool {
+        self.node(node_id).alive
+    }
+
+    /// Nodes directly linked to `node_id`, in id order
+    pub fn neighbours(&self, node_id: NodeId) -> Vec<NodeId> {
+        let neighbours: BTreeSet<NodeId> = self.node(node_id).conns.values().map(|conn| conn.remote).collect();
+        neighbours.into_iter().collect()
+    }
+
+    /// Senders of the open connections of `node_id`, in connection order
+    pub fn senders(&self, node_id: NodeId) -> Vec<Arc<FakeConnectionSender>> {
+        self.node(node_id).conns.values().map(|conn| conn.sender.clone()).collect()
+    }
+
+    /// Messages which had no route, no live destination or no receiving service
+    pub fn dropped_msgs(&self) -> u64 {
+        self.dropped_msgs
+    }
+
+    /// Open a link between two live nodes, services are not asked to accept it. Returns the id on the `from` side.
+    pub fn connect(&mut self, from: NodeId, to: NodeId) -> ConnId {
+        assert_ne!(from, to, "a node can not connect to itself");
+        assert!(self.is_alive(from) && self.is_alive(to), "can not connect {} to {}, node is down", from, to);
+        self.next_conn_uuid += 1;
+        let uuid = self.next_conn_uuid;
+        let from_addr = self.node(from).addr.clone();
+        let to_addr = self.node(to).addr.clone();
+        self.open_conn(from, to, to_addr, ConnId::from_out(SIM_PROTOCOL, uuid));
+        self.open_conn(to, from, from_addr, ConnId::from_in(SIM_PROTOCOL, uuid));
+        self.settle();
+        ConnId::from_out(SIM_PROTOCOL, uuid)
+    }
+
+    fn open_conn(&mut self, node_id: NodeId, remote: NodeId, remote_addr: NodeAddr, conn_id: ConnId) {
+        let now_ms = self.now_ms();
+        let node = self.nodes.get_mut(&node_id).expect("should have node");
+        let sender = Arc::new(FakeConnectionSender::new(remote, conn_id, remote_addr));
+        let outgoing = conn_id.direction() == ConnDirection::Outgoing;
+        let mut handlers = BTreeMap::new();
+        let mut behaviours = std::mem::take(&mut node.behaviours);
+        for (service_id, behaviour) in behaviours.iter_mut() {
+            let ctx = node.ctx(*service_id);
+            let handler = if outgoing {
+                behaviour.on_outgoing_connection_connected(&ctx, now_ms, sender.clone())
+            } else {
+                behaviour.on_incoming_connection_connected(&ctx, now_ms, sender.clone())
+            };
+            if let Some(mut handler) = handler {
+                handler.on_opened(&node.conn_ctx(*service_id, remote, conn_id), now_ms);
+                handlers.insert(*service_id, handler);
+            }
+        }
+        node.behaviours = behaviours;
+        node.conns.insert(conn_id.uuid(), SimConn { conn_id, remote, sender, handlers });
+    }
+
+    /// Close every link between `a` and `b`, both ends see the disconnection. Returns the number of closed links.
+    pub fn disconnect(&mut self, a: NodeId, b: NodeId) -> usize {
+        let uuids: Vec<u64> = self.node(a).conns.iter().filter(|(_, conn)| conn.remote == b).map(|(uuid, _)| *uuid).collect();
+        for uuid in uuids.iter() {
+            self.close_link(a, *uuid);
+        }
+        self.settle();
+        uuids.len()
+    }
+
+    fn close_link(&mut self, node_id: NodeId, uuid: u64) {
+        if let Some(remote) = self.nodes.get(&node_id).and_then(|node| node.conns.get(&uuid)).map(|conn| conn.remote) {
+            self.close_conn(node_id, uuid);
+            self.close_conn(remote, uuid);
+        }
+    }
+
+    fn close_conn(&mut self, node_id: NodeId, uuid: u64) {
+        let now_ms = self.now_ms();
+        let Some(node) = self.nodes.get_mut(&node_id) else {
+            return;
+        };
+        let Some(mut conn) = node.conns.remove(&uuid) else {
+            return;
+        };
+        conn.sender.close();
+        for (service_id, handler) in conn.handlers.iter_mut() {
+            handler.on_closed(&node.conn_ctx(*service_id, conn.remote, conn.conn_id), now_ms);
+        }
+        let mut behaviours = std::mem::take(&mut node.behaviours);
+        for (service_id, behaviour) in behaviours.iter_mut() {
+            let ctx = node.ctx(*service_id);
+            if conn.conn_id.direction() == ConnDirection::Outgoing {
+                behaviour.on_outgoing_connection_disconnected(&ctx, now_ms, conn.remote, conn.conn_id);
+            } else {
+                behaviour.on_incoming_connection_disconnected(&ctx, now_ms, conn.remote, conn.conn_id);
+            }
+        }
+        node.behaviours = behaviours;
+    }
+
+    /// Crash a node: its services stop without notice and its peers see their links drop. A dead master keeps its
+    /// store for inspection.
+    pub fn kill(&mut self, node_id: NodeId) {
+        let links: Vec<(u64, NodeId)> = self.node(node_id).conns.iter().map(|(uuid, conn)| (*uuid, conn.remote)).collect();
+        let node = self.nodes.get_mut(&node_id).expect("should have node");
+        node.alive = false;
+        for (_, conn) in std::mem::take(&mut node.conns) {
+            conn.sender.close();
+        }
+        for (uuid, remote) in links {
+            self.close_conn(remote, uuid);
+        }
+        self.settle();
+    }
+
+    /// Feed transport stats to the handlers of the links from `node_id` to `remote`, like the transport does on
+    /// each measurement. Only that end sees them.
+    pub fn inject_stats(&mut self, node_id: NodeId, remote: NodeId, stats: ConnectionStats) {
+        let now_ms = self.now_ms();
+        let node = self.nodes.get_mut(&node_id).expect("should have node");
+        let mut conns = std::mem::take(&mut node.conns);
+        for conn in conns.values_mut().filter(|conn| conn.remote == remote) {
+            for (service_id, handler) in conn.handlers.iter_mut() {
+                handler.on_event(&node.conn_ctx(*service_id, conn.remote, conn.conn_id), now_ms, ConnectionEvent::Stats(stats.clone()));
+            }
+        }
+        node.conns = conns;
+        self.settle();
+    }
+
+    /// Advance the clock by one tick, then tick every live service and handler
+    pub fn tick(&mut self) {
+        let now_ms = self.clock.advance(self.tick_ms);
+        let tick_ms = self.tick_ms;
+        for node in self.nodes.values_mut().filter(|node| node.alive) {
+            let mut behaviours = std::mem::take(&mut node.behaviours);
+            for (service_id, behaviour) in behaviours.iter_mut() {
+                behaviour.on_tick(&node.ctx(*service_id), now_ms, tick_ms);
+                node.wait_ingest();
+            }
+            node.behaviours = behaviours;
+            let mut conns = std::mem::take(&mut node.conns);
+            for conn in conns.values_mut() {
+                for (service_id, handler) in conn.handlers.iter_mut() {
+                    handler.on_tick(&node.conn_ctx(*service_id, conn.remote, conn.conn_id), now_ms, tick_ms);
+                }
+            }
+            node.conns = conns;
+        }
+        self.settle();
+    }
+
+    pub fn run(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+
+    /// Deliver actions and messages until nothing moves, then wait for the masters to apply what they received
+    pub fn settle(&mut self) {
+        for _ in 0..MAX_SETTLE_ROUNDS {
+            if !self.deliver_round() {
+                for node in self.nodes.values().filter(|node| node.alive) {
+                    node.wait_ingest();
+                }
+                return;
+            }
+        }
+        panic!("network did not settle after {} rounds", MAX_SETTLE_ROUNDS);
+    }
+
+    /// Returns false when there was nothing to deliver
+    fn deliver_round(&mut self) -> bool {
+        let now_ms = self.now_ms();
+        let mut moved = false;
+        let node_ids: Vec<NodeId> = self.nodes.values().filter(|node| node.alive).map(|node| node.id).collect();
+        for node_id in node_ids.iter() {
+            for action in self.pop_actions(*node_id, now_ms) {
+                moved = true;
+                self.on_action(*node_id, action, now_ms);
+                self.node(*node_id).wait_ingest();
+            }
+        }
+
+        let mut in_flight = vec![];
+        for node in self.nodes.values().filter(|node| node.alive) {
+            for (uuid, conn) in node.conns.iter() {
+                in_flight.extend(conn.sender.take_outbox().into_iter().map(|msg| (conn.remote, *uuid, msg)));
+            }
+        }
+        for (node_id, uuid, msg) in in_flight {
+            moved = true;
+            self.route(node_id, Some(uuid), msg, now_ms);
+            self.node(node_id).wait_ingest();
+        }
+        moved
+    }
+
+    fn pop_actions(&mut self, node_id: NodeId, now_ms: u64) -> Vec<SimAction> {
+        let node = self.nodes.get_mut(&node_id).expect("should have node");
+        let mut behaviours = std::mem::take(&mut node.behaviours);
+        if node.awaker.pop_awake_count() > 0 {
+            for (service_id, behaviour) in behaviours.iter_mut() {
+                behaviour.on_awake(&node.ctx(*service_id), now_ms);
+            }
+        }
+        let mut actions = vec![];
+        for behaviour in behaviours.values_mut() {
+            actions.extend(std::iter::from_fn(|| behaviour.pop_action()).map(SimAction::Behaviour));
+        }
+        node.behaviours = behaviours;
+        for (uuid, conn) in node.conns.iter_mut() {
+            for (service_id, handler) in conn.handlers.iter_mut() {
+                actions.extend(std::iter::from_fn(|| handler.pop_action()).map(|action| SimAction::Handler(*service_id, *uuid, action)));
+            }
+        }
+        actions
+    }
+
+    fn on_action(&mut self, node_id: NodeId, action: SimAction, now_ms: u64) {
+        match action {
+            SimAction::Behaviour(action) => match action {
+                NetworkBehaviorAction::ToNet(msg) => self.route(node_id, None, msg, now_ms),
+                NetworkBehaviorAction::ToNetConn(conn_id, msg) => self.send_conn(node_id, conn_id.uuid(), msg),
+                NetworkBehaviorAction::ToNetNode(remote, msg) => self.send_node(node_id, remote, msg),
+                NetworkBehaviorAction::ToHandler(_, event) => match event {
+                    SimHandlerEvent::Agent(event) => match event {},
+                    SimHandlerEvent::Master(event) => match event {},
+                },
+                NetworkBehaviorAction::CloseConn(conn_id) => self.close_link(node_id, conn_id.uuid()),
+                NetworkBehaviorAction::CloseNode(remote) => {
+                    let uuids: Vec<u64> = self.node(node_id).conns.iter().filter(|(_, conn)| conn.remote == remote).map(|(uuid, _)| *uuid).collect();
+                    for uuid in uuids {
+                        self.close_link(node_id, uuid);
+                    }
+                }
+                // links are opened by the scenario and no sdk service runs
+                NetworkBehaviorAction::ConnectTo(_) | NetworkBehaviorAction::ToSdkService(..) => {}
+            },
+            SimAction::Handler(service_id, uuid, action) => match action {
+                ConnectionHandlerAction::ToBehaviour(event) => {
+                    let node = self.nodes.get_mut(&node_id).expect("should have node");
+                    let Some((remote, conn_id)) = node.conns.get(&uuid).map(|conn| (conn.remote, conn.conn_id)) else {
+                        return;
+                    };
+                    let ctx = node.ctx(service_id);
+                    if let Some(behaviour) = node.behaviours.get_mut(&service_id) {
+                        behaviour.on_handler_event(&ctx, now_ms, remote, conn_id, event);
+                    }
+                }
+                ConnectionHandlerAction::ToNet(msg) => self.route(node_id, None, msg, now_ms),
+                ConnectionHandlerAction::ToNetConn(conn_id, msg) => self.send_conn(node_id, conn_id.uuid(), msg),
+                ConnectionHandlerAction::ToNetNode(remote, msg) => self.send_node(node_id, remote, msg),
+                ConnectionHandlerAction::ToHandler(_, event) => match event {
+                    SimHandlerEvent::Agent(event) => match event {},
+                    SimHandlerEvent::Master(event) => match event {},
+                },
+                ConnectionHandlerAction::CloseConn() => self.close_link(node_id, uuid),
+            },
+        }
+    }
+
+    fn send_conn(&mut self, node_id: NodeId, uuid: u64, msg: TransportMsg) {
+        match self.node(node_id).conns.get(&uuid) {
+            Some(conn) => conn.sender.send(msg),
+            None => self.dropped_msgs += 1,
+        }
+    }
+
+    fn send_node(&mut self, node_id: NodeId, remote: NodeId, msg: TransportMsg) {
+        match self.node(node_id).conns.values().find(|conn| conn.remote == remote) {
+            Some(conn) => conn.sender.send(msg),
+            None => self.dropped_msgs += 1,
+        }
+    }
+
+    /// Hand `msg` to its service when `node_id` is the destination, otherwise forward it one hop along a shortest
+    /// path. `via` is the link it arrived on, remote messages are received by the handler of that link.
+    fn route(&mut self, node_id: NodeId, via: Option<u64>, msg: TransportMsg, now_ms: u64) {
+        let Some(dest) = self.destination(node_id, via, &msg.header) else {
+            self.dropped_msgs += 1;
+            return;
+        };
+        if dest != node_id {
+            match self.paths(node_id).get(&dest) {
+                Some((_, uuid)) => self.send_conn(node_id, *uuid, msg),
+                None => self.dropped_msgs += 1,
+            }
+            return;
+        }
+
+        let service_id = msg.header.to_service_id;
+        let node = self.nodes.get_mut(&node_id).expect("should have node");
+        let delivered = match via {
+            Some(uuid) => {
+                let mut conns = std::mem::take(&mut node.conns);
+                let delivered = match conns.get_mut(&uuid) {
+                    Some(conn) => match conn.handlers.get_mut(&service_id) {
+                        Some(handler) => {
+                            handler.on_event(&node.conn_ctx(service_id, conn.remote, conn.conn_id), now_ms, ConnectionEvent::Msg(msg));
+                            true
+                        }
+                        None => false,
+                    },
+                    None => false,
+                };
+                node.conns = conns;
+                delivered
+            }
+            None => {
+                let ctx = node.ctx(service_id);
+                match node.behaviours.get_mut(&service_id) {
+                    Some(behaviour) => {
+                        behaviour.on_local_msg(&ctx, now_ms, msg);
+                        true
+                    }
+                    None => false,
+                }
+            }
+        };
+        if !delivered {
+            self.dropped_msgs += 1;
+        }
+    }
+
+    /// Service routes go to the closest live node running the service, the lowest id on a tie
+    fn destination(&self, node_id: NodeId, via: Option<u64>, header: &MsgHeader) -> Option<NodeId> {
+        match header.route {
+            RouteRule::ToNode(dest) => Some(dest),
+            RouteRule::ToService(_) => {
+                if self.node(node_id).behaviours.contains_key(&header.to_service_id) {
+                    return Some(node_id);
+                }
+                self.paths(node_id)
+                    .into_iter()
+                    .filter(|(dest, _)| self.node(*dest).behaviours.contains_key(&header.to_service_id))
+                    .min_by_key(|(dest, (hops, _))| (*hops, *dest))
+                    .map(|(dest, _)| dest)
+            }
+            RouteRule::Direct => via.map(|_| node_id),
+            RouteRule::ToKey(_) => None,
+        }
+    }
+
+    /// Hop count and first link uuid toward every node reachable from `from`
+    fn paths(&self, from: NodeId) -> BTreeMap<NodeId, (usize, u64)> {
+        let mut paths = BTreeMap::new();
+        let mut seen = BTreeSet::from([from]);
+        let mut queue = VecDeque::from([from]);
+        while let Some(node_id) = queue.pop_front() {
+            let via = paths.get(&node_id).copied();
+            for (uuid, conn) in self.node(node_id).conns.iter() {
+                if seen.insert(conn.remote) {
+                    let (hops, first) = via.map(|(hops, first)| (hops + 1, first)).unwrap_or((1, *uuid));
+                    paths.insert(conn.remote, (hops, first));
+                    queue.push_back(conn.remote);
+                }
+            }
+        }
+        paths
+    }
+}
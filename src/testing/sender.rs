@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use atm0s_sdn_identity::{ConnId, NodeAddr, NodeId};
+use atm0s_sdn_network::{msg::TransportMsg, transport::ConnectionSender};
+use parking_lot::Mutex;
+
+/// Keeps what the network sends on a connection until the simulation delivers it to the other end
+pub struct FakeConnectionSender {
+    remote_node_id: NodeId,
+    conn_id: ConnId,
+    remote_addr: NodeAddr,
+    outbox: Mutex<Vec<TransportMsg>>,
+    sent_msgs: AtomicU64,
+    sent_bytes: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl FakeConnectionSender {
+    pub fn new(remote_node_id: NodeId, conn_id: ConnId, remote_addr: NodeAddr) -> Self {
+        Self {
+            remote_node_id,
+            conn_id,
+            remote_addr,
+            outbox: Mutex::new(vec![]),
+            sent_msgs: AtomicU64::new(0),
+            sent_bytes: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Messages sent since the last call, in order
+    pub fn take_outbox(&self) -> Vec<TransportMsg> {
+        std::mem::take(&mut *self.outbox.lock())
+    }
+
+    pub fn sent_msgs(&self) -> u64 {
+        self.sent_msgs.load(Ordering::Relaxed)
+    }
+
+    /// Header and payload bytes sent since the connection opened
+    pub fn sent_bytes(&self) -> u64 {
+        self.sent_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+impl ConnectionSender for FakeConnectionSender {
+    fn remote_node_id(&self) -> NodeId {
+        self.remote_node_id
+    }
+
+    fn conn_id(&self) -> ConnId {
+        self.conn_id
+    }
+
+    fn remote_addr(&self) -> NodeAddr {
+        self.remote_addr.clone()
+    }
+
+    /// Messages sent after close are lost like on a real connection
+    fn send(&self, msg: TransportMsg) {
+        if self.is_closed() {
+            return;
+        }
+        self.sent_msgs.fetch_add(1, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(msg.get_buf().len() as u64, Ordering::Relaxed);
+        self.outbox.lock().push(msg);
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::{BTreeMap, HashSet};
+
+use atm0s_sdn_identity::{ConnDirection, NodeId};
+
+use crate::{
+    identity::{generate_connection_id, ConnectionMetric, ConnectionStatus},
+    ConnectionMsg, VisualizationAgentMsg, MAX_CONN_STATS_SEND,
+};
+
+/// Protocol of every generated connection
+const SYNTHETIC_PROTOCOL: u8 = 1;
+
+/// Shape of the generated graph
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TopologyModel {
+    /// Links between uniformly picked pairs, `avg_degree` links per node on average
+    Random { avg_degree: usize },
+    /// Preferential attachment, each new node links to `links_per_node` nodes picked by degree, a few hubs emerge
+    ScaleFree { links_per_node: usize },
+    /// Random graphs of `intra_degree` inside each region, plus `inter_links` links from each region to the others
+    RegionalClusters { regions: usize, intra_degree: usize, inter_links: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntheticTopologyConf {
+    pub nodes: u32,
+    pub model: TopologyModel,
+    /// Same seed, same graph and same message stream
+    pub seed: u64,
+    pub round_ms: u64,
+    /// Probability per round for an online node to go offline, it stays silent for `offline_rounds`
+    pub node_churn: f64,
+    pub offline_rounds: u32,
+    /// Probability per round for a link to be down during that round
+    pub link_flap: f64,
+    /// Reported latency varies around the link base latency by up to this percent
+    pub latency_jitter_percent: u32,
+    /// Probability per report of a loss spike on a link
+    pub loss_spike: f64,
+    pub max_conns_per_report: usize,
+}
+
+impl SyntheticTopologyConf {
+    /// 1% node churn, 0.5% link flaps, 20% latency jitter and rare loss spikes, reports batched like agents do
+    pub fn new(nodes: u32, model: TopologyModel) -> Self {
+        Self {
+            nodes,
+            model,
+            seed: 1,
+            round_ms: 1000,
+            node_churn: 0.01,
+            offline_rounds: 3,
+            link_flap: 0.005,
+            latency_jitter_percent: 20,
+            loss_spike: 0.01,
+            max_conns_per_report: MAX_CONN_STATS_SEND,
+        }
+    }
+}
+
+/// SplitMix64, small and stable across releases so seeds keep producing the same topologies
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`, n must not be 0
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+struct SyntheticLink {
+    a: NodeId,
+    b: NodeId,
+    latency: u16,
+    bandwidth: u32,
+    down: bool,
+}
+
+struct SyntheticNode {
+    id: NodeId,
+    addr: String,
+    labels: BTreeMap<String, String>,
+    links: Vec<usize>,
+    offline_until: u32,
+    labels_sent: bool,
+}
+
+/// Reports of a whole network of agents, one round at a time, in the form the master receives them.
+/// Iterating yields rounds forever.
+pub struct SyntheticTopology {
+    conf: SyntheticTopologyConf,
+    rng: SplitMix64,
+    nodes: Vec<SyntheticNode>,
+    links: Vec<SyntheticLink>,
+    round: u32,
+    now_ms: u64,
+}
+
+impl SyntheticTopology {
+    pub fn new(conf: SyntheticTopologyConf) -> Self {
+        let mut rng = SplitMix64(conf.seed);
+        let regions = match conf.model {
+            TopologyModel::RegionalClusters { regions, .. } => regions.max(1),
+            _ => 4,
+        };
+        let mut nodes: Vec<SyntheticNode> = (0..conf.nodes)
+            .map(|index| {
+                let id = index + 1;
+                let region = index as usize % regions;
+                SyntheticNode {
+                    id,
+                    addr: format!("/ip4/10.{}.{}.{}/udp/10000", region, id >> 8 & 0xff, id & 0xff),
+                    labels: BTreeMap::from([(String::from("region"), format!("region-{}", region)), (String::from("zone"), format!("zone-{}", id % 3))]),
+                    links: vec![],
+                    offline_until: 0,
+                    labels_sent: false,
+                }
+            })
+            .collect();
+
+        let pairs = match conf.model {
+            TopologyModel::Random { avg_degree } => {
+                let ids: Vec<usize> = (0..nodes.len()).collect();
+                random_pairs(&mut rng, &ids, ids.len() * avg_degree / 2)
+            }
+            TopologyModel::ScaleFree { links_per_node } => scale_free_pairs(&mut rng, nodes.len(), links_per_node.max(1)),
+            TopologyModel::RegionalClusters { intra_degree, inter_links, .. } => {
+                let mut pairs = vec![];
+                let members: Vec<Vec<usize>> = (0..regions).map(|region| (region..nodes.len()).step_by(regions).collect()).collect();
+                for ids in members.iter() {
+                    pairs.extend(random_pairs(&mut rng, ids, ids.len() * intra_degree / 2));
+                }
+                if regions > 1 {
+                    for (region, ids) in members.iter().enumerate().filter(|(_, ids)| !ids.is_empty()) {
+                        for _ in 0..inter_links {
+                            let other = (region + 1 + rng.below(regions as u64 - 1) as usize) % regions;
+                            if members[other].is_empty() {
+                                continue;
+                            }
+                            let a = ids[rng.below(ids.len() as u64) as usize];
+                            let b = members[other][rng.below(members[other].len() as u64) as usize];
+                            pairs.push((a, b));
+                        }
+                    }
+                }
+                pairs
+            }
+        };
+
+        let mut seen = HashSet::new();
+        let mut links = vec![];
+        for (a, b) in pairs {
+            if a == b || !seen.insert((a.min(b), a.max(b))) {
+                continue;
+            }
+            let same_region = a % regions == b % regions;
+            let latency = if same_region {
+                1 + rng.below(20)
+            } else {
+                40 + rng.below(160)
+            };
+            nodes[a].links.push(links.len());
+            nodes[b].links.push(links.len());
+            links.push(SyntheticLink {
+                a: nodes[a].id,
+                b: nodes[b].id,
+                latency: latency as u16,
+                bandwidth: 1000 * (1 + rng.below(100) as u32),
+                down: false,
+            });
+        }
+
+        Self {
+            conf,
+            rng,
+            nodes,
+            links,
+            round: 0,
+            now_ms: 0,
+        }
+    }
+
+    pub fn link_count(&self) -> usize {
+        self.links.len()
+    }
+
+    /// Time of the last generated round
+    pub fn now_ms(&self) -> u64 {
+        self.now_ms
+    }
+
+    /// Nodes silent this round because of churn
+    pub fn offline_nodes(&self) -> usize {
+        self.nodes.iter().filter(|node| !self.is_online(node)).count()
+    }
+
+    fn is_online(&self, node: &SyntheticNode) -> bool {
+        node.offline_until <= self.round
+    }
+
+    /// Apply churn and link flaps, then return the ping, labels and connection reports of every online node.
+    /// Labels are sent on the first round a node is online and again after each outage.
+    pub fn next_round(&mut self) -> Vec<VisualizationAgentMsg> {
+        self.round += 1;
+        self.now_ms += self.conf.round_ms;
+        let round = self.round;
+        for node in self.nodes.iter_mut() {
+            if node.offline_until <= round && self.rng.chance(self.conf.node_churn) {
+                node.offline_until = round + self.conf.offline_rounds.max(1);
+                node.labels_sent = false;
+            }
+        }
+        for link in self.links.iter_mut() {
+            link.down = self.rng.chance(self.conf.link_flap);
+        }
+
+        let mut msgs = vec![];
+        for index in 0..self.nodes.len() {
+            if !self.is_online(&self.nodes[index]) {
+                continue;
+            }
+            let node = &self.nodes[index];
+            msgs.push(VisualizationAgentMsg::NodePing(node.id, node.addr.clone(), self.now_ms));
+            if !node.labels_sent {
+                msgs.push(VisualizationAgentMsg::NodeLabels(node.id, node.labels.clone()));
+            }
+            let conns: Vec<ConnectionMsg> = node.links.clone().into_iter().map(|link| self.connection_msg(index, link)).collect();
+            let node_id = self.nodes[index].id;
+            for chunk in conns.chunks(self.conf.max_conns_per_report.max(1)) {
+                msgs.push(VisualizationAgentMsg::NodeConnections(node_id, chunk.to_vec()));
+            }
+            self.nodes[index].labels_sent = true;
+        }
+        msgs
+    }
+
+    /// The link seen from `nodes[index]`, the node which opened it reports it as outgoing
+    fn connection_msg(&mut self, index: usize, link_index: usize) -> ConnectionMsg {
+        let node_id = self.nodes[index].id;
+        let link = &self.links[link_index];
+        let (remote, direction) = if link.a == node_id {
+            (link.b, ConnDirection::Outgoing)
+        } else {
+            (link.a, ConnDirection::Incoming)
+        };
+        let remote_online = self.is_online(&self.nodes[remote as usize - 1]);
+        let (base_latency, bandwidth, down) = (link.latency as u64, link.bandwidth, link.down);
+
+        let jitter = base_latency * self.conf.latency_jitter_percent as u64 / 100;
+        let latency = (base_latency + self.rng.below(2 * jitter + 1)).saturating_sub(jitter);
+        let loss_percent = if self.rng.chance(self.conf.loss_spike) {
+            5 + self.rng.below(26) as u32
+        } else {
+            self.rng.below(2) as u32
+        };
+        let direction_byte = direction.to_byte();
+        ConnectionMsg {
+            conn_id: generate_connection_id(SYNTHETIC_PROTOCOL, direction, remote),
+            protocol: SYNTHETIC_PROTOCOL,
+            addr: self.nodes[remote as usize - 1].addr.clone(),
+            node_id: remote,
+            direction: direction_byte,
+            status: if down || !remote_online {
+                ConnectionStatus::DISCONNECTED
+            } else {
+                ConnectionStatus::CONNECTED
+            },
+            metric: ConnectionMetric {
+                latency: latency.min(u16::MAX as u64) as u16,
+                bandwidth: bandwidth - bandwidth / 10 + self.rng.below(bandwidth as u64 / 5 + 1) as u32,
+                loss_percent,
+            },
+            latest_updated_at: self.now_ms,
+        }
+    }
+}
+
+impl Iterator for SyntheticTopology {
+    type Item = Vec<VisualizationAgentMsg>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_round())
+    }
+}
+
+/// `count` distinct pairs of `ids` picked uniformly, fewer when the ids can not hold that many
+fn random_pairs(rng: &mut SplitMix64, ids: &[usize], count: usize) -> Vec<(usize, usize)> {
+    if ids.len() < 2 {
+        return vec![];
+    }
+    let count = count.min(ids.len() * (ids.len() - 1) / 2);
+    let mut seen = HashSet::new();
+    let mut pairs = Vec::with_capacity(count);
+    while pairs.len() < count {
+        let a = ids[rng.below(ids.len() as u64) as usize];
+        let b = ids[rng.below(ids.len() as u64) as usize];
+        if a != b && seen.insert((a.min(b), a.max(b))) {
+            pairs.push((a, b));
+        }
+    }
+    pairs
+}
+
+/// Barabasi-Albert: a small clique, then each node links to `m` distinct nodes picked proportionally to their degree
+fn scale_free_pairs(rng: &mut SplitMix64, nodes: usize, m: usize) -> Vec<(usize, usize)> {
+    let clique = (m + 1).min(nodes);
+    let mut pairs = vec![];
+    // every link end once, picking from it is picking by degree
+    let mut ends = vec![];
+    for a in 0..clique {
+        for b in (a + 1)..clique {
+            pairs.push((a, b));
+            ends.extend([a, b]);
+        }
+    }
+    for a in clique..nodes {
+        let mut targets = vec![];
+        while targets.len() < m.min(a) {
+            let b = ends[rng.below(ends.len() as u64) as usize];
+            if !targets.contains(&b) {
+                targets.push(b);
+            }
+        }
+        for b in targets {
+            pairs.push((a, b));
+            ends.extend([a, b]);
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn degrees(topology: &SyntheticTopology) -> Vec<usize> {
+        topology.nodes.iter().map(|node| node.links.len()).collect()
+    }
+
+    #[test]
+    fn same_seed_should_generate_same_stream() {
+        let conf = SyntheticTopologyConf::new(200, TopologyModel::ScaleFree { links_per_node: 2 });
+        let a: Vec<_> = SyntheticTopology::new(conf.clone()).take(5).collect();
+        let b: Vec<_> = SyntheticTopology::new(conf.clone()).take(5).collect();
+        assert_eq!(a, b);
+        let c: Vec<_> = SyntheticTopology::new(SyntheticTopologyConf { seed: 2, ..conf }).take(5).collect();
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn models_should_have_expected_link_counts() {
+        let random = SyntheticTopology::new(SyntheticTopologyConf::new(1000, TopologyModel::Random { avg_degree: 6 }));
+        assert_eq!(random.link_count(), 3000);
+
+        let scale_free = SyntheticTopology::new(SyntheticTopologyConf::new(1000, TopologyModel::ScaleFree { links_per_node: 3 }));
+        assert_eq!(scale_free.link_count(), 6 + 996 * 3);
+        let max_degree = degrees(&scale_free).into_iter().max().unwrap_or(0);
+        assert!(max_degree > 50, "should grow hubs, max degree {}", max_degree);
+
+        let regional = SyntheticTopology::new(SyntheticTopologyConf::new(
+            1000,
+            TopologyModel::RegionalClusters {
+                regions: 5,
+                intra_degree: 4,
+                inter_links: 10,
+            },
+        ));
+        let cross = regional.links.iter().filter(|link| link.a % 5 != link.b % 5).count();
+        assert_eq!(regional.link_count() - cross, 2000);
+        assert!(cross > 0 && cross <= 50);
+    }
+
+    #[test]
+    fn round_should_report_both_ends_and_batch_connections() {
+        let conf = SyntheticTopologyConf {
+            node_churn: 0.0,
+            link_flap: 0.0,
+            ..SyntheticTopologyConf::new(100, TopologyModel::Random { avg_degree: 30 })
+        };
+        let mut topology = SyntheticTopology::new(conf);
+        let msgs = topology.next_round();
+
+        let pings = msgs.iter().filter(|msg| matches!(msg, VisualizationAgentMsg::NodePing(..))).count();
+        let labels = msgs.iter().filter(|msg| matches!(msg, VisualizationAgentMsg::NodeLabels(..))).count();
+        assert_eq!((pings, labels), (100, 100));
+        let mut reported = 0;
+        for msg in msgs.iter() {
+            if let VisualizationAgentMsg::NodeConnections(_, conns) = msg {
+                assert!(conns.len() <= MAX_CONN_STATS_SEND);
+                assert!(conns.iter().all(|conn| conn.status == ConnectionStatus::CONNECTED));
+                reported += conns.len();
+            }
+        }
+        assert_eq!(reported, 2 * topology.link_count());
+        assert!(!topology.next_round().iter().any(|msg| matches!(msg, VisualizationAgentMsg::NodeLabels(..))));
+    }
+
+    #[test]
+    fn churn_should_silence_nodes_and_mark_their_links_down() {
+        let conf = SyntheticTopologyConf {
+            node_churn: 0.1,
+            link_flap: 0.0,
+            ..SyntheticTopologyConf::new(500, TopologyModel::Random { avg_degree: 4 })
+        };
+        let mut topology = SyntheticTopology::new(conf);
+        let msgs = topology.next_round();
+        let offline = topology.offline_nodes();
+        assert!(offline > 0);
+
+        let pings = msgs.iter().filter(|msg| matches!(msg, VisualizationAgentMsg::NodePing(..))).count();
+        assert_eq!(pings, 500 - offline);
+        let down = msgs
+            .iter()
+            .filter_map(|msg| match msg {
+                VisualizationAgentMsg::NodeConnections(_, conns) => Some(conns.iter().filter(|conn| conn.status == ConnectionStatus::DISCONNECTED).count()),
+                _ => None,
+            })
+            .sum::<usize>();
+        assert!(down > 0);
+    }
+}
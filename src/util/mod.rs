@@ -1,6 +1,7 @@
 use std::{
     collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 pub fn calc_hash<T: Hash>(t: &T) -> u64 {
@@ -9,6 +10,10 @@ pub fn calc_hash<T: Hash>(t: &T) -> u64 {
     s.finish()
 }
 
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis() as u64
+}
+
 #[cfg(test)]
 mod test {
     use crate::util::calc_hash;
@@ -0,0 +1,338 @@
+//! Multi-node scenarios on the in-process `SimNetwork`. They need the `testing` feature, which a plain
+//! `cargo test` does not enable: run them with `cargo test --features testing`.
+
+use std::collections::BTreeMap;
+
+use atm0s_sdn_identity::{NodeAddrBuilder, NodeId};
+use atm0s_sdn_visualization::{
+    testing::{link_stats, SimNetwork},
+    AgentAckConf, AggregatorConf, ConnectionStatus, MasterReplicationConf, MasterRole, NodeData, RegionScope, TopologyEvent, TopologySubscription, VisualizationAgentBehaviourConf,
+    VisualizationMasterBehaviourConf,
+};
+
+const TICK_MS: u64 = 1000;
+
+fn agent_conf(node_id: NodeId) -> VisualizationAgentBehaviourConf {
+    VisualizationAgentBehaviourConf {
+        master_timeout_ms: 3 * TICK_MS,
+        ..VisualizationAgentBehaviourConf::new(node_id, NodeAddrBuilder::new(node_id).addr())
+    }
+}
+
+fn master_conf(masters: &[NodeId]) -> VisualizationMasterBehaviourConf {
+    VisualizationMasterBehaviourConf {
+        replication: Some(MasterReplicationConf {
+            peers: masters.to_vec(),
+            sync_interval_ms: TICK_MS,
+            peer_timeout_ms: 3 * TICK_MS,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Masters 1 and 2 and agents up to `size`, each node linked to the next 1, 2 and 5 nodes of the ring
+fn mesh(size: NodeId) -> (SimNetwork, Vec<(NodeId, NodeId)>) {
+    let mut net = SimNetwork::new(TICK_MS);
+    for node_id in 1..=size {
+        if node_id <= 2 {
+            net.add_master(agent_conf(node_id), master_conf(&[1, 2]));
+        } else {
+            net.add_agent(agent_conf(node_id));
+        }
+    }
+    let mut links = vec![];
+    for node_id in 1..=size {
+        for step in [1, 2, 5] {
+            let peer = (node_id - 1 + step) % size + 1;
+            net.connect(node_id, peer);
+            links.push((node_id, peer));
+        }
+    }
+    (net, links)
+}
+
+/// Same value seen from both ends
+fn latency(a: NodeId, b: NodeId) -> u16 {
+    ((a * b + a + b) % 50 + 1) as u16
+}
+
+fn inject_link_stats(net: &mut SimNetwork, links: &[(NodeId, NodeId)]) {
+    for (a, b) in links {
+        net.inject_stats(*a, *b, link_stats(latency(*a, *b), 0, 1000));
+        net.inject_stats(*b, *a, link_stats(latency(*a, *b), 0, 1000));
+    }
+}
+
+fn node_ids(net: &SimNetwork, master: NodeId) -> Vec<NodeId> {
+    let mut ids: Vec<NodeId> = net.controller(master).get_nodes().iter().map(|node| node.id).collect();
+    ids.sort();
+    ids
+}
+
+fn conn_status(node: &NodeData, dest: NodeId) -> Vec<ConnectionStatus> {
+    node.conns.iter().filter(|conn| conn.node_id == dest).map(|conn| conn.status.clone()).collect()
+}
+
+#[test]
+fn mesh_of_50_nodes_should_converge_on_every_master() {
+    let (mut net, links) = mesh(50);
+    inject_link_stats(&mut net, &links);
+    net.run(5);
+
+    for master in [1, 2] {
+        let nodes = net.controller(master).get_nodes();
+        assert_eq!(nodes.len(), 50, "master {} should know every node", master);
+        for node in nodes.iter() {
+            assert_eq!(node.conns.len(), net.neighbours(node.id).len(), "master {} should have all links of node {}", master, node.id);
+            for conn in node.conns.iter() {
+                assert_eq!(conn.status, ConnectionStatus::CONNECTED);
+                assert_eq!(conn.metric.latency, latency(node.id, conn.node_id));
+            }
+        }
+        assert_eq!(net.master_sdk(master).status().map(|status| status.masters), Some(vec![1, 2]));
+    }
+    assert_eq!(net.master_sdk(1).role(), Some(MasterRole::Leader));
+    assert_eq!(net.master_sdk(2).role(), Some(MasterRole::Standby));
+    for node_id in 3..=50 {
+        let health = net.agent_sdk(node_id).master_health();
+        assert!(health.healthy, "node {} should hear from a master", node_id);
+        assert_eq!(health.leader, Some(1));
+    }
+}
+
+#[test]
+fn flapping_link_should_be_reported_down_and_up() {
+    let (mut net, links) = mesh(10);
+    inject_link_stats(&mut net, &links);
+    net.run(2);
+    let mut feed = net.controller(1).subscribe();
+
+    for _ in 0..3 {
+        assert_eq!(net.disconnect(5, 6), 1);
+        // agents send the reports queued on the previous tick
+        net.run(2);
+        let node = net.controller(1).get_node(5).expect("should have node 5");
+        assert_eq!(conn_status(&node, 6), vec![ConnectionStatus::DISCONNECTED]);
+
+        net.connect(5, 6);
+        net.inject_stats(5, 6, link_stats(80, 5, 500));
+        net.run(2);
+        let node = net.controller(1).get_node(5).expect("should have node 5");
+        assert_eq!(conn_status(&node, 6), vec![ConnectionStatus::CONNECTED]);
+    }
+
+    let events: Vec<TopologyEvent> = std::iter::from_fn(|| feed.try_recv()).collect();
+    let downs = events.iter().filter(|event| matches!(event, TopologyEvent::LinkDown { node_id: 5, dest: 6, .. })).count();
+    let ups = events.iter().filter(|event| matches!(event, TopologyEvent::LinkUp { node_id: 5, dest: 6, .. })).count();
+    assert_eq!((downs, ups), (3, 3));
+    let node = net.controller(1).get_node(5).expect("should have node 5");
+    let conn = node.conns.iter().find(|conn| conn.node_id == 6).expect("should have link to 6");
+    assert_eq!((conn.metric.latency, conn.metric.loss_percent), (80, 5));
+}
+
+#[test]
+fn agents_should_fail_over_when_master_dies() {
+    let (mut net, links) = mesh(20);
+    inject_link_stats(&mut net, &links);
+    net.run(3);
+    assert_eq!(net.agent_sdk(3).master_health().master, Some(1));
+
+    net.kill(1);
+    net.run(8);
+
+    assert!(!net.is_alive(1));
+    assert_eq!(net.master_sdk(2).role(), Some(MasterRole::Leader));
+    assert_eq!(net.master_sdk(2).status().map(|status| status.masters), Some(vec![2]));
+    for node_id in 3..=20 {
+        let health = net.agent_sdk(node_id).master_health();
+        assert!(health.healthy, "node {} should hear from master 2", node_id);
+        assert_eq!(health.master, Some(2));
+        assert_eq!(health.leader, Some(2));
+    }
+    // agents send the ping queued on the previous tick
+    let now_ms = net.now_ms();
+    for node in net.controller(2).get_nodes().iter().filter(|node| node.id != 1) {
+        assert_eq!(node.last_ping_ts, now_ms - TICK_MS, "node {} should still report", node.id);
+        assert!(conn_status(node, 1).iter().all(|status| *status == ConnectionStatus::DISCONNECTED));
+    }
+}
+
+/// Masters 1 and 2 linked together, agents 10 and 11 next to master 1, 20 and 21 next to master 2, and each agent
+/// of the first pair linked to one of the second
+fn two_sites() -> SimNetwork {
+    let mut net = SimNetwork::new(TICK_MS);
+    net.add_master(agent_conf(1), master_conf(&[1, 2]));
+    net.add_master(agent_conf(2), master_conf(&[1, 2]));
+    for node_id in [10, 11, 20, 21] {
+        net.add_agent(agent_conf(node_id));
+    }
+    net.connect(1, 2);
+    for (agent, master, peer) in [(10, 1, 20), (11, 1, 21)] {
+        net.connect(agent, master);
+        net.connect(agent, peer);
+    }
+    net.connect(20, 2);
+    net.connect(21, 2);
+    net
+}
+
+#[test]
+fn lowest_live_master_should_lead_and_agents_fail_over_when_it_dies() {
+    let mut net = two_sites();
+    net.run(5);
+
+    for master in [1, 2] {
+        assert_eq!(node_ids(&net, master), vec![1, 2, 10, 11, 20, 21]);
+        assert_eq!(net.master_sdk(master).status().map(|status| status.leader), Some(1));
+    }
+    for (agent, nearest) in [(10, 1), (20, 2)] {
+        let health = net.agent_sdk(agent).master_health();
+        assert!(health.healthy);
+        assert_eq!(health.master, Some(nearest));
+        assert_eq!(health.leader, Some(1));
+        assert_eq!(health.masters, vec![1, 2]);
+        assert_eq!(health.pinned, None);
+    }
+
+    net.kill(1);
+    net.run(8);
+
+    for agent in [10, 11] {
+        let health = net.agent_sdk(agent).master_health();
+        assert!(health.healthy);
+        assert_eq!(health.master, Some(2));
+        assert_eq!(health.leader, Some(2));
+        assert_eq!(health.masters, vec![2]);
+    }
+    assert_eq!(net.master_sdk(2).status().map(|status| status.masters), Some(vec![2]));
+    assert_eq!(net.master_sdk(2).role(), Some(MasterRole::Leader));
+    // agents send the ping queued on the previous tick
+    let now_ms = net.now_ms();
+    for node in net.controller(2).get_nodes().iter().filter(|node| node.id != 1) {
+        assert_eq!(node.last_ping_ts, now_ms - TICK_MS, "node {} should still report", node.id);
+    }
+}
+
+#[test]
+fn only_leader_should_fire_alerts() {
+    let mut net = two_sites();
+    net.run(3);
+    let mut leader_feed = net.controller(1).subscribe();
+    let mut standby_feed = net.controller(2).subscribe();
+
+    // node 20 only reports to the standby, which replicates the lossy link to the leader
+    net.inject_stats(20, 10, link_stats(10, 50, 100));
+    // reported on the next tick, replicated on the one after
+    net.run(3);
+
+    let lossy = |master: NodeId| {
+        net.controller(master)
+            .get_node(20)
+            .and_then(|node| node.conns.iter().find(|conn| conn.node_id == 10).map(|conn| conn.metric.loss_percent))
+    };
+    assert_eq!(lossy(2), Some(50));
+    assert_eq!(lossy(1), Some(50));
+    let alerts = |feed: &mut TopologySubscription| std::iter::from_fn(|| feed.try_recv()).filter(|event| matches!(event, TopologyEvent::AlertFired(_))).count();
+    assert_eq!(alerts(&mut leader_feed), 1);
+    assert_eq!(alerts(&mut standby_feed), 0);
+}
+
+#[test]
+fn agents_should_unpin_a_dead_master_and_find_a_new_one() {
+    let mut net = SimNetwork::new(TICK_MS);
+    net.add_master(agent_conf(1), master_conf(&[1, 2]));
+    net.add_master(agent_conf(2), master_conf(&[1, 2]));
+    net.add_agent(agent_conf(10));
+    net.connect(1, 2);
+    net.connect(10, 1);
+    net.run(3);
+    assert_eq!(net.agent_sdk(10).master_health().masters, vec![1, 2]);
+
+    // the agent only reached the masters through 1, it fails over to 2 which is gone too
+    net.kill(1);
+    net.kill(2);
+    net.run(4);
+    assert_eq!(net.agent_sdk(10).master_health().pinned, Some(2));
+
+    // a master unknown to the agent starts, the master service route reaches it once the agent unpins
+    net.add_master(agent_conf(3), VisualizationMasterBehaviourConf::default());
+    net.connect(10, 3);
+    net.run(5);
+
+    let health = net.agent_sdk(10).master_health();
+    assert!(health.healthy);
+    assert_eq!(health.pinned, None);
+    assert_eq!(health.master, Some(3));
+    assert!(net.controller(3).get_node(10).is_some());
+}
+
+#[test]
+fn reports_forwarded_by_an_aggregator_should_be_acked_by_the_upstream_master() {
+    let mut net = SimNetwork::new(TICK_MS);
+    net.add_master(agent_conf(1), VisualizationMasterBehaviourConf::default());
+    let aggregator = VisualizationMasterBehaviourConf {
+        aggregator: Some(AggregatorConf::new("eu", RegionScope::NodeRange(20, 29), 1)),
+        ..Default::default()
+    };
+    net.add_master(agent_conf(2), aggregator);
+    net.add_agent(VisualizationAgentBehaviourConf {
+        ack: Some(AgentAckConf {
+            retry_base_ms: 3 * TICK_MS,
+            ..Default::default()
+        }),
+        ..agent_conf(10)
+    });
+    net.connect(1, 2);
+    net.connect(10, 2);
+    net.run(2);
+    let stats = net.agent_sdk(10).delivery_stats().expect("should have stats");
+    assert_eq!((stats.retried, stats.lost), (0, 0));
+    assert!(net.controller(1).get_node(10).is_some());
+    assert!(net.controller(2).get_node(10).is_none());
+
+    // forwarded reports lost while the upstream is unreachable are retransmitted, not acked by the aggregator
+    net.disconnect(1, 2);
+    net.inject_stats(10, 2, link_stats(30, 0, 100));
+    net.run(2);
+    net.connect(1, 2);
+    net.run(5);
+    let stats = net.agent_sdk(10).delivery_stats().expect("should have stats");
+    assert!(stats.retried > 0);
+    assert_eq!(stats.lost, 0);
+    let node = net.controller(1).get_node(10).expect("should have node");
+    assert_eq!(node.conns.iter().map(|conn| conn.metric.latency).collect::<Vec<_>>(), vec![30]);
+}
+
+#[test]
+fn labels_set_through_the_agent_sdk_should_reach_the_master() {
+    let (mut net, links) = mesh(10);
+    inject_link_stats(&mut net, &links);
+    net.run(2);
+    assert_eq!(net.controller(1).get_node(5).map(|node| node.labels), Some(BTreeMap::new()));
+
+    let labels = BTreeMap::from([(String::from("region"), String::from("eu"))]);
+    net.agent_sdk(5).set_labels(labels.clone());
+    net.run(2);
+    assert_eq!(net.controller(1).get_node(5).map(|node| node.labels), Some(labels));
+}
+
+#[test]
+fn same_scenario_should_replay_identically() {
+    let scenario = || {
+        let (mut net, links) = mesh(20);
+        inject_link_stats(&mut net, &links);
+        net.run(2);
+        net.disconnect(3, 4);
+        net.kill(1);
+        net.run(5);
+        net.connect(3, 4);
+        net.run(2);
+        let traffic: Vec<u64> = (1..=20).flat_map(|node_id| net.senders(node_id)).map(|sender| sender.sent_bytes()).collect();
+        let mut nodes = net.controller(2).get_nodes();
+        nodes.sort_by_key(|node| node.id);
+        let history = [1, 2].map(|master| net.controller(master).event_history(0, u64::MAX));
+        (nodes, history, traffic, net.dropped_msgs())
+    };
+    assert_eq!(scenario(), scenario());
+}